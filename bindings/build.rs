@@ -19,9 +19,11 @@ fn main() {
             SetWindowLongPtrW, GetWindowLongPtrW, PeekMessageW,
             CW_USEDEFAULT, HWND, LPARAM, MSG, WINDOW_EX_STYLE, WINDOW_STYLE, WM_ACTIVATEAPP, WM_CLOSE, WM_PAINT,
             WM_SIZE, WNDCLASSEXW, WNDCLASS_STYLES, WNDPROC, WPARAM, WS_OVERLAPPEDWINDOW, WS_VISIBLE, GWLP_USERDATA,
-            WM_CREATE, CREATESTRUCTW, WM_DESTROY, PM_REMOVE, CS_VREDRAW, CS_HREDRAW, WM_KEYDOWN, WM_KEYUP
+            WM_CREATE, CREATESTRUCTW, WM_DESTROY, PM_REMOVE, CS_VREDRAW, CS_HREDRAW, WM_KEYDOWN, WM_KEYUP,
+            WM_SYSKEYDOWN, WM_SYSKEYUP, WM_MOUSEMOVE, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MBUTTONDOWN, WM_MBUTTONUP,
+            WM_RBUTTONDOWN, WM_RBUTTONUP, WM_XBUTTONDOWN, WM_XBUTTONUP, WM_MOUSEWHEEL, WM_DEVICECHANGE
         },
         Windows::Win32::UI::DisplayDevices::{RECT, DEVMODEW},
-        Windows::Win32::UI::KeyboardAndMouseInput::GetKeyState,
+        Windows::Win32::UI::KeyboardAndMouseInput::{GetKeyState, SetCapture, ReleaseCapture},
     );
 }
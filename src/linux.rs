@@ -0,0 +1,832 @@
+use log::debug;
+use log::info;
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::rmh;
+use crate::rmh::audio::SoundParams;
+use crate::rmh::controller_db::ControllerDb;
+use crate::rmh::evdev::{
+    AbsInfo, DeviceInfo, DeviceState, EvdevPad, EventSource, InputEvent, RecordedDevice, ABS_MISC, EV_ABS, EV_KEY,
+    KEY_MAX,
+};
+use crate::rmh::input::{Deadzones, Input, MAX_CONTROLLERS};
+use crate::rmh::ring::{sample_ring, RingConsumer};
+
+// ioctl request numbers from linux/input.h
+const IOC_READ: u64 = 2;
+
+const fn ioc(dir: u64, nr: u64, size: u64) -> u64 {
+    (dir << 30) | (size << 16) | ((b'E' as u64) << 8) | nr
+}
+
+const fn eviocgid() -> u64 {
+    ioc(IOC_READ, 0x02, std::mem::size_of::<libc::input_id>() as u64)
+}
+
+const fn eviocgname(len: u64) -> u64 {
+    ioc(IOC_READ, 0x06, len)
+}
+
+const fn eviocgkey(len: u64) -> u64 {
+    ioc(IOC_READ, 0x18, len)
+}
+
+const fn eviocgbit(ev: u64, len: u64) -> u64 {
+    ioc(IOC_READ, 0x20 + ev, len)
+}
+
+const fn eviocgabs(abs: u64) -> u64 {
+    ioc(IOC_READ, 0x40 + abs, std::mem::size_of::<libc::input_absinfo>() as u64)
+}
+
+fn test_bit(bits: &[u8], bit: usize) -> bool {
+    match bits.get(bit / 8) {
+        Some(b) => b & (1 << (bit % 8)) != 0,
+        None => false,
+    }
+}
+
+// A /dev/input/event* node, read without blocking.
+pub struct LinuxDevice {
+    fd: libc::c_int,
+    path: PathBuf,
+    info: DeviceInfo,
+}
+
+impl LinuxDevice {
+    pub fn open(path: &Path) -> Result<LinuxDevice, String> {
+        let c_path = std::ffi::CString::new(path.as_os_str().to_string_lossy().as_bytes())
+            .map_err(|e| e.to_string())?;
+        let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_RDONLY | libc::O_NONBLOCK | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(format!("{}: {}", path.display(), std::io::Error::last_os_error()));
+        }
+        // from here on Drop closes fd
+        let mut device = LinuxDevice {
+            fd,
+            path: path.to_path_buf(),
+            info: DeviceInfo::default(),
+        };
+        device.info = linux_device_info(fd)?;
+        Ok(device)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for LinuxDevice {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+impl EventSource for LinuxDevice {
+    fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    fn next_event(&mut self) -> Result<Option<InputEvent>, String> {
+        let mut event: libc::input_event = unsafe { std::mem::zeroed() };
+        let size = std::mem::size_of::<libc::input_event>();
+        let read = unsafe { libc::read(self.fd, &mut event as *mut _ as *mut libc::c_void, size) };
+        if read < 0 {
+            let error = std::io::Error::last_os_error();
+            if error.kind() == std::io::ErrorKind::WouldBlock {
+                return Ok(None);
+            }
+            // ENODEV once the device is unplugged
+            return Err(format!("{}: {}", self.path.display(), error));
+        }
+        if read as usize != size {
+            return Err(format!("{}: short read", self.path.display()));
+        }
+        Ok(Some(InputEvent {
+            type_: event.type_,
+            code: event.code,
+            value: event.value,
+        }))
+    }
+
+    fn state(&mut self) -> Result<DeviceState, String> {
+        let ioctl_error = |what: &str| format!("{}: {}: {}", self.path.display(), what, std::io::Error::last_os_error());
+
+        let mut key_bits = [0u8; KEY_MAX as usize / 8 + 1];
+        if unsafe { libc::ioctl(self.fd, eviocgkey(key_bits.len() as u64) as _, key_bits.as_mut_ptr()) } < 0 {
+            return Err(ioctl_error("EVIOCGKEY"));
+        }
+        let keys_down = self.info.keys.iter().copied().filter(|&k| test_bit(&key_bits, k as usize)).collect();
+
+        let mut abs = Vec::new();
+        for &(code, _) in self.info.abs.iter() {
+            let absinfo = linux_abs_info(self.fd, code).map_err(|_| ioctl_error("EVIOCGABS"))?;
+            abs.push((code, absinfo.value));
+        }
+        Ok(DeviceState { keys_down, abs })
+    }
+}
+
+fn linux_abs_info(fd: libc::c_int, code: u16) -> Result<libc::input_absinfo, std::io::Error> {
+    let mut absinfo: libc::input_absinfo = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(fd, eviocgabs(code as u64) as _, &mut absinfo) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(absinfo)
+}
+
+fn linux_device_info(fd: libc::c_int) -> Result<DeviceInfo, String> {
+    let ioctl_error = |what: &str| format!("{}: {}", what, std::io::Error::last_os_error());
+
+    let mut id: libc::input_id = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(fd, eviocgid() as _, &mut id) } < 0 {
+        return Err(ioctl_error("EVIOCGID"));
+    }
+
+    let mut name = [0u8; 256];
+    let name_len = unsafe { libc::ioctl(fd, eviocgname(name.len() as u64) as _, name.as_mut_ptr()) };
+    let name = if name_len > 0 {
+        let end = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        String::from_utf8_lossy(&name[..end]).into_owned()
+    } else {
+        String::new()
+    };
+
+    let mut key_bits = [0u8; KEY_MAX as usize / 8 + 1];
+    if unsafe { libc::ioctl(fd, eviocgbit(EV_KEY as u64, key_bits.len() as u64) as _, key_bits.as_mut_ptr()) } < 0 {
+        return Err(ioctl_error("EVIOCGBIT(EV_KEY)"));
+    }
+    let mut abs_bits = [0u8; ABS_MISC as usize / 8 + 1];
+    if unsafe { libc::ioctl(fd, eviocgbit(EV_ABS as u64, abs_bits.len() as u64) as _, abs_bits.as_mut_ptr()) } < 0 {
+        return Err(ioctl_error("EVIOCGBIT(EV_ABS)"));
+    }
+
+    let keys = (0..=KEY_MAX).filter(|&k| test_bit(&key_bits, k as usize)).collect();
+    let mut abs = Vec::new();
+    for code in (0..ABS_MISC).filter(|&a| test_bit(&abs_bits, a as usize)) {
+        let absinfo = linux_abs_info(fd, code).map_err(|_| ioctl_error("EVIOCGABS"))?;
+        abs.push((code, AbsInfo {
+            value: absinfo.value,
+            minimum: absinfo.minimum,
+            maximum: absinfo.maximum,
+            flat: absinfo.flat,
+        }));
+    }
+
+    Ok(DeviceInfo {
+        name,
+        bustype: id.bustype,
+        vendor: id.vendor,
+        product: id.product,
+        version: id.version,
+        keys,
+        abs,
+    })
+}
+
+// Every event node that looks like a joystick. Nodes we can't open (usually
+// for lack of permission) are skipped.
+pub fn linux_find_gamepads(skip: &[PathBuf]) -> Vec<LinuxDevice> {
+    let entries = match std::fs::read_dir("/dev/input") {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!("can't list /dev/input: {}", e);
+            return Vec::new();
+        }
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| matches!(p.file_name(), Some(n) if n.to_string_lossy().starts_with("event")))
+        .filter(|p| !skip.contains(p))
+        .collect();
+    paths.sort();
+
+    paths
+        .iter()
+        .filter_map(|path| match LinuxDevice::open(path) {
+            Ok(device) if device.info().is_joystick() => Some(device),
+            Ok(_) => None,
+            Err(e) => {
+                debug!("{}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+// Free slots are filled from a rescan of /dev/input this many frames apart.
+const RESCAN_FRAMES: u32 = 120;
+
+struct PadSlot {
+    source: Box<dyn EventSource>,
+    // None for sources that aren't device nodes, e.g. recordings
+    path: Option<PathBuf>,
+    pad: EvdevPad,
+}
+
+// Controller slots fed from evdev devices, or from any other EventSource
+// handed to attach().
+pub struct LinuxPads {
+    slots: Vec<Option<PadSlot>>,
+    db: ControllerDb,
+    frames_to_rescan: u32,
+}
+
+impl LinuxPads {
+    pub fn new(db: ControllerDb) -> LinuxPads {
+        LinuxPads {
+            slots: (0..MAX_CONTROLLERS).map(|_| None).collect(),
+            db,
+            frames_to_rescan: 0,
+        }
+    }
+
+    // Puts a source in the first free slot, returning the slot.
+    pub fn attach(&mut self, input: &mut Input, source: Box<dyn EventSource>, path: Option<PathBuf>) -> Option<usize> {
+        let slot = self.slots.iter().position(|s| s.is_none())?;
+        let info = source.info();
+        let mapping = self.db.get(&info.guid()).cloned();
+        let mapped = mapping.is_some();
+        let pad = EvdevPad::new(info, mapping);
+        info!(
+            "controller {}: {} ({})",
+            slot,
+            info.name,
+            if mapped { pad.mapping().name.as_str() } else { "default layout" }
+        );
+        self.slots[slot] = Some(PadSlot { source, path, pad });
+        input.set_connected(slot, true);
+        Some(slot)
+    }
+
+    pub fn update(&mut self, input: &mut Input, deadzones: &Deadzones) {
+        if self.frames_to_rescan == 0 {
+            self.frames_to_rescan = RESCAN_FRAMES;
+            if self.slots.iter().any(|s| s.is_none()) {
+                let open: Vec<PathBuf> = self.slots.iter().flatten().filter_map(|s| s.path.clone()).collect();
+                for device in linux_find_gamepads(&open) {
+                    let path = device.path().to_path_buf();
+                    if self.attach(input, Box::new(device), Some(path)).is_none() {
+                        break;
+                    }
+                }
+            }
+        }
+        self.frames_to_rescan -= 1;
+
+        for (slot, entry) in self.slots.iter_mut().enumerate() {
+            if let Some(pad_slot) = entry {
+                let pad = input.controller_mut(slot);
+                if let Err(e) = pad_slot.pad.poll(pad_slot.source.as_mut(), pad, deadzones) {
+                    info!("controller {} lost: {}", slot, e);
+                    *entry = None;
+                    input.set_connected(slot, false);
+                }
+            }
+        }
+    }
+}
+
+// ALSA is loaded at runtime, like XInput and DirectSound on Windows, so the
+// game still starts on machines without libasound.
+type SndPcmOpenFn = unsafe extern "C" fn(*mut *mut libc::c_void, *const libc::c_char, libc::c_int, libc::c_int) -> libc::c_int;
+type SndPcmSetParamsFn = unsafe extern "C" fn(
+    *mut libc::c_void,
+    libc::c_int,
+    libc::c_int,
+    libc::c_uint,
+    libc::c_uint,
+    libc::c_int,
+    libc::c_uint,
+) -> libc::c_int;
+type SndPcmWriteiFn = unsafe extern "C" fn(*mut libc::c_void, *const libc::c_void, libc::c_ulong) -> libc::c_long;
+type SndPcmRecoverFn = unsafe extern "C" fn(*mut libc::c_void, libc::c_int, libc::c_int) -> libc::c_int;
+type SndPcmDelayFn = unsafe extern "C" fn(*mut libc::c_void, *mut libc::c_long) -> libc::c_int;
+type SndPcmCloseFn = unsafe extern "C" fn(*mut libc::c_void) -> libc::c_int;
+type SndStrerrorFn = unsafe extern "C" fn(libc::c_int) -> *const libc::c_char;
+
+const SND_PCM_STREAM_PLAYBACK: libc::c_int = 0;
+const SND_PCM_NONBLOCK: libc::c_int = 1;
+const SND_PCM_FORMAT_S16_LE: libc::c_int = 2;
+const SND_PCM_ACCESS_RW_INTERLEAVED: libc::c_int = 3;
+
+// Failed writes in a row, each followed by snd_pcm_recover, before write()
+// gives up on the device.
+const MAX_RECOVERIES: u32 = 3;
+
+struct Alsa {
+    pcm_open: SndPcmOpenFn,
+    pcm_set_params: SndPcmSetParamsFn,
+    pcm_writei: SndPcmWriteiFn,
+    pcm_recover: SndPcmRecoverFn,
+    pcm_delay: SndPcmDelayFn,
+    pcm_close: SndPcmCloseFn,
+    strerror: SndStrerrorFn,
+}
+
+fn linux_load_alsa() -> Result<Alsa, String> {
+    unsafe {
+        let lib = libc::dlopen(b"libasound.so.2\0".as_ptr() as *const libc::c_char, libc::RTLD_NOW | libc::RTLD_LOCAL);
+        if lib.is_null() {
+            return Err("libasound.so.2 not found".to_string());
+        }
+        debug!("loaded alsa");
+
+        let symbol = |name: &[u8]| {
+            let addr = libc::dlsym(lib, name.as_ptr() as *const libc::c_char);
+            if addr.is_null() {
+                Err(format!("libasound has no {}", String::from_utf8_lossy(&name[..name.len() - 1])))
+            } else {
+                Ok(addr)
+            }
+        };
+        Ok(Alsa {
+            pcm_open: std::mem::transmute_copy(&symbol(b"snd_pcm_open\0")?),
+            pcm_set_params: std::mem::transmute_copy(&symbol(b"snd_pcm_set_params\0")?),
+            pcm_writei: std::mem::transmute_copy(&symbol(b"snd_pcm_writei\0")?),
+            pcm_recover: std::mem::transmute_copy(&symbol(b"snd_pcm_recover\0")?),
+            pcm_delay: std::mem::transmute_copy(&symbol(b"snd_pcm_delay\0")?),
+            pcm_close: std::mem::transmute_copy(&symbol(b"snd_pcm_close\0")?),
+            strerror: std::mem::transmute_copy(&symbol(b"snd_strerror\0")?),
+        })
+    }
+}
+
+impl Alsa {
+    fn error(&self, what: &str, err: libc::c_int) -> String {
+        let msg = unsafe { std::ffi::CStr::from_ptr((self.strerror)(err)) };
+        format!("{}: {}", what, msg.to_string_lossy())
+    }
+}
+
+// Counterpart of the DirectSound buffer: a non-blocking PCM that the main
+// loop tops up every frame so that about `latency_ms` of sound is queued.
+// Any ALSA device name works, including "null", which swallows everything,
+// and the file plugin (e.g. "file:FILE=/tmp/rmh.raw,FORMAT=raw"), so it runs
+// without a sound card.
+pub struct AlsaOutput {
+    alsa: Alsa,
+    pcm: *mut libc::c_void,
+    n_channels: usize,
+    latency_frames: usize,
+    // times the device ran dry and had to be restarted
+    pub underruns: u32,
+}
+
+impl AlsaOutput {
+    pub fn open(device: &str, params: &SoundParams, latency_ms: u32) -> Result<AlsaOutput, String> {
+        if params.bits_per_sample != 16 {
+            return Err(format!("unsupported sample size {}", params.bits_per_sample));
+        }
+        let alsa = linux_load_alsa()?;
+        let name = std::ffi::CString::new(device).map_err(|e| e.to_string())?;
+
+        let mut pcm = std::ptr::null_mut();
+        let err = unsafe { (alsa.pcm_open)(&mut pcm, name.as_ptr(), SND_PCM_STREAM_PLAYBACK, SND_PCM_NONBLOCK) };
+        if err < 0 {
+            return Err(alsa.error(&format!("snd_pcm_open({})", device), err));
+        }
+        // from here on Drop closes pcm
+        let output = AlsaOutput {
+            alsa,
+            pcm,
+            n_channels: params.n_channels as usize,
+            latency_frames: (params.n_samples_per_sec as u32 * latency_ms / 1000) as usize,
+            underruns: 0,
+        };
+
+        // the device buffer holds twice the target so a late frame doesn't
+        // underrun right away
+        let err = unsafe {
+            (output.alsa.pcm_set_params)(
+                pcm,
+                SND_PCM_FORMAT_S16_LE,
+                SND_PCM_ACCESS_RW_INTERLEAVED,
+                params.n_channels as libc::c_uint,
+                params.n_samples_per_sec as libc::c_uint,
+                1,
+                latency_ms * 2 * 1000,
+            )
+        };
+        if err < 0 {
+            return Err(output.alsa.error("snd_pcm_set_params", err));
+        }
+        info!("alsa: {} at {}Hz, {}ms latency", device, params.n_samples_per_sec, latency_ms);
+        Ok(output)
+    }
+
+    // How many frames (samples per channel) to write now to get back to the
+    // latency target.
+    pub fn frames_to_write(&mut self) -> Result<usize, String> {
+        let mut delay: libc::c_long = 0;
+        let err = unsafe { (self.alsa.pcm_delay)(self.pcm, &mut delay) };
+        if err < 0 {
+            self.recover(err)?;
+            return Ok(self.latency_frames);
+        }
+        Ok(self.latency_frames.saturating_sub(delay.max(0) as usize))
+    }
+
+    // Queues interleaved samples, restarting the device after an underrun.
+    // Returns how many frames were taken; fewer than given when the device
+    // buffer is full.
+    pub fn write(&mut self, samples: &[i16]) -> Result<usize, String> {
+        let total = samples.len() / self.n_channels;
+        let mut written = 0;
+        let mut recoveries = 0;
+        while written < total {
+            let rest = &samples[written * self.n_channels..];
+            let n = unsafe {
+                (self.alsa.pcm_writei)(self.pcm, rest.as_ptr() as *const libc::c_void, (total - written) as libc::c_ulong)
+            };
+            if n == -(libc::EAGAIN as libc::c_long) {
+                break;
+            }
+            if n < 0 {
+                recoveries += 1;
+                if recoveries > MAX_RECOVERIES {
+                    return Err(self.alsa.error("snd_pcm_writei", n as libc::c_int));
+                }
+                self.recover(n as libc::c_int)?;
+                continue;
+            }
+            recoveries = 0;
+            written += n as usize;
+        }
+        Ok(written)
+    }
+
+    fn recover(&mut self, err: libc::c_int) -> Result<(), String> {
+        if err == -libc::EPIPE {
+            self.underruns += 1;
+            debug!("alsa underrun #{}", self.underruns);
+        }
+        let result = unsafe { (self.alsa.pcm_recover)(self.pcm, err, 1) };
+        if result < 0 {
+            return Err(self.alsa.error("snd_pcm_recover", result));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AlsaOutput {
+    fn drop(&mut self) {
+        unsafe { (self.alsa.pcm_close)(self.pcm) };
+    }
+}
+
+// Pull model: instead of the main loop pushing a frame's worth of sound, a
+// thread keeps the device topped up from a ring the game fills ahead of
+// time, so a slow frame eats into the queued sound rather than the device
+// buffer. When the ring runs dry the thread plays silence and the ring's
+// stats count the underrun.
+pub struct LinuxAudioThread {
+    stop: Arc<AtomicBool>,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl LinuxAudioThread {
+    pub fn spawn(
+        device: &str,
+        params: SoundParams,
+        latency_ms: u32,
+        mut consumer: RingConsumer,
+    ) -> Result<LinuxAudioThread, String> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let device = device.to_string();
+        // the device is opened on the audio thread; report how that went
+        let (opened_tx, opened_rx) = std::sync::mpsc::channel();
+
+        let handle = std::thread::Builder::new()
+            .name("audio".to_string())
+            .spawn(move || {
+                let mut output = match AlsaOutput::open(&device, &params, latency_ms) {
+                    Ok(output) => {
+                        let _ = opened_tx.send(Ok(()));
+                        output
+                    }
+                    Err(e) => {
+                        let _ = opened_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let n_channels = params.n_channels as usize;
+                let sleep = std::time::Duration::from_millis((latency_ms / 4).max(1) as u64);
+                // pulled from the ring; the device may take only part of it,
+                // and the rest goes out first on the next pass
+                let mut buf = Vec::new();
+                let mut buf_written = 0;
+
+                while !thread_stop.load(Ordering::Relaxed) {
+                    let frames = match output.frames_to_write() {
+                        Ok(frames) => frames,
+                        Err(e) => {
+                            info!("audio thread stopping: {}", e);
+                            return;
+                        }
+                    };
+                    if frames > 0 {
+                        crate::timed_block!("audio pull");
+                        if buf_written == buf.len() {
+                            buf.resize(frames * n_channels, 0);
+                            consumer.pull(&mut buf);
+                            buf_written = 0;
+                        }
+                        match output.write(&buf[buf_written..]) {
+                            Ok(written) => buf_written += written * n_channels,
+                            Err(e) => {
+                                info!("audio thread stopping: {}", e);
+                                return;
+                            }
+                        }
+                    }
+                    std::thread::sleep(sleep);
+                }
+            })
+            .map_err(|e| e.to_string())?;
+
+        match opened_rx.recv() {
+            Ok(Ok(())) => Ok(LinuxAudioThread {
+                stop,
+                handle: Some(handle),
+            }),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err("audio thread died".to_string()),
+        }
+    }
+}
+
+impl Drop for LinuxAudioThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+struct LinuxLogger;
+
+impl log::Log for LinuxLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        eprintln!("{} {}", record.level(), record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: LinuxLogger = LinuxLogger;
+
+// Cleared by SIGINT/SIGTERM, the only way to quit without a window.
+static RUNNING: AtomicBool = AtomicBool::new(true);
+
+extern "C" fn linux_on_signal(_signal: libc::c_int) {
+    RUNNING.store(false, Ordering::Relaxed);
+}
+
+// RMH_AUDIO_DEVICE overrides it, e.g. with "null" on a machine without sound
+const AUDIO_DEVICE: &str = "default";
+const AUDIO_LATENCY_MS: u32 = 50;
+const CONTROLLER_DB_PATH: &str = "gamecontrollerdb.txt";
+// how far ahead of the audio thread the game keeps the ring filled
+const AUDIO_AHEAD_MS: u32 = 100;
+const FRAME_SECONDS: f64 = 1.0 / 60.0;
+const BUFFER_WIDTH: i32 = 720;
+const BUFFER_HEIGHT: i32 = 480;
+
+fn linux_u32_argb(a: u32, r: u32, g: u32, b: u32) -> u32 {
+    (a << 24) + (r << 16) + (g << 8) + b
+}
+
+fn linux_elapsed_ms(since: std::time::Instant) -> f32 {
+    since.elapsed().as_secs_f32() * 1000.0
+}
+
+// SDL's gamecontrollerdb.txt next to the game, if there is one.
+fn linux_load_controller_db() -> ControllerDb {
+    match ControllerDb::load(Path::new(CONTROLLER_DB_PATH), "Linux") {
+        Ok((db, errors)) => {
+            for e in errors {
+                debug!("{}: {}", CONTROLLER_DB_PATH, e);
+            }
+            info!("{} controller mappings", db.len());
+            db
+        }
+        Err(e) => {
+            debug!("no controller mappings: {}", e);
+            ControllerDb::default()
+        }
+    }
+}
+
+// There's no window on Linux yet: the game runs, reads its input and plays
+// sound, and the frame is rendered into a backbuffer nobody shows.
+pub fn linux_main() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Info);
+    unsafe {
+        libc::signal(libc::SIGINT, linux_on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, linux_on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+
+    let sound_params = SoundParams {
+        bits_per_sample: 16,
+        n_channels: 2,
+        n_samples_per_sec: 48000,
+        buf_size_seconds: 2,
+    };
+    let mut state = rmh::GameState::new(sound_params.n_samples_per_sec as u32);
+    rmh::start_music(&mut state);
+
+    let mut input = Input::default();
+    let mut pads = LinuxPads::new(linux_load_controller_db());
+    // plays a recorded controller (see RecordedDevice for the format), one
+    // recorded frame per game frame, for trying the input without hardware
+    if let Ok(path) = std::env::var("RMH_PAD_RECORDING") {
+        match RecordedDevice::load(Path::new(&path)) {
+            Ok(device) => {
+                pads.attach(&mut input, Box::new(device), None);
+            }
+            Err(e) => info!("no recorded controller: {}", e),
+        }
+    }
+    let deadzones = Deadzones::default();
+    let mut rumble = rmh::rumble::Rumble::new();
+    let mut bitmap_mem = vec![0u32; (BUFFER_WIDTH * BUFFER_HEIGHT) as usize];
+
+    let n_channels = sound_params.n_channels as usize;
+    let ahead_samples = (sound_params.n_samples_per_sec as u32 * AUDIO_AHEAD_MS / 1000) as usize * n_channels;
+    // the ring stands in for DirectSound's looping buffer, sized the same
+    let ring_samples = (sound_params.buf_size_bytes() / sound_params.bytes_per_sample()) as usize * n_channels;
+    let (mut producer, consumer) = sample_ring(ring_samples);
+    // start out full so the thread's first pulls don't count as underruns
+    producer.push(&vec![0; ahead_samples]);
+    // dropping it stops the thread, so it's kept until the loop ends
+    let audio_device = std::env::var("RMH_AUDIO_DEVICE").unwrap_or_else(|_| AUDIO_DEVICE.to_string());
+    let audio_thread = match LinuxAudioThread::spawn(&audio_device, sound_params, AUDIO_LATENCY_MS, consumer) {
+        Ok(thread) => Some(thread),
+        Err(e) => {
+            info!("no sound: {}", e);
+            None
+        }
+    };
+    let mut audio_samples = Vec::new();
+    let mut sine_wave_sample_counter = 0;
+    let mut frame_history = rmh::perf::FrameHistory::new();
+
+    let game_start = std::time::Instant::now();
+    let mut frame_start = game_start;
+    let mut dt = 0.0;
+
+    while RUNNING.load(Ordering::Relaxed) {
+        input.begin_frame();
+        input.time = frame_start.duration_since(game_start).as_secs_f64();
+        input.dt = dt;
+
+        {
+            crate::timed_block!("input");
+            pads.update(&mut input, &deadzones);
+        }
+        for event in input.pad_events.iter() {
+            if let rmh::input::PadEvent::Disconnected(slot) = *event {
+                rumble.stop(slot);
+            }
+        }
+
+        let mut timings = rmh::perf::FrameTimings::default();
+
+        let section_start = std::time::Instant::now();
+        rmh::update_state(&mut state, &input, &mut rumble);
+        rumble.update(dt, &mut rmh::rumble::NullRumble);
+        timings.update_ms = linux_elapsed_ms(section_start);
+
+        let section_start = std::time::Instant::now();
+        rmh::render_gfx(
+            &mut bitmap_mem,
+            BUFFER_WIDTH,
+            BUFFER_HEIGHT,
+            state.x_offset,
+            state.y_offset,
+            &linux_u32_argb,
+        );
+        rmh::render_hud(&mut state, &input, &mut bitmap_mem, BUFFER_WIDTH, BUFFER_HEIGHT);
+        timings.render_ms = linux_elapsed_ms(section_start);
+
+        let section_start = std::time::Instant::now();
+        if audio_thread.is_some() {
+            crate::timed_block!("audio");
+            // top the ring back up, in whole frames
+            let wanted = ahead_samples.saturating_sub(producer.len()) / n_channels * n_channels;
+            audio_samples.clear();
+            audio_samples.resize(wanted, 0);
+            rmh::render_audio(
+                &mut audio_samples,
+                state.sine_wave_half_len,
+                &mut sine_wave_sample_counter,
+                &mut state.mixer,
+            );
+            producer.push(&audio_samples);
+
+            let queued_seconds = producer.len() as f32 / n_channels as f32 / sound_params.n_samples_per_sec as f32;
+            input.audio_latency = queued_seconds + AUDIO_LATENCY_MS as f32 / 1000.0;
+            input.audio_stats = producer.stats();
+        }
+        timings.audio_ms = linux_elapsed_ms(section_start);
+        // nothing to present without a window
+
+        rmh::profile::frame_end();
+
+        let frame_end = frame_start + std::time::Duration::from_secs_f64(FRAME_SECONDS);
+        let now = std::time::Instant::now();
+        if frame_end > now {
+            std::thread::sleep(frame_end - now);
+        }
+        let now = std::time::Instant::now();
+        dt = now.duration_since(frame_start).as_secs_f32();
+        frame_start = now;
+
+        // the whole frame, sleep included
+        timings.total_ms = dt * 1000.0;
+        frame_history.push(timings);
+    }
+
+    drop(audio_thread);
+    let stats = producer.stats();
+    info!("sound: {} underruns, {} of {} samples missed", stats.underruns, stats.samples_missed, stats.samples_played);
+    // what the overlay shows on Windows, for the last frames before quitting
+    info!("{} (last {} frames)", rmh::perf::SUMMARY_HEADER, rmh::perf::FRAME_HISTORY_LEN);
+    for line in rmh::perf::summary(&frame_history).iter() {
+        info!("{}", line);
+    }
+
+    // like F4 on Windows, for the frames that led up to quitting
+    if let Ok(path) = std::env::var("RMH_TRACE") {
+        match rmh::profile::export_chrome_trace(Path::new(&path)) {
+            Ok(()) => info!("wrote {}", path),
+            Err(e) => debug!("trace export failed: {}", e),
+        }
+    }
+
+    // volumes may have been changed in game
+    if let Err(e) = state.mixer.settings.save(Path::new(rmh::AUDIO_SETTINGS_PATH)) {
+        debug!("could not save audio settings: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rmh::evdev::RecordedDevice;
+
+    // Needs libasound; run with `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn alsa_null_device() {
+        let params = SoundParams {
+            bits_per_sample: 16,
+            n_channels: 2,
+            n_samples_per_sec: 48000,
+            buf_size_seconds: 2,
+        };
+        let mut output = AlsaOutput::open("null", &params, 20).unwrap();
+        let frames = output.frames_to_write().unwrap();
+        assert!(frames > 0 && frames <= 960);
+
+        let samples = vec![0i16; frames * 2];
+        assert_eq!(output.write(&samples).unwrap(), frames);
+        assert_eq!(output.underruns, 0);
+    }
+
+    #[test]
+    fn pads_replay_recording() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/evdev_xbox360.txt");
+        let device = RecordedDevice::load(&path).unwrap();
+        let mut input = Input::default();
+        let mut pads = LinuxPads::new(ControllerDb::default());
+        // keep update() away from the machine's own /dev/input
+        pads.frames_to_rescan = RESCAN_FRAMES;
+        assert_eq!(pads.attach(&mut input, Box::new(device), None), Some(0));
+        assert!(input.controller(0).is_connected);
+
+        let deadzones = Deadzones::default();
+        let mut a_presses = 0;
+        let mut a_releases = 0;
+        for _ in 0..5 {
+            input.begin_frame();
+            pads.update(&mut input, &deadzones);
+            a_presses += input.controller(0).a.was_pressed() as u32;
+            a_releases += input.controller(0).a.was_released() as u32;
+        }
+        assert_eq!((a_presses, a_releases), (1, 1));
+        assert!(!input.controller(0).b.is_down());
+        assert!(input.controller(0).is_connected);
+    }
+}
@@ -3,5 +3,12 @@
 mod win32;
 #[cfg(target_os="windows")]
 include!("win32.rs");
+#[cfg(target_os="linux")]
+mod linux;
 
-mod rmh;
\ No newline at end of file
+mod rmh;
+
+#[cfg(target_os="linux")]
+fn main() {
+    linux::linux_main();
+}
@@ -1,52 +1,325 @@
 use log::debug;
 
-type BuildPixelFn<'a> = &'a dyn Fn(u32,u32,u32,u32,) -> u32;
+pub mod actions;
+pub mod audio;
+pub mod bus;
+pub mod controller_db;
+pub mod evdev;
+pub mod input;
+pub mod keyboard;
+pub mod midi;
+pub mod mixer;
+pub mod music;
+#[cfg(any(target_os="windows", test))]
+pub mod overlay;
+pub mod render;
+pub mod text;
+pub mod tracker;
+pub mod truetype;
+pub mod perf;
+pub mod profile;
+pub mod ring;
+pub mod rumble;
+pub mod sfx;
+pub mod synth;
 
-pub struct Pad {
-    pub up: bool,
-    pub down: bool,
-    pub left: bool,
-    pub right: bool,
-}
+pub use actions::{Action, ActionMap, ALL_ACTIONS};
+pub use input::Input;
+
+use render::{Bitmap, V2};
+use text::{Align, BitmapFont, GlyphSource};
+
+type BuildPixelFn<'a> = &'a dyn Fn(u32,u32,u32,u32,) -> u32;
 
 pub struct GameState {
     pub x_offset: i32,
     pub y_offset: i32,
     pub sine_wave_half_len: i32,
+    pub actions: ActionMap,
+    pub mixer: mixer::Mixer,
+    pub music: music::MusicPlayer,
+    // interleaved stereo at the mixer rate
+    pub jump_sound: std::sync::Arc<Vec<f32>>,
+    // the last jump, cut off if it's still playing when the next one starts
+    pub jump_voice: mixer::SoundId,
+    // sounds rolled with F7 so far; picks the next preset and seed
+    pub auditions: u64,
+    // after F6, the index in ALL_ACTIONS of the action waiting for its new
+    // key or button
+    pub rebinding: Option<usize>,
+    pub ui_font: UiFont,
+    pub sprite: Bitmap,
+}
+
+// Menu text: a TrueType font rasterized at UI_FONT_PX, or a bitmap font.
+pub enum UiFont {
+    TrueType(truetype::TrueTypeFont),
+    Bitmap(BitmapFont),
+}
+
+pub const BINDINGS_PATH: &str = "rmh_bindings.cfg";
+pub const AUDIO_SETTINGS_PATH: &str = "rmh_audio.cfg";
+pub const JUMP_SFX_PATH: &str = "rmh_jump.sfx";
+pub const MUSIC_PATH: &str = "rmh_music.ogg";
+pub const MOD_MUSIC_PATH: &str = "rmh_music.mod";
+pub const MIDI_MUSIC_PATH: &str = "rmh_music.mid";
+pub const AUDITION_SFX_PATH: &str = "rmh_audition.sfx";
+pub const UI_FONT_PATH: &str = "rmh_ui.ttf";
+pub const UI_BMFONT_PATH: &str = "rmh_ui.fnt";
+pub const SPRITE_PATH: &str = "rmh_sprite.tga";
+
+const UI_FONT_PX: u32 = 20;
+
+impl GameState {
+    // Loads the user's settings, writing out the defaults on first run.
+    pub fn new(sample_rate: u32) -> GameState {
+        let mut mixer = mixer::Mixer::new(sample_rate);
+        mixer.set_settings(load_audio_settings());
+        GameState {
+            x_offset: 0,
+            y_offset: 0,
+            sine_wave_half_len: 30,
+            actions: load_bindings(),
+            mixer,
+            music: music::MusicPlayer::new(sample_rate),
+            jump_sound: load_sfx(JUMP_SFX_PATH, sfx::SfxPreset::Jump).render(sample_rate, 0),
+            jump_voice: 0,
+            auditions: 0,
+            rebinding: None,
+            ui_font: load_ui_font(),
+            sprite: load_sprite(),
+        }
+    }
+}
+
+// Falls back to the default bindings, writing them out on first run so
+// there's a file to edit.
+pub fn load_bindings() -> ActionMap {
+    let path = std::path::Path::new(BINDINGS_PATH);
+    if !path.exists() {
+        let actions = ActionMap::default();
+        if let Err(e) = actions.save(path) {
+            debug!("could not write default bindings: {}", e);
+        }
+        return actions;
+    }
+    match ActionMap::load(path) {
+        Ok(actions) => actions,
+        Err(e) => {
+            debug!("could not load bindings, using defaults: {}", e);
+            ActionMap::default()
+        }
+    }
+}
+
+// Same as the bindings: defaults are written out on first run.
+pub fn load_audio_settings() -> bus::AudioSettings {
+    let path = std::path::Path::new(AUDIO_SETTINGS_PATH);
+    if !path.exists() {
+        let settings = bus::AudioSettings::default();
+        if let Err(e) = settings.save(path) {
+            debug!("could not write default audio settings: {}", e);
+        }
+        return settings;
+    }
+    match bus::AudioSettings::load(path) {
+        Ok(settings) => settings,
+        Err(e) => {
+            debug!("could not load audio settings, using defaults: {}", e);
+            bus::AudioSettings::default()
+        }
+    }
+}
+
+// Like the bindings: a preset is rolled and written out on first run, so
+// there's a parameter file to tweak.
+pub fn load_sfx(path: &str, preset: sfx::SfxPreset) -> sfx::SfxParams {
+    let path = std::path::Path::new(path);
+    if !path.exists() {
+        let params = sfx::SfxParams::preset(preset, &mut sfx::SfxRng::new(0));
+        if let Err(e) = params.save(path) {
+            debug!("could not write default sound: {}", e);
+        }
+        return params;
+    }
+    match sfx::SfxParams::load(path) {
+        Ok(params) => params,
+        Err(e) => {
+            debug!("could not load sound, using preset: {}", e);
+            sfx::SfxParams::preset(preset, &mut sfx::SfxRng::new(0))
+        }
+    }
+}
+
+// The first font found: TrueType, then BMFont, then the built-in debug font.
+pub fn load_ui_font() -> UiFont {
+    let ttf_error = match truetype::TrueTypeFont::load(std::path::Path::new(UI_FONT_PATH)) {
+        Ok(font) => return UiFont::TrueType(font),
+        Err(e) => e,
+    };
+    match BitmapFont::load(std::path::Path::new(UI_BMFONT_PATH)) {
+        Ok(font) => UiFont::Bitmap(font),
+        Err(fnt_error) => {
+            debug!("built-in ui font: {}; {}", ttf_error, fnt_error);
+            UiFont::Bitmap(BitmapFont::builtin())
+        }
+    }
+}
+
+pub fn load_sprite() -> Bitmap {
+    let sprite = std::fs::read(SPRITE_PATH)
+        .map_err(|e| format!("{}: {}", SPRITE_PATH, e))
+        .and_then(|bytes| Bitmap::from_tga(&bytes));
+    match sprite {
+        Ok(sprite) => sprite,
+        Err(e) => {
+            debug!("no sprite, using a disc: {}", e);
+            default_sprite()
+        }
+    }
+}
+
+// A disc with a lighter half, so it can be seen turning.
+fn default_sprite() -> Bitmap {
+    const SIZE: i32 = 16;
+    let radius = SIZE as f32 / 2.0;
+    let mut argb = Vec::with_capacity((SIZE * SIZE) as usize);
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let dx = x as f32 + 0.5 - radius;
+            let dy = y as f32 + 0.5 - radius;
+            let alpha = ((radius - (dx * dx + dy * dy).sqrt()).clamp(0.0, 1.0) * 255.0) as u32;
+            let rgb = if x < SIZE / 2 { 0x00FF_D040 } else { 0x00C0_6020 };
+            argb.push((alpha << 24) | rgb);
+        }
+    }
+    Bitmap::from_argb(SIZE, SIZE, &argb)
+}
+
+// Plays the first soundtrack found: streamed Vorbis, then a tracker module,
+// then MIDI through the synth.
+pub fn start_music(state: &mut GameState) {
+    let music_voice = state.music.voice();
+    state.mixer.play(bus::Bus::Music, music_voice);
+
+    let ogg_error = match music::OggStream::open(std::path::Path::new(MUSIC_PATH)) {
+        Ok(mut stream) => {
+            stream.set_looping(true);
+            state.music.play(stream, 0.0);
+            return;
+        }
+        Err(e) => e,
+    };
+    let mod_error = match tracker::Module::load(std::path::Path::new(MOD_MUSIC_PATH)) {
+        Ok(module) => {
+            debug!("music: {} ({})", MOD_MUSIC_PATH, module.title);
+            let mut player = tracker::ModPlayer::new(std::sync::Arc::new(module));
+            player.looping = true;
+            state.music.play_source(Box::new(player), 0.0);
+            return;
+        }
+        Err(e) => e,
+    };
+    match midi::Song::load(std::path::Path::new(MIDI_MUSIC_PATH)) {
+        Ok(song) => {
+            let mut player = midi::MidiPlayer::new(std::sync::Arc::new(song));
+            player.looping = true;
+            state.music.play_source(Box::new(player), 0.0);
+        }
+        Err(midi_error) => debug!("no music: {}; {}; {}", ogg_error, mod_error, midi_error),
+    }
 }
 
 pub fn render_gfx(
-    mem: &mut Vec<u32>,
+    mem: &mut [u32],
     w: i32,
     h: i32,
     x_offset: i32,
     y_offset: i32,
     build_pixel: BuildPixelFn
 ) {
+    crate::timed_block!("render_gfx");
+    let top = [1.0, 0.0, 0.0, 0.0];
+    let bottom = [1.0, 0.0, 0.0, 0.04];
     for y in 0..h {
+        // background gradient is interpolated in linear space
+        let t = y as f32 / h.max(1) as f32;
+        let mut bg = [0.0; 4];
+        for i in 0..4 {
+            bg[i] = top[i] + (bottom[i] - top[i]) * t;
+        }
+        let bg = render::linear1_to_srgb255(bg);
+        let bg = build_pixel(bg[0] as u32, bg[1] as u32, bg[2] as u32, bg[3] as u32);
         for x in 0..w {
             let idx = (y * w + x) as usize;
             if (x-x_offset) % 100 == 0 || (y-y_offset) % 100 == 0 {
                 mem[idx]= build_pixel(255, 0, 255, 0);
             } else {
-                mem[idx]= build_pixel(255, 0, 0, 0);
+                mem[idx]= bg;
             }
         }
     }
 }
 
+// Drawn over render_gfx's frame, which must be 0xAARRGGBB (see render.rs):
+// the sprite spinning on the mouse cursor, then the text.
+pub fn render_hud(state: &mut GameState, input: &Input, mem: &mut [u32], w: i32, h: i32) {
+    crate::timed_block!("render_hud");
+    let size = state.sprite.width.max(state.sprite.height) as f32 * 2.0;
+    let x_axis = V2::from_angle(input.time as f32, size);
+    let y_axis = x_axis.perp();
+    let center = V2::new(input.mouse.x as f32, input.mouse.y as f32);
+    render::draw_bitmap_quad(mem, w, h, center - (x_axis + y_axis) * 0.5, x_axis, y_axis, &state.sprite);
+
+    let music_paused = state.music.is_paused();
+    let prompt = state.rebinding.map(|index| rebinding_prompt(input, ALL_ACTIONS[index]));
+    match &mut state.ui_font {
+        UiFont::TrueType(font) => draw_hud_text(mem, w, h, &mut font.sized(UI_FONT_PX), music_paused, prompt.as_deref()),
+        UiFont::Bitmap(font) => draw_hud_text(mem, w, h, font, music_paused, prompt.as_deref()),
+    }
+}
+
+// The debug keys bottom left, pause top right, and the rebinding prompt
+// boxed in the middle.
+fn draw_hud_text(mem: &mut [u32], w: i32, h: i32, font: &mut dyn GlyphSource, music_paused: bool, prompt: Option<&str>) {
+    let padding = 8;
+    let bottom = h - padding - font.line_height();
+    text::draw_text(mem, w, h, font, padding, bottom, "F6 rebind keys, F7 roll a sound", 0xFFC0_C0C0);
+
+    if music_paused {
+        let layout = text::layout_text(font, "music paused", Some(w - padding * 2), Align::Right);
+        text::draw_layout(mem, w, h, font, &layout, padding, padding, 0xFFFF_FFFF);
+    }
+
+    if let Some(prompt) = prompt {
+        let box_width = w - padding * 4;
+        let layout = text::layout_text(font, prompt, Some(box_width), Align::Center);
+        let x = padding * 2;
+        let y = (h - layout.height) / 2;
+        let left = x + (box_width - layout.width) / 2;
+        let right = left + layout.width;
+        render::draw_rect(mem, w, h, left - padding, y - padding, right + padding, y + layout.height + padding, 0xC000_0000);
+        text::draw_layout(mem, w, h, font, &layout, x, y, 0xFFFF_FFFF);
+    }
+}
+
+fn rebinding_prompt(input: &Input, action: Action) -> String {
+    let what = if input.keyboard_only() { "a key" } else { "a key or button" };
+    format!("press {} for {}, escape to skip", what, action.name())
+}
+
 pub fn render_audio(
-    buf: &mut Vec<i16>,
+    buf: &mut [i16],
     sine_wave_half_len: i32,
     t_sine: &mut i32,
+    mixer: &mut mixer::Mixer,
 ) {
+    crate::timed_block!("render_audio");
     let amplitude = 2000;
     for i in (0..buf.len()).step_by(2) {
-        let radians = (
-            (std::f32::consts::PI * 2.0)
+        let radians = (std::f32::consts::PI * 2.0)
             / (sine_wave_half_len * 2) as f32
-            * (*t_sine) as f32
-        ) as f32;
+            * (*t_sine) as f32;
         let sample = (radians.sin() * amplitude as f32) as i16;
         buf[i] = sample;
         buf[i+1] = sample;
@@ -55,24 +328,233 @@ pub fn render_audio(
             *t_sine = 0;
         }
     }
+    mixer.mix(buf);
 }
 
 pub fn update_state(
     state: &mut GameState,
-    pad: &Pad,
+    input: &Input,
+    rumble: &mut rumble::Rumble,
 ) {
-    if pad.up {
+    crate::timed_block!("update_state");
+    if state.rebinding.is_some() {
+        if update_rebinding(state, input) {
+            if let Err(e) = state.actions.save(std::path::Path::new(BINDINGS_PATH)) {
+                debug!("could not save bindings: {}", e);
+            }
+        }
+        return;
+    }
+    if input.keys.was_pressed(keyboard::Key::F6) {
+        state.rebinding = Some(0);
+        debug!("rebinding: {}", rebinding_prompt(input, ALL_ACTIONS[0]));
+        return;
+    }
+    if input.keys.was_pressed(keyboard::Key::F7) {
+        audition_sfx(state);
+    }
+    let actions = &state.actions;
+    if actions.is_down(input, Action::MoveUp) {
         // state.y_offset -= 5;
         state.sine_wave_half_len += 1;
     }
-    if pad.down {
+    if actions.is_down(input, Action::MoveDown) {
         // state.y_offset += 5;
         state.sine_wave_half_len -= 1;
     }
-    if pad.left {
+    if actions.is_down(input, Action::MoveLeft) {
         state.x_offset -= 5;
     }
-    if pad.right {
+    if actions.is_down(input, Action::MoveRight) {
         state.x_offset += 5;
     }
-}
\ No newline at end of file
+    for pad in input.connected_pads() {
+        state.x_offset += (pad.left_stick.x * 5.0) as i32;
+    }
+    if actions.was_pressed(input, Action::Jump) {
+        // input is read once per frame, so the press lands at the start of
+        // this frame's audio; input.time is wall-clock and says nothing about
+        // where the mixer is
+        let sound = mixer::Clip::new(state.jump_sound.clone(), 1.0);
+        let frame = state.mixer.frame_after(0.0);
+        if state.mixer.is_playing(state.jump_voice) {
+            state.mixer.stop_at(frame, state.jump_voice);
+        }
+        state.jump_voice = state.mixer.play_at(frame, bus::Bus::Sfx, Box::new(sound));
+        for slot in 0..input::MAX_CONTROLLERS {
+            if input.controller(slot).is_connected {
+                rumble.request(slot, 0.3, 0.6, 0.15);
+            }
+        }
+    }
+    if actions.was_pressed(input, Action::Pause) {
+        if state.music.is_paused() {
+            state.music.resume();
+        } else {
+            state.music.pause();
+        }
+    }
+}
+// Rolls a sound from the next preset in turn and plays it, keeping its
+// parameters in AUDITION_SFX_PATH so one worth keeping can be copied over.
+fn audition_sfx(state: &mut GameState) {
+    let presets = sfx::ALL_SFX_PRESETS;
+    let preset = presets[(state.auditions % presets.len() as u64) as usize];
+    let params = sfx::SfxParams::preset(preset, &mut sfx::SfxRng::new(state.auditions));
+    state.auditions += 1;
+    debug!("audition: {:?}, saved to {}", preset, AUDITION_SFX_PATH);
+    if let Err(e) = params.save(std::path::Path::new(AUDITION_SFX_PATH)) {
+        debug!("could not save sound: {}", e);
+    }
+    let sound = mixer::Clip::new(params.render(state.mixer.sample_rate, 0), 1.0);
+    state.mixer.play(bus::Bus::Ui, Box::new(sound));
+}
+
+// Walks through ALL_ACTIONS, giving each the first key or button pressed;
+// Escape skips an action and keeps its bindings. Other reserved keys (see
+// actions::RESERVED_KEYS) are ignored. Returns whether a binding changed;
+// the caller saves after every change, so quitting halfway keeps what was
+// bound so far.
+fn update_rebinding(state: &mut GameState, input: &Input) -> bool {
+    let index = match state.rebinding {
+        Some(index) => index,
+        None => return false,
+    };
+    let action = ALL_ACTIONS[index];
+    let mut changed = false;
+    if input.keys.was_pressed(keyboard::Key::Escape) {
+        debug!("rebinding: kept {}", action.name());
+    } else if let Some(binding) = actions::capture_binding(input) {
+        state.actions.rebind(action, binding);
+        debug!("rebinding: {} = {:?}", action.name(), binding);
+        changed = true;
+    } else {
+        return false;
+    }
+
+    if index + 1 < ALL_ACTIONS.len() {
+        state.rebinding = Some(index + 1);
+        debug!("rebinding: {}", rebinding_prompt(input, ALL_ACTIONS[index + 1]));
+    } else {
+        state.rebinding = None;
+        debug!("rebinding: done");
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_state() -> GameState {
+        GameState {
+            x_offset: 0,
+            y_offset: 0,
+            sine_wave_half_len: 30,
+            actions: ActionMap::default(),
+            mixer: mixer::Mixer::new(48000),
+            music: music::MusicPlayer::new(48000),
+            jump_sound: std::sync::Arc::new(vec![0.5; 200]),
+            jump_voice: 0,
+            auditions: 0,
+            rebinding: None,
+            ui_font: UiFont::Bitmap(BitmapFont::builtin()),
+            sprite: default_sprite(),
+        }
+    }
+
+    #[test]
+    fn jump_sound_starts_with_the_frames_audio() {
+        let mut state = test_state();
+        let mut rumble = rumble::Rumble::new();
+        let mut buffer = vec![0i16; 1600];
+        state.mixer.mix(&mut buffer);
+
+        // a few seconds in: wall-clock time is nowhere near the mixer clock
+        let mut input = Input { time: 3.25, ..Input::default() };
+        input.key_event(keyboard::Key::Space, true, false);
+        update_state(&mut state, &input, &mut rumble);
+
+        let mut buffer = vec![0i16; 1600];
+        state.mixer.mix(&mut buffer);
+        assert_eq!(buffer.iter().position(|&s| s != 0), Some(0));
+    }
+
+    #[test]
+    fn rebinding_walks_every_action() {
+        use actions::Binding;
+        let mut state = test_state();
+        let mut rumble = rumble::Rumble::new();
+        let mut input = Input::default();
+        input.key_event(keyboard::Key::F6, true, false);
+        update_state(&mut state, &input, &mut rumble);
+        assert_eq!(state.rebinding, Some(0));
+
+        let new_keys = [keyboard::Key::I, keyboard::Key::K, keyboard::Key::J, keyboard::Key::L];
+        for (i, &action) in ALL_ACTIONS.iter().enumerate() {
+            // debug keys don't get bound and don't move on
+            input.release_all();
+            input.begin_frame();
+            input.key_event(keyboard::Key::F3, true, false);
+            assert!(!update_rebinding(&mut state, &input));
+            assert_eq!(state.rebinding, Some(i));
+
+            input.release_all();
+            input.begin_frame();
+            match new_keys.get(i) {
+                Some(&key) => {
+                    input.key_event(key, true, false);
+                    assert!(update_rebinding(&mut state, &input));
+                    assert!(state.actions.bindings(action).contains(&Binding::Key(key)));
+                }
+                None => {
+                    input.key_event(keyboard::Key::Escape, true, false);
+                    assert!(!update_rebinding(&mut state, &input));
+                    assert_eq!(state.actions.bindings(action), ActionMap::default().bindings(action));
+                }
+            }
+        }
+        assert_eq!(state.rebinding, None);
+        assert_eq!(state.actions.bindings(Action::MoveUp)[1..], [Binding::Key(keyboard::Key::I)]);
+    }
+
+    #[test]
+    fn jumping_again_cuts_the_last_jump_off() {
+        let mut state = test_state();
+        state.jump_sound = std::sync::Arc::new(vec![0.5; 8000]);
+        let mut rumble = rumble::Rumble::new();
+        let mut input = Input::default();
+        input.key_event(keyboard::Key::Space, true, false);
+        update_state(&mut state, &input, &mut rumble);
+        let first = state.jump_voice;
+        state.mixer.mix(&mut [0i16; 1600]);
+        assert!(state.mixer.is_playing(first));
+
+        input.release_all();
+        input.begin_frame();
+        input.key_event(keyboard::Key::Space, true, false);
+        update_state(&mut state, &input, &mut rumble);
+        state.mixer.mix(&mut [0i16; 1600]);
+        assert!(!state.mixer.is_playing(first));
+        assert!(state.mixer.is_playing(state.jump_voice));
+    }
+
+    #[test]
+    fn hud_boxes_the_rebinding_prompt() {
+        const GREY: u32 = 0xFF80_8080;
+        let (w, h) = (320, 200);
+        let mut state = test_state();
+        let input = Input::default();
+        let middle = |mem: &[u32]| mem[(h / 2 - 30) as usize * w as usize..(h / 2 + 30) as usize * w as usize].to_vec();
+
+        let mut mem = vec![GREY; (w * h) as usize];
+        render_hud(&mut state, &input, &mut mem, w, h);
+        assert!(middle(&mem).iter().all(|&p| p == GREY));
+
+        state.rebinding = Some(0);
+        render_hud(&mut state, &input, &mut mem, w, h);
+        let band = middle(&mem);
+        assert!(band.contains(&0xFFFF_FFFF));
+        assert!(band.iter().filter(|&&p| p & 0xFF < 0x80).count() > 1000);
+    }
+}
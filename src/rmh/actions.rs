@@ -0,0 +1,439 @@
+use super::input::{Input, Pad};
+#[cfg(any(target_os="windows", test))]
+use super::input::KEYBOARD_PAD;
+use super::keyboard::{Key, KeyEvent};
+
+// What the game reacts to, independent of the key or button that triggers it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Run,
+    Pause,
+    Back,
+}
+
+pub const ALL_ACTIONS: &[Action] = &[
+    Action::MoveUp,
+    Action::MoveDown,
+    Action::MoveLeft,
+    Action::MoveRight,
+    Action::Jump,
+    Action::Run,
+    Action::Pause,
+    Action::Back,
+];
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveUp => "move_up",
+            Action::MoveDown => "move_down",
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::Jump => "jump",
+            Action::Run => "run",
+            Action::Pause => "pause",
+            Action::Back => "back",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        ALL_ACTIONS.iter().copied().find(|a| a.name() == name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PadButton {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    X,
+    Y,
+    LeftShoulder,
+    RightShoulder,
+    LeftThumb,
+    RightThumb,
+    Start,
+    Back,
+}
+
+pub const ALL_PAD_BUTTONS: &[PadButton] = &[
+    PadButton::Up,
+    PadButton::Down,
+    PadButton::Left,
+    PadButton::Right,
+    PadButton::A,
+    PadButton::B,
+    PadButton::X,
+    PadButton::Y,
+    PadButton::LeftShoulder,
+    PadButton::RightShoulder,
+    PadButton::LeftThumb,
+    PadButton::RightThumb,
+    PadButton::Start,
+    PadButton::Back,
+];
+
+impl PadButton {
+    pub fn name(self) -> &'static str {
+        match self {
+            PadButton::Up => "up",
+            PadButton::Down => "down",
+            PadButton::Left => "left",
+            PadButton::Right => "right",
+            PadButton::A => "a",
+            PadButton::B => "b",
+            PadButton::X => "x",
+            PadButton::Y => "y",
+            PadButton::LeftShoulder => "left_shoulder",
+            PadButton::RightShoulder => "right_shoulder",
+            PadButton::LeftThumb => "left_thumb",
+            PadButton::RightThumb => "right_thumb",
+            PadButton::Start => "start",
+            PadButton::Back => "back",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PadButton> {
+        ALL_PAD_BUTTONS.iter().copied().find(|b| b.name() == name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Key(Key),
+    // matches the button on any connected controller
+    Pad(PadButton),
+}
+
+impl Binding {
+    fn parse(s: &str) -> Result<Binding, String> {
+        let mut parts = s.splitn(2, ':');
+        let kind = parts.next().unwrap_or("").trim();
+        let name = parts.next().unwrap_or("").trim();
+        match kind {
+            "key" => Key::from_name(name)
+                .map(Binding::Key)
+                .ok_or(format!("unknown key '{}'", name)),
+            "pad" => PadButton::from_name(name)
+                .map(Binding::Pad)
+                .ok_or(format!("unknown pad button '{}'", name)),
+            _ => Err(format!("bad binding '{}', expected key:<name> or pad:<name>", s)),
+        }
+    }
+
+    fn to_config(self) -> String {
+        match self {
+            Binding::Key(key) => format!("key:{}", key.name()),
+            Binding::Pad(button) => format!("pad:{}", button.name()),
+        }
+    }
+}
+
+// Bindings per action, indexed by `Action as usize`.
+#[derive(Clone, Debug)]
+pub struct ActionMap {
+    bindings: Vec<Vec<Binding>>,
+}
+
+impl ActionMap {
+    pub fn empty() -> ActionMap {
+        ActionMap {
+            bindings: vec![Vec::new(); ALL_ACTIONS.len()],
+        }
+    }
+
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        &self.bindings[action as usize]
+    }
+
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        let bindings = &mut self.bindings[action as usize];
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn clear(&mut self, action: Action) {
+        self.bindings[action as usize].clear();
+    }
+
+    // Replaces the bindings of the same kind (key or pad) as `binding`, so
+    // rebinding the keyboard leaves the controller bindings alone.
+    pub fn rebind(&mut self, action: Action, binding: Binding) {
+        let same_kind = |b: &Binding| {
+            matches!((b, &binding), (Binding::Key(_), Binding::Key(_)) | (Binding::Pad(_), Binding::Pad(_)))
+        };
+        self.bindings[action as usize].retain(|b| !same_kind(b));
+        self.bindings[action as usize].push(binding);
+    }
+
+    pub fn is_down(&self, input: &Input, action: Action) -> bool {
+        self.any(input, action, |key| input.keys.is_down(key), |pad, button| {
+            pad.button(button).is_down()
+        })
+    }
+
+    pub fn was_pressed(&self, input: &Input, action: Action) -> bool {
+        self.any(input, action, |key| input.keys.was_pressed(key), |pad, button| {
+            pad.button(button).was_pressed()
+        })
+    }
+
+    fn any(
+        &self,
+        input: &Input,
+        action: Action,
+        key_test: impl Fn(Key) -> bool,
+        pad_test: impl Fn(&Pad, PadButton) -> bool,
+    ) -> bool {
+        self.bindings(action).iter().any(|binding| match *binding {
+            Binding::Key(key) => key_test(key),
+            Binding::Pad(button) => input.connected_controllers().any(|pad| pad_test(pad, button)),
+        })
+    }
+
+    // Fills the keyboard pad from the keys, the way the bindings lay it out:
+    // a pad button is held while any key bound to the same action as the
+    // button is. Called once the frame's key events are in; they are replayed
+    // in order, so a tap within one frame still shows up as a press.
+    #[cfg(any(target_os="windows", test))]
+    pub fn update_keyboard_pad(&self, input: &mut Input) {
+        let mut layout: Vec<(PadButton, Vec<Key>)> = Vec::new();
+        for &button in ALL_PAD_BUTTONS {
+            let mut keys = Vec::new();
+            for bindings in self.bindings.iter().filter(|b| b.contains(&Binding::Pad(button))) {
+                for binding in bindings {
+                    if let Binding::Key(key) = *binding {
+                        keys.push(key);
+                    }
+                }
+            }
+            layout.push((button, keys));
+        }
+
+        // the keys' state before this frame's events
+        let mut down: Vec<Key> = layout.iter().flat_map(|(_, keys)| keys.iter().copied()).filter(|&key| {
+            let state = input.keys.key(key);
+            state.ended_down != (state.half_transition_count % 2 == 1)
+        }).collect();
+
+        let pad = &mut input.pads[KEYBOARD_PAD];
+        let mut apply = |down: &[Key]| {
+            for (button, keys) in &layout {
+                pad.button_mut(*button).update(keys.iter().any(|k| down.contains(k)));
+            }
+        };
+        apply(&down);
+        for event in input.keys.events.iter().filter(|e| !e.is_repeat) {
+            down.retain(|&k| k != event.key);
+            if event.is_down {
+                down.push(event.key);
+            }
+            apply(&down);
+        }
+    }
+
+    // One `action = binding, binding` line per action.
+    pub fn to_config(&self) -> String {
+        let mut out = String::new();
+        for &action in ALL_ACTIONS {
+            let bindings: Vec<String> = self.bindings(action).iter().map(|b| b.to_config()).collect();
+            out.push_str(&format!("{} = {}\n", action.name(), bindings.join(", ")));
+        }
+        out
+    }
+
+    // Actions missing from the config keep their default bindings. Blank
+    // lines and lines starting with '#' are skipped.
+    pub fn from_config(config: &str) -> Result<ActionMap, String> {
+        let mut map = ActionMap::default();
+        for (line_no, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |e: String| format!("line {}: {}", line_no + 1, e);
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            let action = Action::from_name(name).ok_or_else(|| err(format!("unknown action '{}'", name)))?;
+            let rest = parts.next().ok_or_else(|| err("expected '='".to_string()))?;
+
+            map.clear(action);
+            for binding in rest.split(',').map(str::trim).filter(|b| !b.is_empty()) {
+                map.bind(action, Binding::parse(binding).map_err(err)?);
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<ActionMap, String> {
+        let config = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        ActionMap::from_config(&config)
+    }
+
+    pub fn save(&self, path: &std::path::Path) -> Result<(), String> {
+        std::fs::write(path, self.to_config()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+impl Default for ActionMap {
+    fn default() -> Self {
+        use Binding::{Key as K, Pad as P};
+
+        let mut map = ActionMap::empty();
+        let defaults: &[(Action, &[Binding])] = &[
+            (Action::MoveUp, &[K(Key::W), K(Key::Up), P(PadButton::Up)]),
+            (Action::MoveDown, &[K(Key::S), K(Key::Down), P(PadButton::Down)]),
+            (Action::MoveLeft, &[K(Key::A), K(Key::Left), P(PadButton::Left)]),
+            (Action::MoveRight, &[K(Key::D), K(Key::Right), P(PadButton::Right)]),
+            (Action::Jump, &[K(Key::Space), P(PadButton::A)]),
+            (Action::Run, &[K(Key::LeftShift), P(PadButton::B)]),
+            (Action::Pause, &[K(Key::Enter), P(PadButton::Start)]),
+            (Action::Back, &[K(Key::Escape), P(PadButton::Back)]),
+        ];
+        for (action, bindings) in defaults {
+            for binding in bindings.iter() {
+                map.bind(*action, *binding);
+            }
+        }
+        map
+    }
+}
+
+// Keys "press a key to bind" never hands out: Escape skips the action being
+// bound, F3 to F6 are the debug overlays, trace export and rebinding itself.
+pub const RESERVED_KEYS: &[Key] = &[Key::Escape, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7];
+
+// The first key or controller button pressed this frame, for "press a key to
+// bind" menus. Reserved keys are left to their own handlers.
+pub fn capture_binding(input: &Input) -> Option<Binding> {
+    let is_new_press = |e: &&KeyEvent| e.is_down && !e.is_repeat && !RESERVED_KEYS.contains(&e.key);
+    if let Some(event) = input.keys.events.iter().find(is_new_press) {
+        return Some(Binding::Key(event.key));
+    }
+    for pad in input.connected_controllers() {
+        if let Some(&button) = ALL_PAD_BUTTONS.iter().find(|&&b| pad.button(b).was_pressed()) {
+            return Some(Binding::Pad(button));
+        }
+    }
+    None
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(input: &mut Input, key: Key) {
+        input.begin_frame();
+        input.key_event(key, true, false);
+    }
+
+    #[test]
+    fn keyboard_pad_comes_first() {
+        let mut input = Input::default();
+        input.pads[KEYBOARD_PAD].is_connected = true;
+        press(&mut input, Key::Space);
+        ActionMap::default().update_keyboard_pad(&mut input);
+        assert!(input.pads[KEYBOARD_PAD].a.was_pressed());
+        assert!(!input.controller(0).a.is_down());
+        assert!(input.keyboard_only());
+
+        input.set_connected(0, true);
+        assert!(input.controller(0).is_connected);
+        assert!(!input.keyboard_only());
+        assert_eq!(input.connected_pads().count(), 2);
+        assert_eq!(input.connected_controllers().count(), 1);
+    }
+
+    #[test]
+    fn rebinding_replaces_keys_only() {
+        let mut map = ActionMap::default();
+        map.rebind(Action::Jump, Binding::Key(Key::K));
+        assert_eq!(map.bindings(Action::Jump), &[Binding::Pad(PadButton::A), Binding::Key(Key::K)]);
+
+        // Space no longer jumps
+        let mut input = Input::default();
+        input.pads[KEYBOARD_PAD].is_connected = true;
+        press(&mut input, Key::Space);
+        assert!(!map.was_pressed(&input, Action::Jump));
+        press(&mut input, Key::K);
+        assert!(map.was_pressed(&input, Action::Jump));
+    }
+
+    #[test]
+    fn keyboard_pad_follows_the_bindings() {
+        let mut map = ActionMap::default();
+        map.rebind(Action::Jump, Binding::Key(Key::K));
+        let mut input = Input::default();
+
+        press(&mut input, Key::Space);
+        map.update_keyboard_pad(&mut input);
+        assert!(!input.pads[KEYBOARD_PAD].a.is_down());
+        press(&mut input, Key::K);
+        map.update_keyboard_pad(&mut input);
+        assert!(input.pads[KEYBOARD_PAD].a.was_pressed());
+
+        // W and Up both move up: letting go of one keeps the button held
+        press(&mut input, Key::W);
+        input.key_event(Key::Up, true, false);
+        input.key_event(Key::W, false, false);
+        map.update_keyboard_pad(&mut input);
+        assert!(input.pads[KEYBOARD_PAD].up.is_down());
+        assert_eq!(input.pads[KEYBOARD_PAD].up.half_transition_count, 1);
+
+        // a tap within one frame is a press and a release
+        press(&mut input, Key::Enter);
+        input.key_event(Key::Enter, false, false);
+        map.update_keyboard_pad(&mut input);
+        assert!(input.pads[KEYBOARD_PAD].start.was_pressed() && input.pads[KEYBOARD_PAD].start.was_released());
+        assert!(!input.pads[KEYBOARD_PAD].start.is_down());
+    }
+
+    #[test]
+    fn captures_keys_and_controller_buttons() {
+        let mut input = Input::default();
+        input.pads[KEYBOARD_PAD].is_connected = true;
+        assert_eq!(capture_binding(&input), None);
+
+        press(&mut input, Key::J);
+        assert_eq!(capture_binding(&input), Some(Binding::Key(Key::J)));
+        // a repeat is not a new press
+        input.begin_frame();
+        input.key_event(Key::J, true, true);
+        assert_eq!(capture_binding(&input), None);
+
+        press(&mut input, Key::F3);
+        assert_eq!(capture_binding(&input), None);
+        input.key_event(Key::L, true, false);
+        assert_eq!(capture_binding(&input), Some(Binding::Key(Key::L)));
+
+        input.begin_frame();
+        input.set_connected(1, true);
+        input.controller_mut(1).y.update(true);
+        assert_eq!(capture_binding(&input), Some(Binding::Pad(PadButton::Y)));
+    }
+
+    #[test]
+    fn config_round_trip() {
+        let mut map = ActionMap::default();
+        map.rebind(Action::Run, Binding::Pad(PadButton::X));
+        map.clear(Action::Back);
+        let back = ActionMap::from_config(&map.to_config()).unwrap();
+        for &action in ALL_ACTIONS {
+            assert_eq!(back.bindings(action), map.bindings(action), "{:?}", action);
+        }
+        assert_eq!(
+            ActionMap::from_config("jump = key:Nope").err(),
+            Some("line 1: unknown key 'Nope'".to_string())
+        );
+    }
+}
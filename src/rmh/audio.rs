@@ -0,0 +1,227 @@
+// Platform independent side of the sound output: buffer format and the
+// bookkeeping that decides where and how much to write into a looping
+// device buffer each frame, given the cursors the device reports. Only
+// DirectSound hands out cursors; ALSA is fed from a ring instead.
+
+#[derive(Clone, Copy, Debug)]
+pub struct SoundParams {
+    pub bits_per_sample: u16,
+    pub n_channels: u16,
+    pub n_samples_per_sec: u16,
+    pub buf_size_seconds: u16,
+}
+
+impl SoundParams {
+    pub fn buf_size_bytes(&self) -> u32 {
+        (self.n_channels as u32 *
+        self.bits_per_sample as u32 *
+        self.n_samples_per_sec as u32 *
+        self.buf_size_seconds as u32) / 8
+    }
+
+    pub fn bytes_per_sample(&self) -> u32 {
+        (self.bits_per_sample * self.n_channels / 8) as u32
+    }
+}
+
+#[cfg(any(target_os="windows", test))]
+pub fn circular_distance(a: u32, b: u32, circle_size: u32) -> i32 {
+
+    let ending_block = circle_size / 100 * 75;
+    let starting_block = circle_size / 100 * 25;
+
+    if a >= ending_block && b <= starting_block {
+        return a as i32 - (b + circle_size) as i32;
+    }
+
+    if b >= ending_block && a <= starting_block {
+        return (a + circle_size) as i32 - b as i32
+    }
+
+    a as i32 - b as i32
+}
+
+// Everything that went into one frame's write, in bytes into the device buffer.
+#[cfg(any(target_os="windows", test))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CursorSnapshot {
+    pub play_cursor: u32,
+    pub write_cursor: u32,
+    pub byte_to_lock: u32,
+    pub bytes_to_write: u32,
+    pub tracker_dist: i32,
+    // where the play cursor should be when the frame being prepared is shown
+    pub expected_flip_byte: u32,
+    // the write cursor is ahead of that flip, so this frame's sound can't
+    // start in sync with its picture
+    pub high_latency: bool,
+}
+
+#[cfg(any(target_os="windows", test))]
+#[derive(Default)]
+pub struct CursorModel {
+    // our own idea of where the next sample goes, in samples (all channels)
+    pub sound_sample_idx: u32,
+    // false until the first plan(), which starts the tracker at the write cursor
+    pub is_valid: bool,
+    // write cursor minus play cursor, smoothed over frames: the least time
+    // between handing a sample to the device and hearing it
+    pub latency_bytes: f32,
+}
+
+#[cfg(any(target_os="windows", test))]
+impl CursorModel {
+    // Called before this frame's flip, seconds_into_frame after the frame
+    // started (Handmade Hero day 20). The flip is expected where the play
+    // cursor will be once the rest of the frame has passed, and the device
+    // buffer is filled up to a frame past it, so sound written now starts
+    // when this frame gets shown. When the card's write cursor is already
+    // past that flip there's no way to hit it, so we write a frame's worth
+    // past the write cursor instead and live with the latency.
+    pub fn plan(
+        &mut self,
+        params: &SoundParams,
+        play_cursor: u32,
+        write_cursor: u32,
+        frame_seconds: f32,
+        seconds_into_frame: f32,
+    ) -> CursorSnapshot {
+        let buf_size = params.buf_size_bytes();
+        let bytes_per_sample = params.bytes_per_sample();
+        let bytes_per_second = params.n_samples_per_sec as u32 * bytes_per_sample;
+        let align = |bytes: u32| bytes / bytes_per_sample * bytes_per_sample;
+
+        let latency = (write_cursor + buf_size - play_cursor) % buf_size;
+        if !self.is_valid {
+            self.sound_sample_idx = write_cursor / bytes_per_sample;
+            self.latency_bytes = latency as f32;
+            self.is_valid = true;
+        }
+        self.latency_bytes = self.latency_bytes * 0.9 + latency as f32 * 0.1;
+
+        let mut byte_to_lock = self.sound_sample_idx * bytes_per_sample % buf_size;
+        let mut tracker_dist = circular_distance(byte_to_lock, write_cursor, buf_size);
+        if tracker_dist < 0 {
+            // we fell behind the write cursor (the game loop stalled); the
+            // region we'd write is already playing, so skip ahead
+            self.sound_sample_idx = write_cursor / bytes_per_sample;
+            byte_to_lock = align(write_cursor);
+            tracker_dist = 0;
+        }
+
+        let bytes_per_frame = align((bytes_per_second as f32 * frame_seconds) as u32);
+        let bytes_until_flip = align((bytes_per_second as f32 * (frame_seconds - seconds_into_frame).max(0.0)) as u32);
+        let flip_byte = play_cursor + bytes_until_flip;
+
+        // how much the write cursor moves around from one reading to the next
+        let safety_bytes = align(bytes_per_frame / 3);
+        let mut safe_write_cursor = write_cursor;
+        if safe_write_cursor < play_cursor {
+            safe_write_cursor += buf_size;
+        }
+        safe_write_cursor += safety_bytes;
+
+        let high_latency = safe_write_cursor >= flip_byte;
+        let target = if high_latency {
+            safe_write_cursor + bytes_per_frame
+        } else {
+            flip_byte + bytes_per_frame
+        } % buf_size;
+
+        // nothing to do if an earlier long write already got past the target
+        let bytes_to_write = match circular_distance(target, byte_to_lock, buf_size) {
+            d if d > 0 => (d as u32).min(buf_size),
+            _ => 0,
+        };
+
+        CursorSnapshot {
+            play_cursor,
+            write_cursor,
+            byte_to_lock,
+            bytes_to_write,
+            tracker_dist,
+            expected_flip_byte: flip_byte % buf_size,
+            high_latency,
+        }
+    }
+
+    pub fn advance(&mut self, params: &SoundParams, bytes_written: u32) {
+        self.sound_sample_idx += bytes_written / params.bytes_per_sample();
+        self.sound_sample_idx %= params.buf_size_bytes() / params.bytes_per_sample();
+    }
+
+    pub fn latency_seconds(&self, params: &SoundParams) -> f32 {
+        let bytes_per_second = params.n_samples_per_sec as u32 * params.bytes_per_sample();
+        self.latency_bytes / bytes_per_second as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4 bytes per sample, 192000 bytes per second; frames of 1/8 s keep the
+    // byte counts exact
+    const PARAMS: SoundParams = SoundParams {
+        bits_per_sample: 16,
+        n_channels: 2,
+        n_samples_per_sec: 48000,
+        buf_size_seconds: 1,
+    };
+    const FRAME_SECONDS: f32 = 0.125;
+    const BYTES_PER_FRAME: u32 = 24000;
+
+    #[test]
+    fn low_latency_writes_through_the_frame_after_the_flip() {
+        let mut model = CursorModel::default();
+        // half way into the frame, the flip is half a frame of sound away
+        let s = model.plan(&PARAMS, 0, 1920, FRAME_SECONDS, 0.0625);
+        assert!(!s.high_latency);
+        assert_eq!(s.expected_flip_byte, 12000);
+        assert_eq!(s.byte_to_lock, 1920);
+        assert_eq!(s.byte_to_lock + s.bytes_to_write, 12000 + BYTES_PER_FRAME);
+        assert!((model.latency_seconds(&PARAMS) - 0.01).abs() < 1e-6);
+    }
+
+    #[test]
+    fn high_latency_writes_a_frame_past_the_write_cursor() {
+        let mut model = CursorModel::default();
+        // the write cursor plus a third of a frame of jitter is past the flip
+        let s = model.plan(&PARAMS, 0, 9600, FRAME_SECONDS, 0.0625);
+        assert!(s.high_latency);
+        assert_eq!(s.byte_to_lock, 9600);
+        assert_eq!(s.byte_to_lock + s.bytes_to_write, 9600 + BYTES_PER_FRAME / 3 + BYTES_PER_FRAME);
+    }
+
+    #[test]
+    fn skips_ahead_when_the_write_cursor_passed_the_tracker() {
+        let mut model = CursorModel::default();
+        let s = model.plan(&PARAMS, 0, 1920, FRAME_SECONDS, 0.0625);
+        model.advance(&PARAMS, s.bytes_to_write);
+
+        // a long stall: the tracker at 36000 is already playing
+        let s = model.plan(&PARAMS, 40000, 41920, FRAME_SECONDS, 0.0625);
+        assert_eq!(s.byte_to_lock, 41920);
+        assert_eq!(s.tracker_dist, 0);
+        assert_eq!(model.sound_sample_idx, 41920 / 4);
+    }
+
+    #[test]
+    fn writes_nothing_when_already_ahead() {
+        let mut model = CursorModel::default();
+        let s = model.plan(&PARAMS, 0, 1920, FRAME_SECONDS, 0.0625);
+        model.advance(&PARAMS, s.bytes_to_write);
+
+        let s = model.plan(&PARAMS, 0, 1920, FRAME_SECONDS, 0.0625);
+        assert_eq!(s.byte_to_lock, 36000);
+        assert_eq!(s.bytes_to_write, 0);
+    }
+
+    #[test]
+    fn tracker_wraps_with_the_buffer() {
+        let mut model = CursorModel::default();
+        model.plan(&PARAMS, 180000, 184000, FRAME_SECONDS, 0.0);
+        model.advance(&PARAMS, 12000);
+        assert_eq!(model.sound_sample_idx * 4, 4000);
+    }
+}
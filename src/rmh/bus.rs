@@ -0,0 +1,178 @@
+// Mixer buses and the user's volume settings for them. Every voice plays on
+// one bus; each bus has its own volume and mute, and everything goes through
+// master at the end. Music is ducked while sound effects or dialogue play so
+// they stay audible.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bus {
+    Master,
+    Music,
+    Sfx,
+    Ui,
+    Voice,
+}
+
+pub const ALL_BUSES: &[Bus] = &[Bus::Master, Bus::Music, Bus::Sfx, Bus::Ui, Bus::Voice];
+pub const BUS_COUNT: usize = 5;
+
+impl Bus {
+    pub fn name(self) -> &'static str {
+        match self {
+            Bus::Master => "master",
+            Bus::Music => "music",
+            Bus::Sfx => "sfx",
+            Bus::Ui => "ui",
+            Bus::Voice => "voice",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Bus> {
+        ALL_BUSES.iter().copied().find(|b| b.name() == name)
+    }
+
+    // Whether sounds on this bus duck the music.
+    pub fn ducks_music(self) -> bool {
+        matches!(self, Bus::Sfx | Bus::Voice)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BusSettings {
+    // 0..1
+    pub volume: f32,
+    pub muted: bool,
+}
+
+impl Default for BusSettings {
+    fn default() -> BusSettings {
+        BusSettings { volume: 1.0, muted: false }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AudioSettings {
+    // indexed by `Bus as usize`
+    pub buses: [BusSettings; BUS_COUNT],
+    // music volume while ducked, 1 turns ducking off
+    pub duck_level: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> AudioSettings {
+        AudioSettings {
+            buses: [BusSettings::default(); BUS_COUNT],
+            duck_level: 0.5,
+        }
+    }
+}
+
+impl AudioSettings {
+    pub fn bus(&self, bus: Bus) -> BusSettings {
+        self.buses[bus as usize]
+    }
+
+    pub fn bus_mut(&mut self, bus: Bus) -> &mut BusSettings {
+        &mut self.buses[bus as usize]
+    }
+
+    // The gain a bus should be at before smoothing and ducking.
+    pub fn target_gain(&self, bus: Bus) -> f32 {
+        let settings = self.bus(bus);
+        if settings.muted { 0.0 } else { settings.volume.clamp(0.0, 1.0) }
+    }
+
+    pub fn to_config(&self) -> String {
+        let mut out = String::new();
+        for &bus in ALL_BUSES {
+            let settings = self.bus(bus);
+            out.push_str(&format!("{} = {}{}\n", bus.name(), settings.volume, if settings.muted { ", muted" } else { "" }));
+        }
+        out.push_str(&format!("duck_level = {}\n", self.duck_level));
+        out
+    }
+
+    // Lines are `<bus> = <volume>[, muted]` or `duck_level = <level>`; what's
+    // missing keeps its default. Blank lines and lines starting with '#' are
+    // skipped.
+    pub fn from_config(config: &str) -> Result<AudioSettings, String> {
+        let mut settings = AudioSettings::default();
+        for (line_no, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |e: String| format!("line {}: {}", line_no + 1, e);
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            let rest = parts.next().ok_or_else(|| err("expected '='".to_string()))?;
+
+            let mut values = rest.split(',').map(str::trim);
+            let value = values.next().unwrap_or("");
+            let number: f32 = value.parse().map_err(|_| err(format!("bad number '{}'", value)))?;
+            if name == "duck_level" {
+                settings.duck_level = number.clamp(0.0, 1.0);
+                continue;
+            }
+            let bus = Bus::from_name(name).ok_or_else(|| err(format!("unknown bus '{}'", name)))?;
+            let bus = settings.bus_mut(bus);
+            bus.volume = number.clamp(0.0, 1.0);
+            bus.muted = false;
+            for flag in values {
+                match flag {
+                    "muted" => bus.muted = true,
+                    _ => return Err(err(format!("unknown flag '{}'", flag))),
+                }
+            }
+        }
+        Ok(settings)
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<AudioSettings, String> {
+        let config = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        AudioSettings::from_config(&config).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &std::path::Path) -> Result<(), String> {
+        std::fs::write(path, self.to_config()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_round_trip() {
+        let mut settings = AudioSettings::default();
+        settings.bus_mut(Bus::Ui).muted = true;
+        settings.bus_mut(Bus::Music).volume = 0.3;
+        settings.duck_level = 0.7;
+        assert_eq!(AudioSettings::from_config(&settings.to_config()), Ok(settings));
+    }
+
+    #[test]
+    fn config_defaults_and_clamping() {
+        let settings = AudioSettings::from_config("# volumes\n\nmusic = 1.5\nsfx = 0.2, muted\n").unwrap();
+        assert_eq!(settings.bus(Bus::Music), BusSettings { volume: 1.0, muted: false });
+        assert_eq!(settings.bus(Bus::Sfx), BusSettings { volume: 0.2, muted: true });
+        assert_eq!(settings.bus(Bus::Master), BusSettings::default());
+        assert_eq!(settings.duck_level, AudioSettings::default().duck_level);
+    }
+
+    #[test]
+    fn config_errors() {
+        assert_eq!(AudioSettings::from_config("music = 0.5, loud"), Err("line 1: unknown flag 'loud'".to_string()));
+        assert_eq!(AudioSettings::from_config("\n# x\nbass = 1"), Err("line 3: unknown bus 'bass'".to_string()));
+        assert_eq!(AudioSettings::from_config("music = half"), Err("line 1: bad number 'half'".to_string()));
+        assert_eq!(AudioSettings::from_config("music"), Err("line 1: expected '='".to_string()));
+    }
+
+    #[test]
+    fn muted_bus_targets_silence() {
+        let mut settings = AudioSettings::default();
+        settings.bus_mut(Bus::Voice).volume = 0.6;
+        assert_eq!(settings.target_gain(Bus::Voice), 0.6);
+        settings.bus_mut(Bus::Voice).muted = true;
+        assert_eq!(settings.target_gain(Bus::Voice), 0.0);
+    }
+}
@@ -0,0 +1,454 @@
+// SDL GameControllerDB mappings (gamecontrollerdb.txt), used to turn the raw
+// buttons, axes and hats of a generic joystick into a Pad. One mapping per
+// line:
+//
+//   <guid>,<name>,a:b0,b:b1,leftx:a0,lefty:a1,dpup:h0.1,...,platform:Linux,
+//
+// Inputs are bN (button), aN (axis, optionally +aN/-aN for one half and aN~
+// for inverted) or hN.M (hat N, direction bitmask M). Outputs may be prefixed
+// with + or - to drive only half of an axis.
+
+use std::collections::HashMap;
+
+use super::actions::PadButton;
+use super::input::{apply_stick_deadzone, apply_trigger_deadzone, Deadzones, Pad, Stick};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Half {
+    Full,
+    Positive,
+    Negative,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    Button(usize),
+    Axis { index: usize, half: Half, inverted: bool },
+    Hat { index: usize, mask: u8 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PadAxis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Button(PadButton),
+    Axis(PadAxis, Half),
+}
+
+#[derive(Clone, Debug)]
+pub struct Mapping {
+    pub guid: String,
+    pub name: String,
+    pub bindings: Vec<(Target, Source)>,
+}
+
+// Raw joystick state as the platform reads it. Axes are normalized to -1..1
+// with y down, the way SDL reports them; hats hold SDL direction bits
+// (1 up, 2 right, 4 down, 8 left).
+#[derive(Clone, Debug, Default)]
+pub struct RawJoystick {
+    pub buttons: Vec<bool>,
+    pub axes: Vec<f32>,
+    pub hats: Vec<u8>,
+}
+
+fn parse_target(name: &str) -> Option<Target> {
+    let (half, name) = match name.as_bytes().first() {
+        Some(b'+') => (Half::Positive, &name[1..]),
+        Some(b'-') => (Half::Negative, &name[1..]),
+        _ => (Half::Full, name),
+    };
+    let axis = |axis| Some(Target::Axis(axis, half));
+    match name {
+        "leftx" => axis(PadAxis::LeftX),
+        "lefty" => axis(PadAxis::LeftY),
+        "rightx" => axis(PadAxis::RightX),
+        "righty" => axis(PadAxis::RightY),
+        "lefttrigger" => axis(PadAxis::LeftTrigger),
+        "righttrigger" => axis(PadAxis::RightTrigger),
+        "a" => Some(Target::Button(PadButton::A)),
+        "b" => Some(Target::Button(PadButton::B)),
+        "x" => Some(Target::Button(PadButton::X)),
+        "y" => Some(Target::Button(PadButton::Y)),
+        "back" => Some(Target::Button(PadButton::Back)),
+        "start" => Some(Target::Button(PadButton::Start)),
+        "leftshoulder" => Some(Target::Button(PadButton::LeftShoulder)),
+        "rightshoulder" => Some(Target::Button(PadButton::RightShoulder)),
+        "leftstick" => Some(Target::Button(PadButton::LeftThumb)),
+        "rightstick" => Some(Target::Button(PadButton::RightThumb)),
+        "dpup" => Some(Target::Button(PadButton::Up)),
+        "dpdown" => Some(Target::Button(PadButton::Down)),
+        "dpleft" => Some(Target::Button(PadButton::Left)),
+        "dpright" => Some(Target::Button(PadButton::Right)),
+        // guide, misc1, paddles, touchpad: nothing in Pad for them
+        _ => None,
+    }
+}
+
+fn parse_source(s: &str) -> Result<Source, String> {
+    let bad = || format!("bad input '{}'", s);
+    let (half, rest) = match s.as_bytes().first() {
+        Some(b'+') => (Half::Positive, &s[1..]),
+        Some(b'-') => (Half::Negative, &s[1..]),
+        _ => (Half::Full, s),
+    };
+    let (inverted, rest) = match rest.strip_suffix('~') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+
+    if let Some(index) = rest.strip_prefix('a') {
+        let index = index.parse().map_err(|_| bad())?;
+        return Ok(Source::Axis { index, half, inverted });
+    }
+    if half != Half::Full || inverted {
+        return Err(bad());
+    }
+    if let Some(index) = rest.strip_prefix('b') {
+        return Ok(Source::Button(index.parse().map_err(|_| bad())?));
+    }
+    if let Some(hat) = rest.strip_prefix('h') {
+        let mut parts = hat.splitn(2, '.');
+        let index = parts.next().unwrap_or("").parse().map_err(|_| bad())?;
+        let mask = parts.next().unwrap_or("").parse().map_err(|_| bad())?;
+        return Ok(Source::Hat { index, mask });
+    }
+    Err(bad())
+}
+
+impl Mapping {
+    // Parses one line, returning the mapping and its platform field if any.
+    pub fn parse(line: &str) -> Result<(Mapping, Option<String>), String> {
+        let mut fields = line.trim().split(',');
+        let guid = fields.next().unwrap_or("").trim();
+        if guid.len() != 32 || !guid.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("bad guid '{}'", guid));
+        }
+        let name = fields.next().ok_or("missing name")?.trim();
+
+        let mut mapping = Mapping {
+            guid: guid.to_ascii_lowercase(),
+            name: name.to_string(),
+            bindings: Vec::new(),
+        };
+        let mut platform = None;
+        for field in fields.map(str::trim).filter(|f| !f.is_empty()) {
+            let mut parts = field.splitn(2, ':');
+            let key = parts.next().unwrap_or("");
+            let value = parts.next().ok_or_else(|| format!("bad field '{}'", field))?;
+            if key == "platform" {
+                platform = Some(value.to_string());
+                continue;
+            }
+            if let Some(target) = parse_target(key) {
+                mapping.bindings.push((target, parse_source(value)?));
+            }
+        }
+        Ok((mapping, platform))
+    }
+
+    // Reads the raw state through the mapping into `pad`. Buttons go through
+    // ButtonState::update so transitions are counted; sticks come out y up.
+    pub fn apply(&self, raw: &RawJoystick, pad: &mut Pad, deadzones: &Deadzones) {
+        // indexed like Pad::buttons_mut and PadButton
+        let mut buttons = [false; 14];
+        // sticks in SDL orientation, triggers 0..1
+        let mut axes = [0.0f32; 6];
+
+        for (target, source) in self.bindings.iter() {
+            let value = source_value(raw, *source);
+            match *target {
+                Target::Button(button) => buttons[button as usize] |= value > 0.5,
+                Target::Axis(axis, half) => {
+                    let is_trigger = matches!(axis, PadAxis::LeftTrigger | PadAxis::RightTrigger);
+                    let full_axis_source = matches!(source, Source::Axis { half: Half::Full, .. });
+                    // a full -1..1 axis driving a 0..1 output rests at -1
+                    let value = if full_axis_source && (half != Half::Full || is_trigger) {
+                        (value + 1.0) * 0.5
+                    } else {
+                        value
+                    };
+                    let value = match half {
+                        Half::Negative => -value,
+                        _ => value,
+                    };
+                    axes[axis as usize] += value;
+                }
+            }
+        }
+
+        for (state, &down) in pad.buttons_mut().iter_mut().zip(buttons.iter()) {
+            state.update(down);
+        }
+
+        let clamp = |v: f32| v.clamp(-1.0, 1.0);
+        pad.left_stick = stick(clamp(axes[PadAxis::LeftX as usize]), clamp(axes[PadAxis::LeftY as usize]), deadzones.left_stick);
+        pad.right_stick = stick(clamp(axes[PadAxis::RightX as usize]), clamp(axes[PadAxis::RightY as usize]), deadzones.right_stick);
+        pad.left_trigger = apply_trigger_deadzone(axes[PadAxis::LeftTrigger as usize].clamp(0.0, 1.0), deadzones.trigger);
+        pad.right_trigger = apply_trigger_deadzone(axes[PadAxis::RightTrigger as usize].clamp(0.0, 1.0), deadzones.trigger);
+    }
+}
+
+fn stick(x: f32, y_down: f32, deadzone: f32) -> Stick {
+    apply_stick_deadzone(x, -y_down, deadzone)
+}
+
+// 0/1 for buttons and hats; -1..1 for a full axis, 0..1 for a half.
+fn source_value(raw: &RawJoystick, source: Source) -> f32 {
+    match source {
+        Source::Button(index) => {
+            if raw.buttons.get(index).copied().unwrap_or(false) { 1.0 } else { 0.0 }
+        }
+        Source::Hat { index, mask } => {
+            if raw.hats.get(index).copied().unwrap_or(0) & mask != 0 { 1.0 } else { 0.0 }
+        }
+        Source::Axis { index, half, inverted } => {
+            let mut v = raw.axes.get(index).copied().unwrap_or(0.0);
+            if inverted {
+                v = -v;
+            }
+            match half {
+                Half::Full => v,
+                Half::Positive => v.max(0.0),
+                Half::Negative => (-v).max(0.0),
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ControllerDb {
+    mappings: HashMap<String, Mapping>,
+}
+
+impl ControllerDb {
+    // Keeps the lines for `platform` (e.g. "Linux") and those without a
+    // platform field. Later lines override earlier ones for the same guid,
+    // so user mappings can be appended to the community file. Lines that
+    // don't parse are skipped and reported in the second value.
+    pub fn parse(text: &str, platform: &str) -> (ControllerDb, Vec<String>) {
+        let mut db = ControllerDb::default();
+        let mut errors = Vec::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match Mapping::parse(line) {
+                Ok((mapping, line_platform)) => {
+                    if line_platform.is_none() || line_platform.as_deref() == Some(platform) {
+                        db.mappings.insert(mapping.guid.clone(), mapping);
+                    }
+                }
+                Err(e) => errors.push(format!("line {}: {}", line_no + 1, e)),
+            }
+        }
+        (db, errors)
+    }
+
+    pub fn load(path: &std::path::Path, platform: &str) -> Result<(ControllerDb, Vec<String>), String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(ControllerDb::parse(&text, platform))
+    }
+
+    pub fn get(&self, guid: &str) -> Option<&Mapping> {
+        self.mappings.get(&guid.to_ascii_lowercase())
+    }
+
+    pub fn len(&self) -> usize {
+        self.mappings.len()
+    }
+}
+
+// The GUID SDL builds for a Linux evdev device: bus type, vendor, product
+// and version as little-endian 16 bit values, each followed by two zero
+// bytes.
+pub fn linux_guid(bustype: u16, vendor: u16, product: u16, version: u16) -> String {
+    let mut guid = String::with_capacity(32);
+    for v in [bustype, vendor, product, version].iter() {
+        guid.push_str(&format!("{:02x}{:02x}0000", v & 0xFF, v >> 8));
+    }
+    guid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rmh::actions::ALL_PAD_BUTTONS;
+
+    // lines as they appear in the community gamecontrollerdb.txt
+    const XBOX360_LINUX: &str = "030000005e0400008e02000014010000,Xbox 360 Controller,a:b0,b:b1,back:b6,dpdown:h0.4,dpleft:h0.8,dpright:h0.2,dpup:h0.1,guide:b8,leftshoulder:b4,leftstick:b9,lefttrigger:a2,leftx:a0,lefty:a1,rightshoulder:b5,rightstick:b10,righttrigger:a5,rightx:a3,righty:a4,start:b7,x:b2,y:b3,platform:Linux,";
+    const XBOX360_WINDOWS: &str = "030000005e0400008e02000000000000,Xbox 360 Controller,a:b0,b:b1,back:b6,dpdown:h0.4,dpleft:h0.8,dpright:h0.2,dpup:h0.1,leftshoulder:b4,leftstick:b8,lefttrigger:+a2,leftx:a0,lefty:a1,rightshoulder:b5,rightstick:b9,righttrigger:-a2,rightx:a3,righty:a4,start:b7,x:b2,y:b3,platform:Windows,";
+    const RETRO_LINUX: &str = "03000000790000001100000010010000,Retro Controller,a:b1,b:b2,back:b8,dpdown:+a1,dpleft:-a0,dpright:+a0,dpup:-a1,leftshoulder:b6,lefttrigger:b7,rightshoulder:b4,righttrigger:b5,start:b9,x:b0,y:b3,platform:Linux,";
+
+    fn no_deadzones() -> Deadzones {
+        Deadzones { left_stick: 0.0, right_stick: 0.0, trigger: 0.0 }
+    }
+
+    fn raw(buttons: usize, axes: &[f32], hats: &[u8]) -> RawJoystick {
+        RawJoystick { buttons: vec![false; buttons], axes: axes.to_vec(), hats: hats.to_vec() }
+    }
+
+    fn applied(line: &str, raw: &RawJoystick) -> Pad {
+        let (mapping, _) = Mapping::parse(line).unwrap();
+        let mut pad = Pad::default();
+        mapping.apply(raw, &mut pad, &no_deadzones());
+        pad
+    }
+
+    #[test]
+    fn parses_xbox360_line() {
+        let (mapping, platform) = Mapping::parse(XBOX360_LINUX).unwrap();
+        assert_eq!(platform.as_deref(), Some("Linux"));
+        assert_eq!(mapping.name, "Xbox 360 Controller");
+        // everything but guide, which Pad has no button for
+        assert_eq!(mapping.bindings.len(), 20);
+        assert!(mapping.bindings.contains(&(Target::Button(PadButton::A), Source::Button(0))));
+        assert!(mapping.bindings.contains(&(Target::Button(PadButton::Up), Source::Hat { index: 0, mask: 1 })));
+        assert!(mapping.bindings.contains(&(
+            Target::Axis(PadAxis::RightTrigger, Half::Full),
+            Source::Axis { index: 5, half: Half::Full, inverted: false }
+        )));
+    }
+
+    #[test]
+    fn parses_sources() {
+        assert_eq!(parse_source("+a1"), Ok(Source::Axis { index: 1, half: Half::Positive, inverted: false }));
+        assert_eq!(parse_source("-a0"), Ok(Source::Axis { index: 0, half: Half::Negative, inverted: false }));
+        assert_eq!(parse_source("a3~"), Ok(Source::Axis { index: 3, half: Half::Full, inverted: true }));
+        assert_eq!(parse_source("h0.4"), Ok(Source::Hat { index: 0, mask: 4 }));
+        assert!(parse_source("+b1").is_err());
+        assert!(parse_source("b1~").is_err());
+        assert!(parse_source("h0").is_err());
+    }
+
+    #[test]
+    fn half_axes_drive_the_dpad() {
+        let pad = applied(RETRO_LINUX, &raw(10, &[-1.0, 1.0], &[]));
+        assert!(pad.left.is_down() && pad.down.is_down());
+        assert!(!pad.right.is_down() && !pad.up.is_down());
+
+        let pad = applied(RETRO_LINUX, &raw(10, &[0.0, -1.0], &[]));
+        assert!(pad.up.is_down());
+        assert!(!pad.left.is_down() && !pad.right.is_down() && !pad.down.is_down());
+    }
+
+    #[test]
+    fn inverted_axis() {
+        let line = "03000000000000000000000000000000,Inverted,leftx:a0,lefty:a1~,";
+        // raw y up becomes down, which is Pad y down
+        let pad = applied(line, &raw(0, &[0.0, -1.0], &[]));
+        assert_eq!(pad.left_stick.y, -1.0);
+    }
+
+    #[test]
+    fn hats_drive_the_dpad() {
+        let pad = applied(XBOX360_LINUX, &raw(11, &[0.0; 6], &[1 | 2]));
+        assert!(pad.up.is_down() && pad.right.is_down());
+        assert!(!pad.down.is_down() && !pad.left.is_down());
+
+        let pad = applied(XBOX360_LINUX, &raw(11, &[0.0; 6], &[4 | 8]));
+        assert!(pad.down.is_down() && pad.left.is_down());
+    }
+
+    #[test]
+    fn stick_y_is_flipped_up() {
+        // SDL reports up as -1
+        let pad = applied(XBOX360_LINUX, &raw(11, &[0.0, -1.0, -1.0, 1.0, 0.0, -1.0], &[0]));
+        assert_eq!(pad.left_stick.y, 1.0);
+        assert_eq!(pad.left_stick.x, 0.0);
+        assert_eq!(pad.right_stick.x, 1.0);
+    }
+
+    #[test]
+    fn full_axis_trigger_rests_at_zero() {
+        let pad = applied(XBOX360_LINUX, &raw(11, &[0.0, 0.0, -1.0, 0.0, 0.0, 1.0], &[0]));
+        assert_eq!(pad.left_trigger, 0.0);
+        assert_eq!(pad.right_trigger, 1.0);
+    }
+
+    #[test]
+    fn half_axis_feeds_trigger() {
+        // DirectInput puts both triggers on one axis
+        let pad = applied(XBOX360_WINDOWS, &raw(10, &[0.0, 0.0, 1.0], &[0]));
+        assert_eq!((pad.left_trigger, pad.right_trigger), (1.0, 0.0));
+        let pad = applied(XBOX360_WINDOWS, &raw(10, &[0.0, 0.0, -0.5], &[0]));
+        assert_eq!((pad.left_trigger, pad.right_trigger), (0.0, 0.5));
+    }
+
+    #[test]
+    fn buttons_count_transitions() {
+        let (mapping, _) = Mapping::parse(XBOX360_LINUX).unwrap();
+        let mut pad = Pad::default();
+        let mut raw = raw(11, &[0.0; 6], &[0]);
+        raw.buttons[0] = true;
+        mapping.apply(&raw, &mut pad, &no_deadzones());
+        assert!(pad.a.was_pressed());
+        raw.buttons[0] = false;
+        mapping.apply(&raw, &mut pad, &no_deadzones());
+        assert!(pad.a.was_released());
+        assert!(!pad.b.is_down());
+    }
+
+    #[test]
+    fn pad_button_matches_buttons_mut() {
+        for &button in ALL_PAD_BUTTONS {
+            let mut pad = Pad::default();
+            pad.buttons_mut()[button as usize].ended_down = true;
+            for &other in ALL_PAD_BUTTONS {
+                assert_eq!(pad.button(other).is_down(), other == button, "{:?} vs {:?}", button, other);
+            }
+        }
+    }
+
+    #[test]
+    fn filters_by_platform() {
+        let text = [
+            XBOX360_LINUX,
+            XBOX360_WINDOWS,
+            "03000000c82d00000090000000000000,8BitDo FC30 Pro,a:b1,b:b0,x:b4,y:b3,",
+        ]
+        .join("\n");
+        let (db, errors) = ControllerDb::parse(&text, "Linux");
+        assert!(errors.is_empty());
+        assert_eq!(db.len(), 2);
+        assert!(db.get("030000005e0400008e02000014010000").is_some());
+        assert!(db.get("030000005e0400008e02000000000000").is_none());
+        assert!(db.get("03000000c82d00000090000000000000").is_some());
+    }
+
+    #[test]
+    fn later_lines_override() {
+        let user_line = "030000005E0400008E02000014010000,My Pad,a:b1,b:b0,platform:Linux,";
+        let text = format!("{}\n{}\n", XBOX360_LINUX, user_line);
+        let (db, _) = ControllerDb::parse(&text, "Linux");
+        assert_eq!(db.len(), 1);
+        let mapping = db.get("030000005E0400008E02000014010000").unwrap();
+        assert_eq!(mapping.name, "My Pad");
+        assert!(mapping.bindings.contains(&(Target::Button(PadButton::A), Source::Button(1))));
+    }
+
+    #[test]
+    fn reports_bad_guids() {
+        let text = format!(
+            "# comment\nxinput,XInput Controller,a:b0,b:b1,platform:Windows,\n{}\n030000005e04,Short,a:b0,\n",
+            XBOX360_LINUX
+        );
+        let (db, errors) = ControllerDb::parse(&text, "Linux");
+        assert_eq!(db.len(), 1);
+        assert_eq!(errors, vec!["line 2: bad guid 'xinput'".to_string(), "line 4: bad guid '030000005e04'".to_string()]);
+    }
+
+    #[test]
+    fn linux_guid_matches_sdl() {
+        assert_eq!(linux_guid(0x03, 0x045e, 0x028e, 0x0114), "030000005e0400008e02000014010000");
+    }
+}
@@ -0,0 +1,628 @@
+// Linux evdev gamepads, minus the part that talks to the kernel: turning
+// EV_KEY/EV_ABS events into a Pad, and a recorded device that replays a
+// text file of events instead of reading /dev/input.
+//
+// When the kernel's event buffer overflows it sends SYN_DROPPED. Events up
+// to the next SYN_REPORT are then incomplete, so they're skipped and the
+// pad is resynced from the device's current state instead.
+//
+// Raw buttons, axes and hats are numbered the way SDL numbers them on Linux,
+// so GameControllerDB mappings apply directly. Without a mapping the kernel's
+// standard gamepad layout (BTN_SOUTH is A, ABS_X/ABS_Y the left stick, ...)
+// is used.
+
+use std::collections::VecDeque;
+
+use super::controller_db::{linux_guid, Half, Mapping, PadAxis, RawJoystick, Source, Target};
+use super::actions::PadButton;
+use super::input::{Deadzones, Pad};
+
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_ABS: u16 = 0x03;
+
+pub const SYN_REPORT: u16 = 0;
+pub const SYN_DROPPED: u16 = 3;
+
+pub const BTN_MISC: u16 = 0x100;
+pub const BTN_JOYSTICK: u16 = 0x120;
+pub const BTN_SOUTH: u16 = 0x130;
+pub const BTN_EAST: u16 = 0x131;
+pub const BTN_NORTH: u16 = 0x133;
+pub const BTN_WEST: u16 = 0x134;
+pub const BTN_TL: u16 = 0x136;
+pub const BTN_TR: u16 = 0x137;
+pub const BTN_SELECT: u16 = 0x13a;
+pub const BTN_START: u16 = 0x13b;
+pub const BTN_THUMBL: u16 = 0x13d;
+pub const BTN_THUMBR: u16 = 0x13e;
+// BTN_TOOL_PEN, BTN_TOUCH, ...: tablets and touchpads
+pub const BTN_DIGI: u16 = 0x140;
+pub const BTN_DIGI_END: u16 = 0x150;
+pub const BTN_DPAD_UP: u16 = 0x220;
+pub const BTN_DPAD_DOWN: u16 = 0x221;
+pub const BTN_DPAD_LEFT: u16 = 0x222;
+pub const BTN_DPAD_RIGHT: u16 = 0x223;
+pub const KEY_MAX: u16 = 0x2ff;
+
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+pub const ABS_Z: u16 = 0x02;
+pub const ABS_RX: u16 = 0x03;
+pub const ABS_RY: u16 = 0x04;
+pub const ABS_RZ: u16 = 0x05;
+pub const ABS_HAT0X: u16 = 0x10;
+pub const ABS_HAT3Y: u16 = 0x17;
+pub const ABS_MISC: u16 = 0x28;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputEvent {
+    pub type_: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+// The kernel's calibration for an absolute axis. Values within `flat` of the
+// center read as centered.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AbsInfo {
+    // position when the device was opened
+    pub value: i32,
+    pub minimum: i32,
+    pub maximum: i32,
+    pub flat: i32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub bustype: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+    // supported EV_KEY codes
+    pub keys: Vec<u16>,
+    // supported EV_ABS codes with their ranges
+    pub abs: Vec<(u16, AbsInfo)>,
+}
+
+impl DeviceInfo {
+    pub fn guid(&self) -> String {
+        linux_guid(self.bustype, self.vendor, self.product, self.version)
+    }
+
+    // What SDL counts as a joystick: anything with joystick or gamepad
+    // buttons, or with both an X and a Y axis. Touchpads and tablets also
+    // report X and Y, so axis-only devices mustn't have any digitizer tools.
+    pub fn is_joystick(&self) -> bool {
+        let has_abs = |code| self.abs.iter().any(|(c, _)| *c == code);
+        let has_key_in = |codes: std::ops::Range<u16>| self.keys.iter().any(|k| codes.contains(k));
+        has_key_in(BTN_JOYSTICK..BTN_DIGI)
+            || (has_abs(ABS_X) && has_abs(ABS_Y) && !has_key_in(BTN_DIGI..BTN_DIGI_END))
+    }
+}
+
+// What a device is reporting right now, as opposed to the events that led
+// there.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeviceState {
+    // EV_KEY codes held down
+    pub keys_down: Vec<u16>,
+    // current value of each EV_ABS code
+    pub abs: Vec<(u16, i32)>,
+}
+
+impl DeviceState {
+    fn apply(&mut self, event: InputEvent) {
+        match event.type_ {
+            EV_KEY => {
+                self.keys_down.retain(|&k| k != event.code);
+                if event.value != 0 {
+                    self.keys_down.push(event.code);
+                }
+            }
+            EV_ABS => match self.abs.iter_mut().find(|(code, _)| *code == event.code) {
+                Some(abs) => abs.1 = event.value,
+                None => self.abs.push((event.code, event.value)),
+            },
+            _ => {}
+        }
+    }
+}
+
+// Where a pad's events come from: a real device or a recording.
+pub trait EventSource {
+    fn info(&self) -> &DeviceInfo;
+    // The next queued event, Ok(None) when there is nothing more for now and
+    // an error once the device is gone.
+    fn next_event(&mut self) -> Result<Option<InputEvent>, String>;
+    // Read back after SYN_DROPPED.
+    fn state(&mut self) -> Result<DeviceState, String>;
+}
+
+// A device described by a text file, for running the input code without
+// hardware. Lines are
+//
+//   name <device name>
+//   id <bustype> <vendor> <product> <version>
+//   key <code>
+//   abs <code> <min> <max> <flat> <initial value>
+//   <type> <code> <value>
+//   drop <type> <code> <value>
+//   frame
+//
+// with numbers in decimal or 0x hex, and '#' starting a comment. A drop line
+// is an event that happened on the device but was lost in an overflow: it
+// changes what state() reports without being delivered. A frame line ends
+// what one poll sees, like a real device with nothing more queued.
+pub struct RecordedDevice {
+    info: DeviceInfo,
+    events: VecDeque<Recorded>,
+    // the device as of the events read so far
+    state: DeviceState,
+}
+
+enum Recorded {
+    Event(InputEvent),
+    Dropped(InputEvent),
+    Frame,
+}
+
+fn parse_number(s: &str) -> Result<i64, String> {
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("bad number '{}'", s))
+}
+
+impl RecordedDevice {
+    pub fn parse(text: &str) -> Result<RecordedDevice, String> {
+        let mut info = DeviceInfo::default();
+        let mut events = VecDeque::new();
+
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = |e: String| format!("line {}: {}", line_no + 1, e);
+            let mut words = line.split_whitespace();
+            let first = words.next().unwrap_or("");
+            if first == "name" {
+                info.name = line["name".len()..].trim().to_string();
+                continue;
+            }
+            if first == "frame" {
+                events.push_back(Recorded::Frame);
+                continue;
+            }
+
+            let numbers: Vec<i64> = words.map(parse_number).collect::<Result<_, _>>().map_err(err)?;
+            let expect = |n: usize| {
+                if numbers.len() == n {
+                    Ok(())
+                } else {
+                    Err(err(format!("expected {} numbers after '{}'", n, first)))
+                }
+            };
+            match first {
+                "id" => {
+                    expect(4)?;
+                    info.bustype = numbers[0] as u16;
+                    info.vendor = numbers[1] as u16;
+                    info.product = numbers[2] as u16;
+                    info.version = numbers[3] as u16;
+                }
+                "key" => {
+                    expect(1)?;
+                    info.keys.push(numbers[0] as u16);
+                }
+                "abs" => {
+                    expect(5)?;
+                    let abs = AbsInfo {
+                        value: numbers[4] as i32,
+                        minimum: numbers[1] as i32,
+                        maximum: numbers[2] as i32,
+                        flat: numbers[3] as i32,
+                    };
+                    info.abs.push((numbers[0] as u16, abs));
+                }
+                "drop" => {
+                    expect(3)?;
+                    events.push_back(Recorded::Dropped(InputEvent {
+                        type_: numbers[0] as u16,
+                        code: numbers[1] as u16,
+                        value: numbers[2] as i32,
+                    }));
+                }
+                _ => {
+                    expect(2)?;
+                    events.push_back(Recorded::Event(InputEvent {
+                        type_: parse_number(first).map_err(err)? as u16,
+                        code: numbers[0] as u16,
+                        value: numbers[1] as i32,
+                    }));
+                }
+            }
+        }
+        let state = DeviceState {
+            keys_down: Vec::new(),
+            abs: info.abs.iter().map(|(code, abs)| (*code, abs.value)).collect(),
+        };
+        Ok(RecordedDevice { info, events, state })
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<RecordedDevice, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        RecordedDevice::parse(&text)
+    }
+}
+
+impl EventSource for RecordedDevice {
+    fn info(&self) -> &DeviceInfo {
+        &self.info
+    }
+
+    fn next_event(&mut self) -> Result<Option<InputEvent>, String> {
+        while let Some(recorded) = self.events.pop_front() {
+            match recorded {
+                Recorded::Event(event) => {
+                    self.state.apply(event);
+                    return Ok(Some(event));
+                }
+                Recorded::Dropped(event) => self.state.apply(event),
+                Recorded::Frame => break,
+            }
+        }
+        Ok(None)
+    }
+
+    fn state(&mut self) -> Result<DeviceState, String> {
+        Ok(self.state.clone())
+    }
+}
+
+// Raw state of one device, kept between frames since evdev only reports
+// changes.
+pub struct EvdevPad {
+    mapping: Mapping,
+    // SDL raw index -> evdev code
+    button_codes: Vec<u16>,
+    axis_codes: Vec<(u16, AbsInfo)>,
+    // ABS_HATnX code of each raw hat
+    hat_codes: Vec<u16>,
+    raw: RawJoystick,
+    // hat axes, -1/0/1 per x and y
+    hat_axes: Vec<(i32, i32)>,
+    // events since SYN_DROPPED are incomplete; skip them until the next report
+    dropped: bool,
+    // that report came, read the device state back before applying
+    needs_sync: bool,
+}
+
+impl EvdevPad {
+    // `mapping` is the GameControllerDB entry for the device's guid, if any.
+    pub fn new(info: &DeviceInfo, mapping: Option<Mapping>) -> EvdevPad {
+        let mut keys = info.keys.clone();
+        keys.sort_unstable();
+        keys.dedup();
+        // SDL numbers joystick/gamepad buttons first, then the misc range
+        let mut button_codes: Vec<u16> = keys.iter().copied().filter(|&k| (BTN_JOYSTICK..=KEY_MAX).contains(&k)).collect();
+        button_codes.extend(keys.iter().copied().filter(|&k| (BTN_MISC..BTN_JOYSTICK).contains(&k)));
+
+        let mut abs = info.abs.clone();
+        abs.sort_unstable_by_key(|(code, _)| *code);
+        let axis_codes: Vec<(u16, AbsInfo)> = abs
+            .iter()
+            .copied()
+            .filter(|(code, _)| *code < ABS_MISC && !(ABS_HAT0X..=ABS_HAT3Y).contains(code))
+            .collect();
+        let hat_codes: Vec<u16> = (ABS_HAT0X..=ABS_HAT3Y)
+            .step_by(2)
+            .filter(|&x| abs.iter().any(|(code, _)| *code == x || *code == x + 1))
+            .collect();
+
+        let mut pad = EvdevPad {
+            mapping: Mapping {
+                guid: info.guid(),
+                name: info.name.clone(),
+                bindings: Vec::new(),
+            },
+            raw: RawJoystick {
+                buttons: vec![false; button_codes.len()],
+                axes: vec![0.0; axis_codes.len()],
+                hats: vec![0; hat_codes.len()],
+            },
+            hat_axes: vec![(0, 0); hat_codes.len()],
+            button_codes,
+            axis_codes,
+            hat_codes,
+            dropped: false,
+            needs_sync: false,
+        };
+        pad.mapping = mapping.unwrap_or_else(|| pad.default_mapping(info));
+        // evdev only sends changes, so start from where the axes were
+        for (i, (_, abs)) in pad.axis_codes.iter().enumerate() {
+            pad.raw.axes[i] = normalize_axis(abs, abs.value);
+        }
+        pad
+    }
+
+    pub fn mapping(&self) -> &Mapping {
+        &self.mapping
+    }
+
+    // The kernel's standard gamepad layout, for devices missing from the db.
+    fn default_mapping(&self, info: &DeviceInfo) -> Mapping {
+        let mut bindings = Vec::new();
+        let buttons = [
+            (BTN_SOUTH, PadButton::A),
+            (BTN_EAST, PadButton::B),
+            (BTN_WEST, PadButton::X),
+            (BTN_NORTH, PadButton::Y),
+            (BTN_TL, PadButton::LeftShoulder),
+            (BTN_TR, PadButton::RightShoulder),
+            (BTN_SELECT, PadButton::Back),
+            (BTN_START, PadButton::Start),
+            (BTN_THUMBL, PadButton::LeftThumb),
+            (BTN_THUMBR, PadButton::RightThumb),
+            (BTN_DPAD_UP, PadButton::Up),
+            (BTN_DPAD_DOWN, PadButton::Down),
+            (BTN_DPAD_LEFT, PadButton::Left),
+            (BTN_DPAD_RIGHT, PadButton::Right),
+        ];
+        for (code, button) in buttons.iter() {
+            if let Some(index) = self.button_codes.iter().position(|c| c == code) {
+                bindings.push((Target::Button(*button), Source::Button(index)));
+            }
+        }
+
+        let axes = [
+            (ABS_X, PadAxis::LeftX),
+            (ABS_Y, PadAxis::LeftY),
+            (ABS_RX, PadAxis::RightX),
+            (ABS_RY, PadAxis::RightY),
+            (ABS_Z, PadAxis::LeftTrigger),
+            (ABS_RZ, PadAxis::RightTrigger),
+        ];
+        for (code, axis) in axes.iter() {
+            if let Some(index) = self.axis_codes.iter().position(|(c, _)| c == code) {
+                let source = Source::Axis { index, half: Half::Full, inverted: false };
+                bindings.push((Target::Axis(*axis, Half::Full), source));
+            }
+        }
+
+        if self.hat_codes.first() == Some(&ABS_HAT0X) {
+            let hat = [(1, PadButton::Up), (2, PadButton::Right), (4, PadButton::Down), (8, PadButton::Left)];
+            for (mask, button) in hat.iter() {
+                bindings.push((Target::Button(*button), Source::Hat { index: 0, mask: *mask }));
+            }
+        }
+
+        Mapping {
+            guid: info.guid(),
+            name: info.name.clone(),
+            bindings,
+        }
+    }
+
+    // Applies one event. Returns true on SYN_REPORT, when the pad should be
+    // refreshed with apply(). After an overflow that report sets needs_sync
+    // instead.
+    pub fn handle(&mut self, event: InputEvent) -> bool {
+        match (event.type_, event.code) {
+            (EV_SYN, SYN_REPORT) => {
+                if self.dropped {
+                    self.dropped = false;
+                    self.needs_sync = true;
+                    return false;
+                }
+                return true;
+            }
+            (EV_SYN, SYN_DROPPED) => self.dropped = true,
+            _ if self.dropped => {}
+            (EV_KEY, code) => self.set_key(code, event.value != 0),
+            (EV_ABS, code) => self.set_abs(code, event.value),
+            _ => {}
+        }
+        false
+    }
+
+    // Replaces the raw state with what the device reports, releasing any
+    // button whose up was lost.
+    pub fn sync(&mut self, state: &DeviceState) {
+        for i in 0..self.button_codes.len() {
+            let code = self.button_codes[i];
+            self.set_key(code, state.keys_down.contains(&code));
+        }
+        for &(code, value) in state.abs.iter() {
+            self.set_abs(code, value);
+        }
+        self.needs_sync = false;
+    }
+
+    fn set_key(&mut self, code: u16, down: bool) {
+        if let Some(i) = self.button_codes.iter().position(|&c| c == code) {
+            self.raw.buttons[i] = down;
+        }
+    }
+
+    fn set_abs(&mut self, code: u16, value: i32) {
+        if (ABS_HAT0X..=ABS_HAT3Y).contains(&code) {
+            let x_code = code - (code - ABS_HAT0X) % 2;
+            if let Some(i) = self.hat_codes.iter().position(|&c| c == x_code) {
+                let value = value.signum();
+                if code == x_code {
+                    self.hat_axes[i].0 = value;
+                } else {
+                    self.hat_axes[i].1 = value;
+                }
+                let (x, y) = self.hat_axes[i];
+                // SDL hat bits: 1 up, 2 right, 4 down, 8 left
+                self.raw.hats[i] = match y { -1 => 1, 1 => 4, _ => 0 } | match x { 1 => 2, -1 => 8, _ => 0 };
+            }
+        } else if let Some(i) = self.axis_codes.iter().position(|(c, _)| *c == code) {
+            self.raw.axes[i] = normalize_axis(&self.axis_codes[i].1, value);
+        }
+    }
+
+    pub fn apply(&self, pad: &mut Pad, deadzones: &Deadzones) {
+        self.mapping.apply(&self.raw, pad, deadzones);
+    }
+
+    // Drains everything the source has queued into `pad`. Returns an error
+    // when the device went away.
+    pub fn poll(&mut self, source: &mut dyn EventSource, pad: &mut Pad, deadzones: &Deadzones) -> Result<(), String> {
+        while let Some(event) = source.next_event()? {
+            if self.handle(event) {
+                self.apply(pad, deadzones);
+            } else if self.needs_sync {
+                self.sync(&source.state()?);
+                self.apply(pad, deadzones);
+            }
+        }
+        Ok(())
+    }
+}
+
+// -1..1 across the reported range, with the flat zone around the center
+// snapped to 0.
+fn normalize_axis(abs: &AbsInfo, value: i32) -> f32 {
+    let range = abs.maximum as i64 - abs.minimum as i64;
+    if range <= 0 {
+        return 0.0;
+    }
+    let center = (abs.minimum as i64 + abs.maximum as i64) as f32 * 0.5;
+    let value = value as f32;
+    if (value - center).abs() <= abs.flat as f32 {
+        return 0.0;
+    }
+    ((value - abs.minimum as f32) / range as f32 * 2.0 - 1.0).clamp(-1.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> RecordedDevice {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata").join(name);
+        RecordedDevice::load(&path).unwrap()
+    }
+
+    // One platform frame: transitions restart, then everything queued is
+    // drained.
+    fn poll_frame(evdev: &mut EvdevPad, device: &mut RecordedDevice, pad: &mut Pad) {
+        for button in pad.buttons_mut().iter_mut() {
+            button.half_transition_count = 0;
+        }
+        evdev.poll(device, pad, &Deadzones::default()).unwrap();
+    }
+
+    #[test]
+    fn parses_recorded_info() {
+        let device = fixture("evdev_xbox360.txt");
+        let info = device.info();
+        assert_eq!(info.name, "Microsoft X-Box 360 pad");
+        assert_eq!((info.bustype, info.vendor, info.product, info.version), (0x03, 0x045e, 0x028e, 0x0114));
+        assert_eq!(info.keys.len(), 11);
+        assert_eq!(info.abs.len(), 8);
+        assert!(info.is_joystick());
+        assert_eq!(info.guid(), "030000005e0400008e02000014010000");
+    }
+
+    #[test]
+    fn touchpads_are_not_joysticks() {
+        let touchpad = RecordedDevice::parse(
+            "name SynPS/2 Synaptics TouchPad\n\
+             key 0x110 # BTN_LEFT\n\
+             key 0x145 # BTN_TOOL_FINGER\n\
+             key 0x14a # BTN_TOUCH\n\
+             key 0x14d # BTN_TOOL_DOUBLETAP\n\
+             abs 0x00 1266 5676 0 0 # ABS_X\n\
+             abs 0x01 1096 4758 0 0 # ABS_Y\n\
+             abs 0x18 0 255 0 0 # ABS_PRESSURE\n",
+        ).unwrap();
+        assert!(!touchpad.info().is_joystick());
+
+        // the same axes without the digitizer tools are a joystick
+        let stick = RecordedDevice::parse("abs 0x00 0 255 0 0\nabs 0x01 0 255 0 0\n").unwrap();
+        assert!(stick.info().is_joystick());
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        let err = RecordedDevice::parse("name pad\nabs 0x00 -1 1\n").err().unwrap();
+        assert!(err.starts_with("line 2:"), "{}", err);
+        let err = RecordedDevice::parse("1 0x130 zz\n").err().unwrap();
+        assert!(err.starts_with("line 1:"), "{}", err);
+    }
+
+    #[test]
+    fn replays_xbox360_recording() {
+        let mut device = fixture("evdev_xbox360.txt");
+        let mut evdev = EvdevPad::new(device.info(), None);
+        let mut pad = Pad::default();
+
+        poll_frame(&mut evdev, &mut device, &mut pad);
+        assert!(pad.a.was_pressed());
+        assert!(!pad.b.is_down());
+
+        poll_frame(&mut evdev, &mut device, &mut pad);
+        assert!(pad.a.is_down() && !pad.a.was_pressed());
+        // stick y is up in the Pad, down in evdev
+        let diagonal = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((pad.left_stick.x - diagonal).abs() < 1e-3, "{:?}", pad.left_stick.x);
+        assert!((pad.left_stick.y - diagonal).abs() < 1e-3, "{:?}", pad.left_stick.y);
+
+        poll_frame(&mut evdev, &mut device, &mut pad);
+        assert!(pad.up.was_pressed());
+        assert_eq!(pad.right_trigger, 1.0);
+        assert_eq!(pad.left_trigger, 0.0);
+    }
+
+    #[test]
+    fn resyncs_after_syn_dropped() {
+        let mut device = fixture("evdev_xbox360.txt");
+        let mut evdev = EvdevPad::new(device.info(), None);
+        let mut pad = Pad::default();
+        for _ in 0..3 {
+            poll_frame(&mut evdev, &mut device, &mut pad);
+        }
+        assert!(pad.a.is_down() && pad.up.is_down());
+
+        // the ups for A and the d-pad were lost; the state read back after
+        // the report releases them, and picks up B from the skipped events
+        poll_frame(&mut evdev, &mut device, &mut pad);
+        assert!(!evdev.needs_sync);
+        assert!(pad.a.was_released());
+        assert!(pad.up.was_released());
+        assert!(pad.b.was_pressed());
+        assert_eq!((pad.left_stick.x, pad.left_stick.y), (0.0, 0.0));
+        assert_eq!(pad.right_trigger, 1.0);
+
+        poll_frame(&mut evdev, &mut device, &mut pad);
+        assert!(pad.b.was_released());
+        assert_eq!(device.next_event().unwrap(), None);
+    }
+
+    #[test]
+    fn skips_events_until_report_after_drop() {
+        let info = fixture("evdev_xbox360.txt").info().clone();
+        let mut evdev = EvdevPad::new(&info, None);
+        let key = |code, value| InputEvent { type_: EV_KEY, code, value };
+        let syn = |code| InputEvent { type_: EV_SYN, code, value: 0 };
+
+        assert!(!evdev.handle(syn(SYN_DROPPED)));
+        assert!(!evdev.handle(key(BTN_SOUTH, 1)));
+        assert!(!evdev.handle(syn(SYN_REPORT)));
+        assert!(evdev.needs_sync);
+        evdev.sync(&DeviceState { keys_down: vec![BTN_EAST], abs: Vec::new() });
+        assert!(!evdev.needs_sync);
+
+        let mut pad = Pad::default();
+        evdev.apply(&mut pad, &Deadzones::default());
+        assert!(!pad.a.is_down());
+        assert!(pad.b.is_down());
+    }
+}
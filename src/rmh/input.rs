@@ -0,0 +1,444 @@
+use super::actions::PadButton;
+use super::keyboard::Keyboard;
+#[cfg(any(target_os="windows", test))]
+use super::keyboard::{Key, ALL_KEYS};
+use super::ring::AudioStats;
+
+pub const MAX_CONTROLLERS: usize = 4;
+// index of the pad the platform fills from the keyboard
+pub const KEYBOARD_PAD: usize = 0;
+
+// -1..1 on both axes, y up
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stick {
+    pub x: f32,
+    pub y: f32,
+}
+
+// ended_down is the state at the end of the frame; half_transition_count
+// counts every down or up change during it, so a press and release between
+// two frames still shows up.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ButtonState {
+    pub ended_down: bool,
+    pub half_transition_count: u32,
+}
+
+impl ButtonState {
+    pub fn is_down(&self) -> bool {
+        self.ended_down
+    }
+
+    pub fn was_pressed(&self) -> bool {
+        self.half_transition_count > 1 || (self.half_transition_count == 1 && self.ended_down)
+    }
+
+    #[cfg(test)]
+    pub fn was_released(&self) -> bool {
+        self.half_transition_count > 1 || (self.half_transition_count == 1 && !self.ended_down)
+    }
+
+    pub fn update(&mut self, is_down: bool) {
+        if self.ended_down != is_down {
+            self.ended_down = is_down;
+            self.half_transition_count += 1;
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Pad {
+    pub is_connected: bool,
+    pub up: ButtonState,
+    pub down: ButtonState,
+    pub left: ButtonState,
+    pub right: ButtonState,
+    pub a: ButtonState,
+    pub b: ButtonState,
+    pub x: ButtonState,
+    pub y: ButtonState,
+    pub left_shoulder: ButtonState,
+    pub right_shoulder: ButtonState,
+    pub left_thumb: ButtonState,
+    pub right_thumb: ButtonState,
+    pub start: ButtonState,
+    pub back: ButtonState,
+    pub left_stick: Stick,
+    pub right_stick: Stick,
+    // 0..1
+    pub left_trigger: f32,
+    pub right_trigger: f32,
+}
+
+// Keyboard first, then one pad per controller slot. The keyboard pad is
+// derived from `keys` through the bindings; `keys` also carries the raw key
+// events of the frame. Game code usually reads both through an ActionMap.
+#[derive(Clone, Debug, Default)]
+pub struct Input {
+    // seconds since the game started, at the start of this frame
+    pub time: f64,
+    // length of the previous frame in seconds
+    pub dt: f32,
+    // how long after being mixed a sound is heard, as the platform last
+    // measured it; 0 when unknown
+    pub audio_latency: f32,
+    // totals since the sound output started, on platforms that feed it
+    // through a sample ring; all zero elsewhere
+    pub audio_stats: AudioStats,
+    pub pads: [Pad; MAX_CONTROLLERS + 1],
+    // controllers plugged in or pulled out this frame
+    pub pad_events: Vec<PadEvent>,
+    pub keys: Keyboard,
+    pub mouse: Mouse,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PadEvent {
+    Connected(usize),
+    Disconnected(usize),
+}
+
+// Position is in backbuffer pixels, row 0 at the top, and may lie outside
+// the backbuffer while a button is held and the cursor leaves the window.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Mouse {
+    pub x: i32,
+    pub y: i32,
+    pub left: ButtonState,
+    pub middle: ButtonState,
+    pub right: ButtonState,
+    pub x1: ButtonState,
+    pub x2: ButtonState,
+    // notches scrolled this frame, positive away from the user
+    pub wheel: f32,
+}
+
+impl Mouse {
+    pub fn buttons_mut(&mut self) -> [&mut ButtonState; 5] {
+        [
+            &mut self.left,
+            &mut self.middle,
+            &mut self.right,
+            &mut self.x1,
+            &mut self.x2,
+        ]
+    }
+}
+
+// Maps a point in the window's client area to the backbuffer stretched over it.
+// Both have row 0 at the top, so y scales without flipping.
+#[cfg(any(target_os="windows", test))]
+pub fn window_to_backbuffer(
+    x: i32,
+    y: i32,
+    window_width: i32,
+    window_height: i32,
+    buffer_width: i32,
+    buffer_height: i32,
+) -> (i32, i32) {
+    if window_width <= 0 || window_height <= 0 {
+        return (x, y);
+    }
+    (
+        ((x as i64 * buffer_width as i64).div_euclid(window_width as i64)) as i32,
+        ((y as i64 * buffer_height as i64).div_euclid(window_height as i64)) as i32,
+    )
+}
+
+impl Input {
+    pub fn controller(&self, slot: usize) -> &Pad {
+        &self.pads[KEYBOARD_PAD + 1 + slot]
+    }
+
+    pub fn controller_mut(&mut self, slot: usize) -> &mut Pad {
+        &mut self.pads[KEYBOARD_PAD + 1 + slot]
+    }
+
+    pub fn connected_pads(&self) -> impl Iterator<Item = &Pad> {
+        self.pads.iter().filter(|p| p.is_connected)
+    }
+
+    // Like connected_pads() without the keyboard pad.
+    pub fn connected_controllers(&self) -> impl Iterator<Item = &Pad> {
+        self.pads[KEYBOARD_PAD + 1..].iter().filter(|p| p.is_connected)
+    }
+
+    // With no controller the keyboard is the only thing to read from, e.g.
+    // for which button prompts to show.
+    pub fn keyboard_only(&self) -> bool {
+        self.connected_controllers().next().is_none()
+    }
+
+    // Records a change of connection state. A pad that goes away releases
+    // everything it was holding, so the game sees the ups instead of stuck
+    // buttons.
+    pub fn set_connected(&mut self, slot: usize, connected: bool) {
+        let pad = self.controller_mut(slot);
+        if pad.is_connected == connected {
+            return;
+        }
+        pad.is_connected = connected;
+        if connected {
+            self.pad_events.push(PadEvent::Connected(slot));
+        } else {
+            for button in pad.buttons_mut().iter_mut() {
+                button.update(false);
+            }
+            pad.left_stick = Stick::default();
+            pad.right_stick = Stick::default();
+            pad.left_trigger = 0.0;
+            pad.right_trigger = 0.0;
+            self.pad_events.push(PadEvent::Disconnected(slot));
+        }
+    }
+
+    // Called by the platform before gathering a new frame of input: held
+    // state carries over, transitions start counting from zero.
+    pub fn begin_frame(&mut self) {
+        for pad in self.pads.iter_mut() {
+            for button in pad.buttons_mut().iter_mut() {
+                button.half_transition_count = 0;
+            }
+        }
+        self.pad_events.clear();
+        self.keys.begin_frame();
+        for button in self.mouse.buttons_mut().iter_mut() {
+            button.half_transition_count = 0;
+        }
+        self.mouse.wheel = 0.0;
+    }
+
+    // Feeds one key down or up from the platform's event stream. The
+    // keyboard pad is filled from the keys afterwards, by
+    // ActionMap::update_keyboard_pad.
+    #[cfg(any(target_os="windows", test))]
+    pub fn key_event(&mut self, key: Key, is_down: bool, is_repeat: bool) {
+        self.keys.push_event(key, is_down, is_repeat);
+    }
+
+    // Releases every held key and mouse button, e.g. when the window loses
+    // focus and the ups will never arrive.
+    #[cfg(any(target_os="windows", test))]
+    pub fn release_all(&mut self) {
+        for &key in ALL_KEYS {
+            if self.keys.is_down(key) {
+                self.key_event(key, false, false);
+            }
+        }
+        for button in self.mouse.buttons_mut().iter_mut() {
+            button.update(false);
+        }
+    }
+}
+
+impl Pad {
+    pub fn button(&self, button: PadButton) -> ButtonState {
+        match button {
+            PadButton::Up => self.up,
+            PadButton::Down => self.down,
+            PadButton::Left => self.left,
+            PadButton::Right => self.right,
+            PadButton::A => self.a,
+            PadButton::B => self.b,
+            PadButton::X => self.x,
+            PadButton::Y => self.y,
+            PadButton::LeftShoulder => self.left_shoulder,
+            PadButton::RightShoulder => self.right_shoulder,
+            PadButton::LeftThumb => self.left_thumb,
+            PadButton::RightThumb => self.right_thumb,
+            PadButton::Start => self.start,
+            PadButton::Back => self.back,
+        }
+    }
+
+    #[cfg(any(target_os="windows", test))]
+    pub fn button_mut(&mut self, button: PadButton) -> &mut ButtonState {
+        match button {
+            PadButton::Up => &mut self.up,
+            PadButton::Down => &mut self.down,
+            PadButton::Left => &mut self.left,
+            PadButton::Right => &mut self.right,
+            PadButton::A => &mut self.a,
+            PadButton::B => &mut self.b,
+            PadButton::X => &mut self.x,
+            PadButton::Y => &mut self.y,
+            PadButton::LeftShoulder => &mut self.left_shoulder,
+            PadButton::RightShoulder => &mut self.right_shoulder,
+            PadButton::LeftThumb => &mut self.left_thumb,
+            PadButton::RightThumb => &mut self.right_thumb,
+            PadButton::Start => &mut self.start,
+            PadButton::Back => &mut self.back,
+        }
+    }
+
+    pub fn buttons_mut(&mut self) -> [&mut ButtonState; 14] {
+        [
+            &mut self.up,
+            &mut self.down,
+            &mut self.left,
+            &mut self.right,
+            &mut self.a,
+            &mut self.b,
+            &mut self.x,
+            &mut self.y,
+            &mut self.left_shoulder,
+            &mut self.right_shoulder,
+            &mut self.left_thumb,
+            &mut self.right_thumb,
+            &mut self.start,
+            &mut self.back,
+        ]
+    }
+}
+
+// Asking an empty slot for its state is slow on some APIs (XInput stalls
+// for a noticeable time), so empty slots are polled less and less often, up
+// to MAX_POLL_INTERVAL frames apart. Connected slots are polled every frame.
+#[cfg(any(target_os="windows", test))]
+pub const MAX_POLL_INTERVAL: u32 = 120;
+
+#[cfg(any(target_os="windows", test))]
+#[derive(Clone, Copy, Debug)]
+struct SlotPoll {
+    interval: u32,
+    frames_left: u32,
+}
+
+#[cfg(any(target_os="windows", test))]
+#[derive(Clone, Debug)]
+pub struct PadPoller {
+    slots: [SlotPoll; MAX_CONTROLLERS],
+}
+
+#[cfg(any(target_os="windows", test))]
+impl PadPoller {
+    pub fn new() -> PadPoller {
+        PadPoller {
+            slots: [SlotPoll { interval: 1, frames_left: 0 }; MAX_CONTROLLERS],
+        }
+    }
+
+    // Called once per frame per slot; true when the slot is due.
+    pub fn should_poll(&mut self, slot: usize) -> bool {
+        let poll = &mut self.slots[slot];
+        if poll.frames_left == 0 {
+            return true;
+        }
+        poll.frames_left -= 1;
+        false
+    }
+
+    // Feeds the result of a poll back and forwards connection changes.
+    pub fn report(&mut self, input: &mut Input, slot: usize, connected: bool) {
+        let poll = &mut self.slots[slot];
+        if connected {
+            poll.interval = 1;
+            poll.frames_left = 0;
+        } else {
+            poll.frames_left = poll.interval;
+            poll.interval = (poll.interval * 2).min(MAX_POLL_INTERVAL);
+        }
+        input.set_connected(slot, connected);
+    }
+
+    // The platform saw a device arrive, so look at every slot right away.
+    pub fn poll_all_soon(&mut self) {
+        for poll in self.slots.iter_mut() {
+            poll.interval = 1;
+            poll.frames_left = 0;
+        }
+    }
+}
+
+#[cfg(any(target_os="windows", test))]
+impl Default for PadPoller {
+    fn default() -> Self {
+        PadPoller::new()
+    }
+}
+
+// Fractions of full deflection that read as zero. Defaults match the XInput
+// recommended values.
+#[derive(Clone, Copy, Debug)]
+pub struct Deadzones {
+    pub left_stick: f32,
+    pub right_stick: f32,
+    pub trigger: f32,
+}
+
+impl Default for Deadzones {
+    fn default() -> Self {
+        Deadzones {
+            left_stick: 7849.0 / 32767.0,
+            right_stick: 8689.0 / 32767.0,
+            trigger: 30.0 / 255.0,
+        }
+    }
+}
+
+// Radial deadzone: input within the deadzone circle is zero and the rest is
+// rescaled so the stick still reaches full deflection just past its edge.
+pub fn apply_stick_deadzone(x: f32, y: f32, deadzone: f32) -> Stick {
+    let magnitude = (x * x + y * y).sqrt();
+    if magnitude <= deadzone || deadzone >= 1.0 {
+        return Stick::default();
+    }
+    let scaled = ((magnitude - deadzone) / (1.0 - deadzone)).min(1.0);
+    Stick {
+        x: x / magnitude * scaled,
+        y: y / magnitude * scaled,
+    }
+}
+
+pub fn apply_trigger_deadzone(value: f32, deadzone: f32) -> f32 {
+    if value <= deadzone || deadzone >= 1.0 {
+        return 0.0;
+    }
+    ((value - deadzone) / (1.0 - deadzone)).min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_to_backbuffer_keeps_top_rows_on_top() {
+        assert_eq!(window_to_backbuffer(0, 0, 1280, 960, 640, 480), (0, 0));
+        assert_eq!(window_to_backbuffer(1279, 959, 1280, 960, 640, 480), (639, 479));
+        // a click near the top of the window lands in the first rows
+        assert_eq!(window_to_backbuffer(640, 10, 1280, 960, 640, 480), (320, 5));
+    }
+
+    #[test]
+    fn window_to_backbuffer_outside_window() {
+        // captured drags report points past the edges
+        assert_eq!(window_to_backbuffer(-1, -1, 1280, 960, 640, 480), (-1, -1));
+        assert_eq!(window_to_backbuffer(1300, 1000, 1280, 960, 640, 480), (650, 500));
+        // a minimized window has no size to scale by
+        assert_eq!(window_to_backbuffer(5, 7, 0, 0, 640, 480), (5, 7));
+    }
+
+    #[test]
+    fn empty_slots_are_polled_less_and_less() {
+        let mut poller = PadPoller::new();
+        let mut input = Input::default();
+        let mut polled_on = Vec::new();
+        for frame in 0..300 {
+            if poller.should_poll(0) {
+                polled_on.push(frame);
+                poller.report(&mut input, 0, false);
+            }
+        }
+        let gaps: Vec<u32> = polled_on.windows(2).map(|f| f[1] - f[0]).collect();
+        assert_eq!(gaps[..6], [2, 3, 5, 9, 17, 33]);
+        assert!(gaps.iter().all(|&gap| gap <= MAX_POLL_INTERVAL + 1));
+
+        // a device arriving gets every slot looked at right away
+        poller.poll_all_soon();
+        assert!(poller.should_poll(0));
+        poller.report(&mut input, 0, true);
+        assert!(input.controller(0).is_connected);
+        assert_eq!(input.pad_events, [PadEvent::Connected(0)]);
+        assert!(poller.should_poll(0) && poller.should_poll(0));
+    }
+}
@@ -0,0 +1,140 @@
+use super::input::ButtonState;
+
+macro_rules! keys {
+    ($($key:ident => $name:expr,)*) => {
+        // Physical keys, named after the US layout.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum Key {
+            $($key,)*
+        }
+
+        pub const ALL_KEYS: &[Key] = &[$(Key::$key,)*];
+
+        impl Key {
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Key::$key => $name,)*
+                }
+            }
+        }
+    };
+}
+
+keys! {
+    A => "A", B => "B", C => "C", D => "D", E => "E", F => "F", G => "G",
+    H => "H", I => "I", J => "J", K => "K", L => "L", M => "M", N => "N",
+    O => "O", P => "P", Q => "Q", R => "R", S => "S", T => "T", U => "U",
+    V => "V", W => "W", X => "X", Y => "Y", Z => "Z",
+    Num0 => "0", Num1 => "1", Num2 => "2", Num3 => "3", Num4 => "4",
+    Num5 => "5", Num6 => "6", Num7 => "7", Num8 => "8", Num9 => "9",
+    F1 => "F1", F2 => "F2", F3 => "F3", F4 => "F4", F5 => "F5", F6 => "F6",
+    F7 => "F7", F8 => "F8", F9 => "F9", F10 => "F10", F11 => "F11", F12 => "F12",
+    Escape => "Escape", Enter => "Enter", Space => "Space", Tab => "Tab",
+    Backspace => "Backspace", Insert => "Insert", Delete => "Delete",
+    Home => "Home", End => "End", PageUp => "PageUp", PageDown => "PageDown",
+    Up => "Up", Down => "Down", Left => "Left", Right => "Right",
+    LeftShift => "LeftShift", RightShift => "RightShift",
+    LeftCtrl => "LeftCtrl", RightCtrl => "RightCtrl",
+    LeftAlt => "LeftAlt", RightAlt => "RightAlt",
+    CapsLock => "CapsLock", Pause => "Pause", PrintScreen => "PrintScreen",
+    Minus => "Minus", Equals => "Equals", LeftBracket => "LeftBracket",
+    RightBracket => "RightBracket", Backslash => "Backslash",
+    Semicolon => "Semicolon", Apostrophe => "Apostrophe", Grave => "Grave",
+    Comma => "Comma", Period => "Period", Slash => "Slash",
+    Numpad0 => "Numpad0", Numpad1 => "Numpad1", Numpad2 => "Numpad2",
+    Numpad3 => "Numpad3", Numpad4 => "Numpad4", Numpad5 => "Numpad5",
+    Numpad6 => "Numpad6", Numpad7 => "Numpad7", Numpad8 => "Numpad8",
+    Numpad9 => "Numpad9", NumpadAdd => "NumpadAdd",
+    NumpadSubtract => "NumpadSubtract", NumpadMultiply => "NumpadMultiply",
+    NumpadDivide => "NumpadDivide", NumpadDecimal => "NumpadDecimal",
+    NumpadEnter => "NumpadEnter",
+}
+
+pub const KEY_COUNT: usize = ALL_KEYS.len();
+
+impl Key {
+    pub fn from_name(name: &str) -> Option<Key> {
+        ALL_KEYS.iter().copied().find(|k| k.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyEvent {
+    pub key: Key,
+    pub is_down: bool,
+    // auto-repeated keydown while the key is held
+    pub is_repeat: bool,
+    // modifier state after this event was applied
+    pub modifiers: Modifiers,
+}
+
+// Every key event of the current frame in arrival order, plus the resulting
+// state of each key.
+#[derive(Clone, Debug)]
+pub struct Keyboard {
+    pub events: Vec<KeyEvent>,
+    keys: [ButtonState; KEY_COUNT],
+}
+
+impl Keyboard {
+    pub fn new() -> Keyboard {
+        Keyboard {
+            events: Vec::new(),
+            keys: [ButtonState::default(); KEY_COUNT],
+        }
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.events.clear();
+        for key in self.keys.iter_mut() {
+            key.half_transition_count = 0;
+        }
+    }
+
+    #[cfg(any(target_os="windows", test))]
+    pub fn push_event(&mut self, key: Key, is_down: bool, is_repeat: bool) {
+        self.keys[key.index()].update(is_down);
+        let down = |k: Key| self.keys[k.index()].ended_down;
+        let modifiers = Modifiers {
+            shift: down(Key::LeftShift) || down(Key::RightShift),
+            ctrl: down(Key::LeftCtrl) || down(Key::RightCtrl),
+            alt: down(Key::LeftAlt) || down(Key::RightAlt),
+        };
+        self.events.push(KeyEvent {
+            key,
+            is_down,
+            is_repeat,
+            modifiers,
+        });
+    }
+
+    #[cfg(any(target_os="windows", test))]
+    pub fn key(&self, key: Key) -> ButtonState {
+        self.keys[key.index()]
+    }
+
+    pub fn is_down(&self, key: Key) -> bool {
+        self.keys[key.index()].is_down()
+    }
+
+    pub fn was_pressed(&self, key: Key) -> bool {
+        self.keys[key.index()].was_pressed()
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Self {
+        Keyboard::new()
+    }
+}
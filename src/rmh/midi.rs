@@ -0,0 +1,686 @@
+// Standard MIDI File playback through the synth. Format 0 and 1 files are
+// flattened into one list of channel events timed in seconds through the
+// tempo map, and MidiPlayer plays that list as a mixer voice, one SynthVoice
+// per sounding note.
+
+use std::sync::Arc;
+
+use super::mixer::SoundSource;
+use super::synth::{key_to_frequency, Envelope, Instrument, SynthVoice, Waveform};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MidiMessage {
+    NoteOff { key: u8 },
+    NoteOn { key: u8, velocity: u8 },
+    Controller { controller: u8, value: u8 },
+    Program(u8),
+    // -8192..8191
+    PitchBend(i16),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MidiEvent {
+    pub seconds: f64,
+    pub channel: u8,
+    pub message: MidiMessage,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Song {
+    // sorted by time; events at the same time keep file order
+    pub events: Vec<MidiEvent>,
+    pub length_seconds: f64,
+}
+
+const DEFAULT_TEMPO: u32 = 500_000;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Result<u8, String> {
+        let b = *self.data.get(self.pos).ok_or("unexpected end of data")?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.data.len() - self.pos < n {
+            return Err("unexpected end of data".to_string());
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn varlen(&mut self) -> Result<u32, String> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let b = self.u8()?;
+            value = (value << 7) | (b & 0x7F) as u32;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("variable length value too long".to_string())
+    }
+}
+
+// Events of one track in ticks, before the tempo map is applied.
+enum TrackEvent {
+    Tempo(u32),
+    Channel(u8, MidiMessage),
+}
+
+// Returns the tick the track ends at.
+fn parse_track(data: &[u8], events: &mut Vec<(u64, usize, TrackEvent)>) -> Result<u64, String> {
+    let mut r = Reader { data, pos: 0 };
+    let mut tick = 0u64;
+    let mut running_status = None;
+    while r.pos < data.len() {
+        tick += r.varlen()? as u64;
+        let mut status = r.u8()?;
+        let mut first_data = None;
+        if status < 0x80 {
+            // running status: this byte is already the first data byte
+            first_data = Some(status);
+            status = running_status.ok_or("data byte without status")?;
+        }
+
+        match status {
+            // sysex and meta events cancel running status
+            0xFF => {
+                running_status = None;
+                let kind = r.u8()?;
+                let len = r.varlen()? as usize;
+                let body = r.bytes(len)?;
+                match kind {
+                    0x2F => break,
+                    0x51 if len == 3 => {
+                        let tempo = u32::from_be_bytes([0, body[0], body[1], body[2]]);
+                        events.push((tick, events.len(), TrackEvent::Tempo(tempo)));
+                    }
+                    _ => {}
+                }
+            }
+            0xF0 | 0xF7 => {
+                running_status = None;
+                let len = r.varlen()? as usize;
+                r.bytes(len)?;
+            }
+            0x80..=0xEF => {
+                running_status = Some(status);
+                let channel = status & 0x0F;
+                let mut data_byte = || match first_data.take() {
+                    Some(b) => Ok(b),
+                    None => r.u8(),
+                };
+                let message = match status >> 4 {
+                    0x8 => {
+                        let key = data_byte()?;
+                        data_byte()?;
+                        Some(MidiMessage::NoteOff { key })
+                    }
+                    0x9 => {
+                        let key = data_byte()?;
+                        let velocity = data_byte()?;
+                        if velocity == 0 {
+                            Some(MidiMessage::NoteOff { key })
+                        } else {
+                            Some(MidiMessage::NoteOn { key, velocity })
+                        }
+                    }
+                    0xA => {
+                        data_byte()?;
+                        data_byte()?;
+                        None
+                    }
+                    0xB => {
+                        let controller = data_byte()?;
+                        let value = data_byte()?;
+                        Some(MidiMessage::Controller { controller, value })
+                    }
+                    0xC => Some(MidiMessage::Program(data_byte()?)),
+                    0xD => {
+                        data_byte()?;
+                        None
+                    }
+                    _ => {
+                        let lsb = data_byte()? as i16;
+                        let msb = data_byte()? as i16;
+                        Some(MidiMessage::PitchBend(((msb << 7) | lsb) - 8192))
+                    }
+                };
+                if let Some(message) = message {
+                    events.push((tick, events.len(), TrackEvent::Channel(channel, message)));
+                }
+            }
+            _ => return Err(format!("unsupported status byte {:#x}", status)),
+        }
+    }
+    Ok(tick)
+}
+
+impl Song {
+    pub fn parse(data: &[u8]) -> Result<Song, String> {
+        let mut r = Reader { data, pos: 0 };
+        if r.bytes(4)? != b"MThd" {
+            return Err("not a MIDI file".to_string());
+        }
+        let header_len = r.u32()? as usize;
+        let format = r.u16()?;
+        let n_tracks = r.u16()?;
+        let division = r.u16()?;
+        r.bytes(header_len.saturating_sub(6))?;
+        if format > 1 {
+            return Err(format!("MIDI format {} is not supported", format));
+        }
+
+        // seconds per tick is either tempo / ticks per quarter note, or fixed
+        // for SMPTE time
+        let (ticks_per_quarter, smpte_seconds_per_tick) = if division & 0x8000 == 0 {
+            (division.max(1) as f64, None)
+        } else {
+            let fps = match (division >> 8) as u8 as i8 {
+                -29 => 29.97,
+                fps => -(fps as f64),
+            };
+            let ticks_per_frame = (division & 0xFF).max(1) as f64;
+            (1.0, Some(1.0 / (fps * ticks_per_frame)))
+        };
+
+        // merged in tick order; the second value keeps same-tick events in
+        // track then file order
+        let mut events = Vec::new();
+        let mut end_tick = 0;
+        let mut tracks_read = 0;
+        while tracks_read < n_tracks && r.pos < data.len() {
+            let id = r.bytes(4)?;
+            let len = r.u32()? as usize;
+            let chunk = r.bytes(len)?;
+            if id != b"MTrk" {
+                continue;
+            }
+            let track_end = parse_track(chunk, &mut events).map_err(|e| format!("track {}: {}", tracks_read, e))?;
+            end_tick = end_tick.max(track_end);
+            tracks_read += 1;
+        }
+        events.sort_by_key(|e| (e.0, e.1));
+
+        let mut song = Song::default();
+        let mut tempo = DEFAULT_TEMPO;
+        let mut last_tick = 0u64;
+        let mut seconds = 0.0f64;
+        let mut advance_to = |tick: u64, tempo: u32| {
+            let seconds_per_tick = match smpte_seconds_per_tick {
+                Some(s) => s,
+                None => tempo as f64 / 1_000_000.0 / ticks_per_quarter,
+            };
+            seconds += (tick - last_tick) as f64 * seconds_per_tick;
+            last_tick = tick;
+            seconds
+        };
+        for (tick, _, event) in events {
+            let seconds = advance_to(tick, tempo);
+            match event {
+                TrackEvent::Tempo(t) => tempo = t,
+                TrackEvent::Channel(channel, message) => song.events.push(MidiEvent { seconds, channel, message }),
+            }
+        }
+        // the last end of track, which may come well after the last note
+        song.length_seconds = advance_to(end_tick, tempo);
+        Ok(song)
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<Song, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Song::parse(&data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+const fn instrument(waveform: Waveform, attack: f32, decay: f32, sustain: f32, release: f32, volume: f32) -> Instrument {
+    Instrument {
+        waveform,
+        envelope: Envelope { attack, decay, sustain, release },
+        volume,
+    }
+}
+
+// One instrument per General MIDI family of eight programs.
+const INSTRUMENTS: [Instrument; 16] = [
+    instrument(Waveform::Pulse(0.25), 0.005, 0.6, 0.3, 0.2, 0.5), // piano
+    instrument(Waveform::Sine, 0.001, 0.4, 0.0, 0.1, 0.6), // chromatic percussion
+    instrument(Waveform::Pulse(0.5), 0.01, 0.05, 0.9, 0.05, 0.35), // organ
+    instrument(Waveform::Saw, 0.002, 0.5, 0.2, 0.15, 0.4), // guitar
+    instrument(Waveform::Triangle, 0.005, 0.3, 0.7, 0.1, 0.8), // bass
+    instrument(Waveform::Saw, 0.08, 0.2, 0.8, 0.3, 0.3), // strings
+    instrument(Waveform::Saw, 0.1, 0.2, 0.8, 0.4, 0.3), // ensemble
+    instrument(Waveform::Pulse(0.5), 0.03, 0.1, 0.8, 0.1, 0.35), // brass
+    instrument(Waveform::Pulse(0.125), 0.03, 0.1, 0.8, 0.1, 0.35), // reed
+    instrument(Waveform::Triangle, 0.05, 0.1, 0.8, 0.15, 0.6), // pipe
+    instrument(Waveform::Pulse(0.5), 0.005, 0.1, 0.7, 0.1, 0.35), // synth lead
+    instrument(Waveform::Triangle, 0.3, 0.3, 0.7, 0.6, 0.5), // synth pad
+    instrument(Waveform::Saw, 0.05, 0.3, 0.5, 0.3, 0.3), // synth effects
+    instrument(Waveform::Pulse(0.25), 0.002, 0.4, 0.2, 0.2, 0.4), // ethnic
+    instrument(Waveform::Noise, 0.001, 0.2, 0.0, 0.1, 0.4), // percussive
+    instrument(Waveform::Noise, 0.05, 0.5, 0.3, 0.5, 0.3), // sound effects
+];
+
+const PERCUSSION_CHANNEL: u8 = 9;
+const DRUM: Instrument = instrument(Waveform::Noise, 0.001, 0.15, 0.0, 0.05, 0.5);
+const MAX_VOICES: usize = 32;
+// in semitones for a full bend
+const PITCH_BEND_RANGE: f32 = 2.0;
+
+#[derive(Clone, Copy)]
+struct Channel {
+    program: u8,
+    volume: f32,
+    pan: f32,
+    bend: f32,
+}
+
+impl Default for Channel {
+    fn default() -> Channel {
+        Channel { program: 0, volume: 100.0 / 127.0, pan: 0.5, bend: 0.0 }
+    }
+}
+
+struct Note {
+    channel: u8,
+    key: u8,
+    voice: SynthVoice,
+}
+
+pub struct MidiPlayer {
+    song: Arc<Song>,
+    pub looping: bool,
+    next_event: usize,
+    // frames rendered so far, and the frame the current pass through the
+    // song started at
+    clock: u64,
+    origin: u64,
+    channels: [Channel; 16],
+    notes: Vec<Note>,
+}
+
+impl MidiPlayer {
+    pub fn new(song: Arc<Song>) -> MidiPlayer {
+        MidiPlayer {
+            song,
+            looping: false,
+            next_event: 0,
+            clock: 0,
+            origin: 0,
+            channels: [Channel::default(); 16],
+            notes: Vec::new(),
+        }
+    }
+
+    fn handle(&mut self, event: MidiEvent) {
+        let channel = &mut self.channels[event.channel as usize];
+        match event.message {
+            MidiMessage::NoteOn { key, velocity } => {
+                let instrument = if event.channel == PERCUSSION_CHANNEL {
+                    DRUM
+                } else {
+                    INSTRUMENTS[channel.program as usize / 8]
+                };
+                let frequency = key_to_frequency(key as f32 + channel.bend);
+                if self.notes.len() >= MAX_VOICES {
+                    // steal the oldest, preferring notes already let go
+                    let index = self.notes.iter().position(|n| n.voice.is_released()).unwrap_or(0);
+                    self.notes.remove(index);
+                }
+                self.notes.push(Note {
+                    channel: event.channel,
+                    key,
+                    voice: SynthVoice::new(instrument, frequency, velocity as f32 / 127.0),
+                });
+            }
+            MidiMessage::NoteOff { key } => {
+                for note in self.notes.iter_mut() {
+                    if note.channel == event.channel && note.key == key && !note.voice.is_released() {
+                        note.voice.release();
+                    }
+                }
+            }
+            MidiMessage::Controller { controller, value } => match controller {
+                7 => channel.volume = value as f32 / 127.0,
+                10 => channel.pan = value as f32 / 127.0,
+                // all sound off, all notes off
+                120 | 123 => {
+                    for note in self.notes.iter_mut().filter(|n| n.channel == event.channel) {
+                        note.voice.release();
+                    }
+                }
+                _ => {}
+            },
+            MidiMessage::Program(program) => channel.program = program & 0x7F,
+            MidiMessage::PitchBend(bend) => {
+                channel.bend = bend as f32 / 8192.0 * PITCH_BEND_RANGE;
+                for note in self.notes.iter_mut().filter(|n| n.channel == event.channel) {
+                    note.voice.frequency = key_to_frequency(note.key as f32 + channel.bend);
+                }
+            }
+        }
+    }
+
+    fn render_notes(&mut self, out: &mut [f32], sample_rate: u32) {
+        for note in self.notes.iter_mut() {
+            let channel = &self.channels[note.channel as usize];
+            // constant power pan
+            let angle = channel.pan * std::f32::consts::FRAC_PI_2;
+            let left = angle.cos() * channel.volume;
+            let right = angle.sin() * channel.volume;
+            note.voice.render(out, sample_rate, left, right);
+        }
+        let mut i = 0;
+        while i < self.notes.len() {
+            if self.notes[i].voice.is_finished() {
+                self.notes.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+impl SoundSource for MidiPlayer {
+    // Like Mixer::mix, renders in pieces split at each event's exact frame.
+    fn render(&mut self, out: &mut [f32], sample_rate: u32) -> bool {
+        let frames = out.len() / 2;
+        let end = self.clock + frames as u64;
+        let to_frame = |seconds: f64| (seconds * sample_rate as f64).round() as u64;
+        let song = self.song.clone();
+        let length = to_frame(song.length_seconds);
+
+        let mut pos = 0;
+        loop {
+            let next = match song.events.get(self.next_event) {
+                Some(event) => Some(self.origin + to_frame(event.seconds)),
+                // the end of the song, to loop from
+                None if self.looping && length > 0 => Some(self.origin + length),
+                None => None,
+            };
+            let next = match next {
+                Some(frame) if frame < end => Some(frame),
+                _ => None,
+            };
+            let segment_end = match next {
+                Some(frame) => (frame.max(self.clock) - self.clock) as usize,
+                None => frames,
+            };
+            if segment_end > pos {
+                self.render_notes(&mut out[pos * 2..segment_end * 2], sample_rate);
+                pos = segment_end;
+            }
+            if next.is_none() {
+                break;
+            }
+
+            match song.events.get(self.next_event) {
+                Some(&event) => {
+                    self.handle(event);
+                    self.next_event += 1;
+                }
+                None => {
+                    self.origin += length;
+                    self.next_event = 0;
+                    self.channels = [Channel::default(); 16];
+                }
+            }
+        }
+        self.clock = end;
+
+        let song_over = self.next_event >= song.events.len() && !self.looping;
+        !(song_over && self.notes.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const END_OF_TRACK: &[u8] = &[0x00, 0xFF, 0x2F, 0x00];
+
+    // A whole file: header, then each track body with its end of track.
+    fn smf(format: u16, division: u16, tracks: &[&[u8]]) -> Vec<u8> {
+        let mut data = b"MThd\x00\x00\x00\x06".to_vec();
+        data.extend_from_slice(&format.to_be_bytes());
+        data.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+        data.extend_from_slice(&division.to_be_bytes());
+        for track in tracks {
+            data.extend_from_slice(b"MTrk");
+            data.extend_from_slice(&((track.len() + END_OF_TRACK.len()) as u32).to_be_bytes());
+            data.extend_from_slice(track);
+            data.extend_from_slice(END_OF_TRACK);
+        }
+        data
+    }
+
+    fn messages(song: &Song) -> Vec<MidiMessage> {
+        song.events.iter().map(|e| e.message).collect()
+    }
+
+    #[test]
+    fn decodes_varlen() {
+        let cases: &[(&[u8], u32)] = &[
+            (&[0x00], 0),
+            (&[0x7F], 0x7F),
+            (&[0x81, 0x00], 0x80),
+            (&[0xC0, 0x00], 0x2000),
+            (&[0xFF, 0x7F], 0x3FFF),
+            (&[0x81, 0x80, 0x00], 0x4000),
+            (&[0xFF, 0xFF, 0x7F], 0x1F_FFFF),
+            (&[0x81, 0x80, 0x80, 0x00], 0x20_0000),
+            (&[0xFF, 0xFF, 0xFF, 0x7F], 0x0FFF_FFFF),
+        ];
+        for &(bytes, value) in cases {
+            let mut r = Reader { data: bytes, pos: 0 };
+            assert_eq!(r.varlen(), Ok(value), "{:02x?}", bytes);
+            assert_eq!(r.pos, bytes.len());
+        }
+        let mut r = Reader { data: &[0x80, 0x80, 0x80, 0x80, 0x00], pos: 0 };
+        assert!(r.varlen().is_err());
+        let mut r = Reader { data: &[0x81], pos: 0 };
+        assert!(r.varlen().is_err());
+    }
+
+    #[test]
+    fn running_status_and_zero_velocity() {
+        let track = [
+            0x00, 0x90, 0x3C, 0x40, // note on C4
+            0x60, 0x40, 0x50, // running status: note on E4
+            0x60, 0x3C, 0x00, // running status, velocity 0: C4 off
+            0x00, 0x80, 0x40, 0x40, // E4 off
+            0x00, 0xC1, 0x05, // program 5 on channel 1
+            0x00, 0xE1, 0x00, 0x40, // centered pitch bend
+        ];
+        let song = Song::parse(&smf(0, 96, &[&track])).unwrap();
+        assert_eq!(
+            messages(&song),
+            vec![
+                MidiMessage::NoteOn { key: 0x3C, velocity: 0x40 },
+                MidiMessage::NoteOn { key: 0x40, velocity: 0x50 },
+                MidiMessage::NoteOff { key: 0x3C },
+                MidiMessage::NoteOff { key: 0x40 },
+                MidiMessage::Program(5),
+                MidiMessage::PitchBend(0),
+            ]
+        );
+        assert_eq!(song.events[4].channel, 1);
+        // 0x60 ticks at 96 per quarter and 120bpm is half a second
+        let seconds: Vec<f64> = song.events.iter().map(|e| e.seconds).collect();
+        assert_eq!(seconds, vec![0.0, 0.5, 1.0, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn meta_and_sysex_cancel_running_status() {
+        let after_meta = [0x00, 0x90, 0x3C, 0x40, 0x00, 0xFF, 0x01, 0x02, b'h', b'i', 0x00, 0x3C, 0x00];
+        let err = Song::parse(&smf(0, 96, &[&after_meta])).unwrap_err();
+        assert_eq!(err, "track 0: data byte without status");
+
+        let after_sysex = [0x00, 0x90, 0x3C, 0x40, 0x00, 0xF0, 0x03, 0x7E, 0x09, 0xF7, 0x00, 0x3C, 0x00];
+        let err = Song::parse(&smf(0, 96, &[&after_sysex])).unwrap_err();
+        assert_eq!(err, "track 0: data byte without status");
+
+        // a fresh status after the meta event is fine
+        let restarted = [0x00, 0x90, 0x3C, 0x40, 0x00, 0xFF, 0x01, 0x00, 0x10, 0x80, 0x3C, 0x00];
+        let song = Song::parse(&smf(0, 96, &[&restarted])).unwrap();
+        assert_eq!(messages(&song), vec![MidiMessage::NoteOn { key: 0x3C, velocity: 0x40 }, MidiMessage::NoteOff { key: 0x3C }]);
+    }
+
+    #[test]
+    fn tempo_map_applies_across_format1_tracks() {
+        // conductor track: 120bpm, then 240bpm from the second beat
+        let conductor = [
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // 500000us per quarter
+            0x60, 0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90, // 250000us per quarter
+        ];
+        let notes = [
+            0x60, 0x90, 0x3C, 0x40, // beat 2
+            0x60, 0x3C, 0x00, // beat 3
+            0x81, 0x40, 0x91, 0x40, 0x40, // beat 5, channel 1, two byte delta
+        ];
+        let song = Song::parse(&smf(1, 96, &[&conductor, &notes])).unwrap();
+        let timed: Vec<(f64, u8)> = song.events.iter().map(|e| (e.seconds, e.channel)).collect();
+        assert_eq!(timed, vec![(0.5, 0), (0.75, 0), (1.25, 1)]);
+        assert_eq!(song.length_seconds, 1.25);
+    }
+
+    #[test]
+    fn same_tick_events_keep_track_order() {
+        let first = [0x00, 0x90, 0x3C, 0x40];
+        let second = [0x00, 0x91, 0x40, 0x40];
+        let song = Song::parse(&smf(1, 96, &[&first, &second])).unwrap();
+        let channels: Vec<u8> = song.events.iter().map(|e| e.channel).collect();
+        assert_eq!(channels, vec![0, 1]);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        assert_eq!(Song::parse(b"RIFF\x00\x00\x00\x06").unwrap_err(), "not a MIDI file");
+        assert_eq!(Song::parse(&smf(2, 96, &[])).unwrap_err(), "MIDI format 2 is not supported");
+        let truncated = [0x00, 0x90, 0x3C];
+        let mut data = smf(0, 96, &[]);
+        data.extend_from_slice(b"MTrk\x00\x00\x00\x03");
+        data.extend_from_slice(&truncated);
+        data[11] = 1;
+        assert_eq!(Song::parse(&data).unwrap_err(), "track 0: unexpected end of data");
+    }
+
+    const RATE: u32 = 1000;
+
+    fn event(seconds: f64, channel: u8, message: MidiMessage) -> MidiEvent {
+        MidiEvent { seconds, channel, message }
+    }
+
+    fn note_on(seconds: f64, key: u8) -> MidiEvent {
+        event(seconds, 0, MidiMessage::NoteOn { key, velocity: 100 })
+    }
+
+    fn note_off(seconds: f64, key: u8) -> MidiEvent {
+        event(seconds, 0, MidiMessage::NoteOff { key })
+    }
+
+    fn player(events: Vec<MidiEvent>) -> MidiPlayer {
+        let length_seconds = events.last().map_or(0.0, |e| e.seconds);
+        MidiPlayer::new(Arc::new(Song { events, length_seconds }))
+    }
+
+    fn render_in(player: &mut MidiPlayer, chunks: &[usize]) -> Vec<f32> {
+        let mut out = Vec::new();
+        for &frames in chunks {
+            let mut chunk = vec![0.0; frames * 2];
+            player.render(&mut chunk, RATE);
+            out.extend(chunk);
+        }
+        out
+    }
+
+    #[test]
+    fn events_land_on_their_frame() {
+        let events = vec![note_on(0.5, 60), note_off(0.75, 60)];
+        let whole = render_in(&mut player(events.clone()), &[1000]);
+        // the voice's first frame is silent, the attack starts on the next
+        let first = whole.iter().position(|&s| s != 0.0).unwrap();
+        assert_eq!(first / 2, 501);
+
+        // where the buffers split doesn't matter
+        let split = render_in(&mut player(events), &[333, 1, 166, 250, 250]);
+        assert_eq!(split, whole);
+    }
+
+    #[test]
+    fn looping_resets_channels() {
+        let mut player = player(vec![
+            note_on(0.0, 60),
+            event(0.5, 0, MidiMessage::Program(40)),
+            event(0.5, 0, MidiMessage::Controller { controller: 7, value: 20 }),
+            event(0.5, 0, MidiMessage::PitchBend(8191)),
+            note_off(1.0, 60),
+        ]);
+        player.looping = true;
+
+        render_in(&mut player, &[800]);
+        assert_eq!(player.channels[0].program, 40);
+        assert!(player.channels[0].bend > 1.9);
+
+        // past the loop point but before the second pass reaches 0.5s
+        render_in(&mut player, &[400]);
+        let channel = player.channels[0];
+        let fresh = Channel::default();
+        assert_eq!((channel.program, channel.volume, channel.bend), (fresh.program, fresh.volume, fresh.bend));
+        let note = player.notes.last().unwrap();
+        assert!(!note.voice.is_released());
+        assert_eq!(note.voice.frequency, key_to_frequency(60.0));
+    }
+
+    #[test]
+    fn steals_voices_past_the_limit() {
+        let mut events: Vec<MidiEvent> = (0..MAX_VOICES as u8).map(|key| note_on(0.0, key)).collect();
+        events.push(note_off(0.0, 5));
+        // takes the released note's voice, then the oldest
+        events.push(note_on(0.0, 100));
+        events.push(note_on(0.0, 101));
+        let mut player = player(events);
+        render_in(&mut player, &[1]);
+
+        let keys: Vec<u8> = player.notes.iter().map(|n| n.key).collect();
+        assert_eq!(keys.len(), MAX_VOICES);
+        assert!(!keys.contains(&5));
+        assert!(!keys.contains(&0));
+        assert_eq!(keys[..2], [1, 2]);
+        assert_eq!(keys[MAX_VOICES - 2..], [100, 101]);
+    }
+
+    #[test]
+    fn finishes_after_the_last_note_dies_out() {
+        let mut player = player(vec![note_on(0.0, 60), note_off(0.1, 60)]);
+        let mut buffer = [0.0; 200];
+        // the song is over at 0.1s but the piano's release runs for 0.2s more
+        let mut frames = 100;
+        while player.render(&mut buffer, RATE) {
+            frames += 100;
+            assert!(frames < 1000, "never finished");
+        }
+        assert!(frames >= 300, "stopped after {} frames", frames);
+        buffer = [0.0; 200];
+        assert!(!player.render(&mut buffer, RATE));
+        assert!(buffer.iter().all(|&s| s == 0.0));
+    }
+
+}
@@ -0,0 +1,433 @@
+// Sounds placed on an absolute timeline of sample frames (one frame is one
+// sample per channel). Frame 0 is the first frame the platform asked us to
+// mix, and the clock advances by exactly what gets mixed, so a sound
+// scheduled for frame N starts at the precise offset within whichever fill
+// buffer covers N, however the platform sizes its writes.
+//
+// Voices play on a Bus. Each bus is mixed separately so its volume, mute and
+// the music ducking can be applied, all smoothed so changes don't click.
+
+use std::sync::Arc;
+
+use super::bus::{AudioSettings, Bus, ALL_BUSES, BUS_COUNT};
+
+pub type SoundId = u32;
+
+// Anything that produces stereo sound. render() adds into `out`, interleaved
+// left/right in -1..1, and returns false once the sound has finished.
+pub trait SoundSource: Send {
+    fn render(&mut self, out: &mut [f32], sample_rate: u32) -> bool;
+}
+
+#[cfg(test)]
+pub struct Sine {
+    frequency: f32,
+    volume: f32,
+    phase: f32,
+    // None plays until stopped
+    frames_left: Option<u64>,
+}
+
+#[cfg(test)]
+impl Sine {
+    pub fn new(frequency: f32, volume: f32) -> Sine {
+        Sine {
+            frequency,
+            volume,
+            phase: 0.0,
+            frames_left: None,
+        }
+    }
+
+    pub fn with_duration(mut self, frames: u64) -> Sine {
+        self.frames_left = Some(frames);
+        self
+    }
+}
+
+#[cfg(test)]
+impl SoundSource for Sine {
+    fn render(&mut self, out: &mut [f32], sample_rate: u32) -> bool {
+        let step = std::f32::consts::PI * 2.0 * self.frequency / sample_rate as f32;
+        for frame in out.chunks_mut(2) {
+            if self.frames_left == Some(0) {
+                return false;
+            }
+            let sample = self.phase.sin() * self.volume;
+            for s in frame.iter_mut() {
+                *s += sample;
+            }
+            self.phase = (self.phase + step) % (std::f32::consts::PI * 2.0);
+            if let Some(left) = self.frames_left.as_mut() {
+                *left -= 1;
+            }
+        }
+        self.frames_left != Some(0)
+    }
+}
+
+// Interleaved stereo samples played once from start to end.
+pub struct Clip {
+    samples: Arc<Vec<f32>>,
+    pos: usize,
+    volume: f32,
+}
+
+impl Clip {
+    pub fn new(samples: Arc<Vec<f32>>, volume: f32) -> Clip {
+        Clip { samples, pos: 0, volume }
+    }
+}
+
+impl SoundSource for Clip {
+    fn render(&mut self, out: &mut [f32], _sample_rate: u32) -> bool {
+        let n = out.len().min(self.samples.len() - self.pos);
+        for (o, s) in out[..n].iter_mut().zip(self.samples[self.pos..].iter()) {
+            *o += s * self.volume;
+        }
+        self.pos += n;
+        self.pos < self.samples.len()
+    }
+}
+
+enum Command {
+    Start(Bus, Box<dyn SoundSource>),
+    Stop,
+}
+
+struct Scheduled {
+    frame: u64,
+    id: SoundId,
+    command: Command,
+}
+
+struct Voice {
+    id: SoundId,
+    bus: Bus,
+    source: Box<dyn SoundSource>,
+}
+
+// Roughly how long bus gains take to follow a volume change, and music to
+// duck and come back up.
+const GAIN_SMOOTH_SECONDS: f32 = 0.05;
+const DUCK_ATTACK_SECONDS: f32 = 0.08;
+const DUCK_RELEASE_SECONDS: f32 = 0.5;
+
+// Per frame factor of a one pole smoother settling in about `seconds`.
+fn smoothing(seconds: f32, sample_rate: u32) -> f32 {
+    1.0 - (-1.0 / (seconds * sample_rate as f32)).exp()
+}
+
+pub struct Mixer {
+    pub sample_rate: u32,
+    // read every mix, so game code can change volumes at any time
+    pub settings: AudioSettings,
+    // next frame mix() will produce
+    clock: u64,
+    voices: Vec<Voice>,
+    // kept sorted by frame, oldest first
+    scheduled: Vec<Scheduled>,
+    next_id: SoundId,
+    // smoothed gains by `Bus as usize`, and the music ducking gain
+    gains: [f32; BUS_COUNT],
+    duck_gain: f32,
+    bus_scratch: Vec<Vec<f32>>,
+    scratch: Vec<f32>,
+}
+
+impl Mixer {
+    pub fn new(sample_rate: u32) -> Mixer {
+        let mut mixer = Mixer {
+            sample_rate,
+            settings: AudioSettings::default(),
+            clock: 0,
+            voices: Vec::new(),
+            scheduled: Vec::new(),
+            next_id: 1,
+            gains: [1.0; BUS_COUNT],
+            duck_gain: 1.0,
+            bus_scratch: vec![Vec::new(); BUS_COUNT],
+            scratch: Vec::new(),
+        };
+        mixer.set_settings(mixer.settings.clone());
+        mixer
+    }
+
+    // Replaces the settings without smoothing, e.g. when loading them.
+    pub fn set_settings(&mut self, settings: AudioSettings) {
+        for &bus in ALL_BUSES {
+            self.gains[bus as usize] = settings.target_gain(bus);
+        }
+        self.settings = settings;
+    }
+
+    pub fn seconds_to_frame(&self, seconds: f64) -> u64 {
+        (seconds.max(0.0) * self.sample_rate as f64).round() as u64
+    }
+
+    // The frame `seconds` into the game frame being updated. The platform
+    // mixes right after the update, so that frame's sound starts at the clock
+    // and plays in step with its picture.
+    pub fn frame_after(&self, seconds: f64) -> u64 {
+        self.clock + self.seconds_to_frame(seconds)
+    }
+
+    // Starts `source` on `bus` at `frame`. Frames already mixed start it at
+    // the beginning of the next mix.
+    pub fn play_at(&mut self, frame: u64, bus: Bus, source: Box<dyn SoundSource>) -> SoundId {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.schedule(frame, id, Command::Start(bus, source));
+        id
+    }
+
+    pub fn play(&mut self, bus: Bus, source: Box<dyn SoundSource>) -> SoundId {
+        self.play_at(self.clock, bus, source)
+    }
+
+    pub fn stop_at(&mut self, frame: u64, id: SoundId) {
+        self.schedule(frame, id, Command::Stop);
+    }
+
+    pub fn is_playing(&self, id: SoundId) -> bool {
+        self.voices.iter().any(|v| v.id == id)
+    }
+
+    fn schedule(&mut self, frame: u64, id: SoundId, command: Command) {
+        // after everything at the same frame, so commands keep their order
+        let index = self.scheduled.iter().position(|s| s.frame > frame).unwrap_or(self.scheduled.len());
+        self.scheduled.insert(index, Scheduled { frame, id, command });
+    }
+
+    // Sums the bus buffers into scratch for frames start..end, moving the
+    // smoothed gains along frame by frame. `ducking` is whether anything that
+    // ducks the music played in those frames.
+    fn mix_buses(&mut self, start: usize, end: usize, ducking: bool) {
+        let smooth = smoothing(GAIN_SMOOTH_SECONDS, self.sample_rate);
+        let duck_target = if ducking { self.settings.duck_level } else { 1.0 };
+        let duck_smooth = if duck_target < self.duck_gain {
+            smoothing(DUCK_ATTACK_SECONDS, self.sample_rate)
+        } else {
+            smoothing(DUCK_RELEASE_SECONDS, self.sample_rate)
+        };
+        let mut targets = [0.0; BUS_COUNT];
+        for &bus in ALL_BUSES {
+            targets[bus as usize] = self.settings.target_gain(bus);
+        }
+
+        for i in start * 2..end * 2 {
+            // gains move once per frame, on the left sample
+            if i % 2 == 0 {
+                for (gain, target) in self.gains.iter_mut().zip(targets.iter()) {
+                    *gain += (target - *gain) * smooth;
+                }
+                self.duck_gain += (duck_target - self.duck_gain) * duck_smooth;
+            }
+            let mut sample = self.bus_scratch[Bus::Master as usize][i];
+            for &bus in ALL_BUSES[1..].iter() {
+                let mut gain = self.gains[bus as usize];
+                if bus == Bus::Music {
+                    gain *= self.duck_gain;
+                }
+                sample += self.bus_scratch[bus as usize][i] * gain;
+            }
+            self.scratch[i] = sample * self.gains[Bus::Master as usize];
+        }
+    }
+
+    // Adds the next out.len() / 2 frames of all voices into `out`
+    // (interleaved stereo), saturating, and advances the clock.
+    pub fn mix(&mut self, out: &mut [i16]) {
+        crate::timed_block!("mixer");
+        let frames = out.len() / 2;
+        let end = self.clock + frames as u64;
+        self.scratch.clear();
+        self.scratch.resize(frames * 2, 0.0);
+        for buffer in self.bus_scratch.iter_mut() {
+            buffer.clear();
+            buffer.resize(frames * 2, 0.0);
+        }
+
+        let mut pos = 0;
+        loop {
+            // render up to the next command inside this buffer
+            let next = match self.scheduled.first() {
+                Some(s) if s.frame < end => Some((s.frame.max(self.clock) - self.clock) as usize),
+                _ => None,
+            };
+            let segment_end = next.unwrap_or(frames);
+            if segment_end > pos {
+                let sample_rate = self.sample_rate;
+                let ducking = self.voices.iter().any(|v| v.bus.ducks_music());
+                let mut i = 0;
+                while i < self.voices.len() {
+                    let voice = &mut self.voices[i];
+                    let segment = &mut self.bus_scratch[voice.bus as usize][pos * 2..segment_end * 2];
+                    if voice.source.render(segment, sample_rate) {
+                        i += 1;
+                    } else {
+                        self.voices.remove(i);
+                    }
+                }
+                self.mix_buses(pos, segment_end, ducking);
+                pos = segment_end;
+            }
+            if next.is_none() {
+                break;
+            }
+
+            let scheduled = self.scheduled.remove(0);
+            match scheduled.command {
+                Command::Start(bus, source) => self.voices.push(Voice {
+                    id: scheduled.id,
+                    bus,
+                    source,
+                }),
+                Command::Stop => self.voices.retain(|v| v.id != scheduled.id),
+            }
+        }
+
+        for (o, s) in out.iter_mut().zip(self.scratch.iter()) {
+            *o = (*o as f32 + s * 32767.0).clamp(-32768.0, 32767.0) as i16;
+        }
+        self.clock = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rmh::bus::BusSettings;
+
+    // Frames mixed in buffers of `buffer_frames` until `total` frames.
+    fn mix_frames(mixer: &mut Mixer, buffer_frames: usize, total: usize) -> Vec<i16> {
+        let mut out = Vec::new();
+        while out.len() < total * 2 {
+            let mut buffer = vec![0i16; buffer_frames * 2];
+            mixer.mix(&mut buffer);
+            out.extend_from_slice(&buffer);
+        }
+        out
+    }
+
+    fn first_sound(out: &[i16]) -> Option<usize> {
+        out.iter().position(|&s| s != 0).map(|i| i / 2)
+    }
+
+    #[test]
+    fn scheduled_sound_starts_at_exact_frame() {
+        for &buffer_frames in [1, 7, 64, 100, 333, 1024].iter() {
+            for &start in [0, 5, 99, 100, 513].iter() {
+                let mut mixer = Mixer::new(48000);
+                let id = mixer.play_at(start, Bus::Sfx, Box::new(Clip::new(Arc::new(vec![0.5; 200]), 1.0)));
+                let out = mix_frames(&mut mixer, buffer_frames, 2000);
+                assert_eq!(first_sound(&out), Some(start as usize), "buffers of {}", buffer_frames);
+                assert_eq!(out.iter().filter(|&&s| s != 0).count(), 200);
+                assert!(!mixer.is_playing(id));
+            }
+        }
+    }
+
+    #[test]
+    fn frame_after_lands_mid_buffer() {
+        let mut mixer = Mixer::new(48000);
+        mix_frames(&mut mixer, 800, 1600);
+        assert_eq!(mixer.clock, 1600);
+
+        // 5 ms into the frame being updated is 240 frames into the next mix
+        let frame = mixer.frame_after(0.005);
+        assert_eq!(frame, 1840);
+        mixer.play_at(frame, Bus::Sfx, Box::new(Clip::new(Arc::new(vec![0.5; 20]), 1.0)));
+        let out = mix_frames(&mut mixer, 800, 800);
+        assert_eq!(first_sound(&out), Some(240));
+    }
+
+    #[test]
+    fn late_sound_starts_with_next_mix() {
+        let mut mixer = Mixer::new(48000);
+        mix_frames(&mut mixer, 50, 50);
+        mixer.play_at(3, Bus::Master, Box::new(Clip::new(Arc::new(vec![0.5; 4]), 1.0)));
+        let out = mix_frames(&mut mixer, 50, 50);
+        assert_eq!(&out[..6], &[16383, 16383, 16383, 16383, 0, 0]);
+    }
+
+    #[test]
+    fn stop_at_exact_frame() {
+        let mut mixer = Mixer::new(48000);
+        let id = mixer.play_at(10, Bus::Master, Box::new(Sine::new(1000.0, 0.5)));
+        mixer.stop_at(50, id);
+        let out = mix_frames(&mut mixer, 100, 100);
+        let last = out.iter().rposition(|&s| s != 0).unwrap() / 2;
+        assert!(last > 40 && last < 50, "{}", last);
+        assert!(!mixer.is_playing(id));
+    }
+
+    fn peak(out: &[i16]) -> i16 {
+        out.iter().map(|s| s.saturating_abs()).max().unwrap_or(0)
+    }
+
+    fn settings_with(bus: Bus, volume: f32, muted: bool) -> AudioSettings {
+        let mut settings = AudioSettings::default();
+        *settings.bus_mut(bus) = BusSettings { volume, muted };
+        settings
+    }
+
+    #[test]
+    fn muted_bus_is_silent() {
+        let mut mixer = Mixer::new(48000);
+        mixer.set_settings(settings_with(Bus::Sfx, 1.0, true));
+        mixer.play(Bus::Sfx, Box::new(Sine::new(440.0, 0.5)));
+        mixer.play(Bus::Ui, Box::new(Sine::new(440.0, 0.0)));
+        let out = mix_frames(&mut mixer, 480, 4800);
+        assert_eq!(peak(&out), 0);
+    }
+
+    #[test]
+    fn muting_fades_instead_of_cutting() {
+        let mut mixer = Mixer::new(48000);
+        mixer.play(Bus::Music, Box::new(Sine::new(100.0, 0.5)));
+        mix_frames(&mut mixer, 4800, 4800);
+        mixer.settings.bus_mut(Bus::Music).muted = true;
+        let out = mix_frames(&mut mixer, 48000, 48000);
+        assert!(peak(&out[..200]) > 1000);
+        assert!(peak(&out[48000..]) < 5, "{}", peak(&out[48000..]));
+    }
+
+    #[test]
+    fn sfx_ducks_music_to_duck_level() {
+        let mut mixer = Mixer::new(48000);
+        mixer.set_settings(settings_with(Bus::Music, 0.5, false));
+        mixer.play(Bus::Music, Box::new(Sine::new(100.0, 0.5)));
+        let out = mix_frames(&mut mixer, 4800, 4800);
+        assert!((peak(&out) - 8192).abs() < 50, "{}", peak(&out));
+
+        // a silent effect still ducks for as long as it plays
+        let id = mixer.play(Bus::Sfx, Box::new(Sine::new(1000.0, 0.0).with_duration(48000)));
+        let out = mix_frames(&mut mixer, 48000, 48000);
+        let ducked = peak(&out[60000..]);
+        let expected = 8192.0 * mixer.settings.duck_level;
+        assert!((ducked as f32 - expected).abs() < 50.0, "{} vs {}", ducked, expected);
+        assert!(!mixer.is_playing(id));
+
+        let out = mix_frames(&mut mixer, 96000, 96000);
+        assert!((peak(&out[150000..]) - 8192).abs() < 100, "{}", peak(&out[150000..]));
+    }
+
+    #[test]
+    fn ui_does_not_duck_music() {
+        let mut mixer = Mixer::new(48000);
+        mixer.play(Bus::Music, Box::new(Sine::new(100.0, 0.5)));
+        mixer.play(Bus::Ui, Box::new(Sine::new(1000.0, 0.0)));
+        let out = mix_frames(&mut mixer, 48000, 48000);
+        assert!((peak(&out[48000..]) - 16384).abs() < 50, "{}", peak(&out[48000..]));
+    }
+
+    #[test]
+    fn master_volume_scales_everything() {
+        let mut mixer = Mixer::new(48000);
+        mixer.set_settings(settings_with(Bus::Master, 0.25, false));
+        mixer.play(Bus::Music, Box::new(Sine::new(100.0, 0.5)));
+        let out = mix_frames(&mut mixer, 4800, 4800);
+        assert!((peak(&out) - 4096).abs() < 50, "{}", peak(&out));
+    }
+}
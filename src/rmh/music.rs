@@ -0,0 +1,595 @@
+// Music streamed from Ogg Vorbis files. Songs are decoded a packet at a time
+// as the mixer asks for them, so only a few thousand samples of each track
+// are ever in memory. A MusicPlayer owns the current track and any still
+// fading out, and plays them through a single mixer voice. Tracks can also be
+// any other SoundSource, like a tracker module or a MIDI song, so fades and
+// pausing work the same for all music.
+
+use std::collections::VecDeque;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::sync::{Arc, Mutex};
+
+use lewton::inside_ogg::OggStreamReader;
+use log::debug;
+
+use super::mixer::SoundSource;
+
+pub trait ReadSeek: Read + Seek + Send {}
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+// Seeking in Ogg only gets us to a page boundary and the first packet after
+// a seek decodes to nothing, so we seek this many frames early and decode
+// forward to the exact loop start.
+const SEEK_PREROLL: u64 = 8192;
+
+pub struct OggStream {
+    // only None while being reopened
+    reader: Option<OggStreamReader<Box<dyn ReadSeek>>>,
+    channels: usize,
+    pub sample_rate: u32,
+    // in frames of the file; None loop_end means the end of the stream
+    loop_start: u64,
+    loop_end: Option<u64>,
+    looping: bool,
+    // frames decoded since the last page end; only then do we learn their
+    // absolute position
+    pending: Vec<f32>,
+    // frames before this one are thrown away (decoding towards a loop start)
+    skip_to: u64,
+    // a seek can land on a page that starts with the tail of a packet, which
+    // doesn't decode; errors are skipped until a packet after the seek does
+    seeked: bool,
+    // decoded stereo frames waiting to be read
+    decoded: VecDeque<f32>,
+    // whether anything was decoded since the last loop, so an empty loop
+    // doesn't spin forever
+    decoded_since_loop: bool,
+    finished: bool,
+    // resampling: fractional read position between decoded[0] and decoded[1]
+    frac: f64,
+}
+
+impl OggStream {
+    pub fn new(reader: Box<dyn ReadSeek>) -> Result<OggStream, String> {
+        let reader = OggStreamReader::new(reader).map_err(|e| format!("ogg: {}", e))?;
+        let channels = reader.ident_hdr.audio_channels as usize;
+        if channels == 0 {
+            return Err("ogg: no channels".to_string());
+        }
+        let sample_rate = reader.ident_hdr.audio_sample_rate;
+
+        // the LOOPSTART / LOOPLENGTH comments many tools and engines use
+        let comment = |name: &str| -> Option<u64> {
+            reader.comment_hdr.comment_list.iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .and_then(|(_, value)| value.trim().parse().ok())
+        };
+        let loop_start = comment("LOOPSTART");
+        let loop_end = match (loop_start, comment("LOOPLENGTH"), comment("LOOPEND")) {
+            (Some(start), Some(length), _) => Some(start + length),
+            (_, _, end) => end,
+        };
+
+        Ok(OggStream {
+            reader: Some(reader),
+            channels,
+            sample_rate,
+            loop_start: loop_start.unwrap_or(0),
+            loop_end,
+            looping: loop_start.is_some(),
+            pending: Vec::new(),
+            skip_to: 0,
+            seeked: false,
+            decoded: VecDeque::new(),
+            decoded_since_loop: false,
+            finished: false,
+            frac: 0.0,
+        })
+    }
+
+    pub fn open(path: &std::path::Path) -> Result<OggStream, String> {
+        let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        OggStream::new(Box::new(BufReader::new(file))).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Loops from `end` (or the end of the file) back to `start`, in frames,
+    // overriding the file's own loop comments.
+    #[cfg(test)]
+    pub fn set_loop(&mut self, start: u64, end: Option<u64>) {
+        self.loop_start = start;
+        self.loop_end = end;
+        self.looping = true;
+    }
+
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished && self.decoded.is_empty()
+    }
+
+    fn restart_at(&mut self, frame: u64) -> Result<(), String> {
+        if !self.decoded_since_loop {
+            return Err("ogg: loop contains no audio".to_string());
+        }
+        self.decoded_since_loop = false;
+
+        let mut reader = self.reader.take().unwrap();
+        if frame < SEEK_PREROLL * 2 {
+            // close enough to the start to just decode from there
+            let mut inner = reader.into_inner().into_inner();
+            inner.seek(SeekFrom::Start(0)).map_err(|e| format!("ogg: {}", e))?;
+            reader = OggStreamReader::new(inner).map_err(|e| format!("ogg: {}", e))?;
+        } else {
+            reader.seek_absgp_pg(frame - SEEK_PREROLL).map_err(|e| format!("ogg: {}", e))?;
+            self.seeked = true;
+        }
+        self.reader = Some(reader);
+        self.pending.clear();
+        self.skip_to = frame;
+        Ok(())
+    }
+
+    // Decodes one packet into `decoded`, looping or finishing at the end.
+    fn decode_packet(&mut self) -> Result<(), String> {
+        let reader = self.reader.as_mut().unwrap();
+        let packet = match reader.read_dec_packet_itl() {
+            Ok(packet) => packet,
+            Err(e) if self.seeked => {
+                debug!("ogg: skipped a packet after seeking: {}", e);
+                return Ok(());
+            }
+            Err(e) => return Err(format!("ogg: {}", e)),
+        };
+        self.seeked = false;
+        let samples = match packet {
+            Some(samples) => samples,
+            None => {
+                if self.looping {
+                    return self.restart_at(self.loop_start);
+                }
+                self.finished = true;
+                return Ok(());
+            }
+        };
+
+        for frame in samples.chunks(self.channels) {
+            let left = frame[0] as f32 / 32768.0;
+            let right = frame.get(1).map(|&s| s as f32 / 32768.0).unwrap_or(left);
+            self.pending.push(left);
+            self.pending.push(right);
+        }
+        let end = match reader.get_last_absgp() {
+            Some(end) => end,
+            None => return Ok(()),
+        };
+
+        // pending now ends at `end`
+        let n_frames = (self.pending.len() / 2) as u64;
+        let start = end.saturating_sub(n_frames);
+        let skip = self.skip_to.saturating_sub(start).min(n_frames) as usize;
+        let mut keep = n_frames as usize;
+        let mut loop_now = false;
+        if let (true, Some(loop_end)) = (self.looping, self.loop_end) {
+            if end >= loop_end {
+                keep = loop_end.saturating_sub(start).min(n_frames) as usize;
+                loop_now = true;
+            }
+        }
+        if keep > skip {
+            self.decoded.extend(self.pending[skip * 2..keep * 2].iter());
+            self.decoded_since_loop = true;
+        }
+        self.pending.clear();
+
+        if loop_now {
+            self.restart_at(self.loop_start)?;
+        }
+        Ok(())
+    }
+
+    // Fills `out` (interleaved stereo at `sample_rate`) and returns how many
+    // frames it wrote, fewer than asked only once the stream has ended.
+    // Rates other than the file's are linearly interpolated.
+    pub fn read(&mut self, out: &mut [f32], sample_rate: u32) -> Result<usize, String> {
+        let step = self.sample_rate as f64 / sample_rate as f64;
+        let mut written = 0;
+        for frame in out.chunks_mut(2) {
+            // two frames to interpolate between, or one at the very end
+            loop {
+                while self.decoded.len() < 4 && !self.finished {
+                    self.decode_packet()?;
+                }
+                if self.frac < 1.0 || self.decoded.len() < 2 {
+                    break;
+                }
+                self.decoded.drain(..2);
+                self.frac -= 1.0;
+            }
+            if self.decoded.len() < 2 {
+                break;
+            }
+            let next = |i: usize| self.decoded.get(i + 2).copied().unwrap_or(self.decoded[i]);
+            let t = self.frac as f32;
+            frame[0] = self.decoded[0] + (next(0) - self.decoded[0]) * t;
+            frame[1] = self.decoded[1] + (next(1) - self.decoded[1]) * t;
+            written += 1;
+
+            self.frac += step;
+        }
+        Ok(written)
+    }
+}
+
+enum Track {
+    Stream(Box<OggStream>),
+    Source(Box<dyn SoundSource>),
+}
+
+// A track with its own volume ramp.
+struct Deck {
+    track: Track,
+    gain: f32,
+    target: f32,
+    // gain change per frame
+    step: f32,
+}
+
+impl Deck {
+    fn fade_to(&mut self, target: f32, frames: u32) {
+        self.target = target;
+        self.step = (target - self.gain).abs() / frames.max(1) as f32;
+    }
+
+    // Adds into `out`, returning false once the track is done: finished,
+    // failed or faded out.
+    fn render(&mut self, out: &mut [f32], scratch: &mut Vec<f32>, sample_rate: u32) -> bool {
+        scratch.clear();
+        scratch.resize(out.len(), 0.0);
+        let (frames, more) = match &mut self.track {
+            Track::Stream(stream) => match stream.read(scratch, sample_rate) {
+                Ok(frames) => (frames, !stream.is_finished()),
+                Err(e) => {
+                    debug!("music: {}", e);
+                    return false;
+                }
+            },
+            Track::Source(source) => (out.len() / 2, source.render(scratch, sample_rate)),
+        };
+        for (o, s) in out.chunks_mut(2).zip(scratch.chunks(2)).take(frames) {
+            if self.gain < self.target {
+                self.gain = (self.gain + self.step).min(self.target);
+            } else if self.gain > self.target {
+                self.gain = (self.gain - self.step).max(self.target);
+            }
+            o[0] += s[0] * self.gain;
+            o[1] += s[1] * self.gain;
+        }
+        let faded_out = self.target == 0.0 && self.gain == 0.0;
+        !faded_out && more
+    }
+}
+
+// Pausing ramps the output instead of cutting it, which would click.
+const PAUSE_FADE_SECONDS: f32 = 0.02;
+
+struct PlayerState {
+    sample_rate: u32,
+    current: Option<Deck>,
+    // each keeps its own ramp down, so switching again mid-fade doesn't cut
+    // one off
+    fading_out: Vec<Deck>,
+    paused: bool,
+    pause_gain: f32,
+    mix: Vec<f32>,
+    scratch: Vec<f32>,
+}
+
+impl PlayerState {
+    // Starts the current track's fade out, from whatever gain it's at. With
+    // no fade everything stops at once.
+    fn fade_out_current(&mut self, frames: u32) {
+        if frames == 0 {
+            self.current = None;
+            self.fading_out.clear();
+            return;
+        }
+        if let Some(mut old) = self.current.take() {
+            old.fade_to(0.0, frames);
+            self.fading_out.push(old);
+        }
+    }
+}
+
+// Shared between the game, which controls it, and the mixer voice that
+// renders it.
+#[derive(Clone)]
+pub struct MusicPlayer {
+    state: Arc<Mutex<PlayerState>>,
+}
+
+impl MusicPlayer {
+    pub fn new(sample_rate: u32) -> MusicPlayer {
+        MusicPlayer {
+            state: Arc::new(Mutex::new(PlayerState {
+                sample_rate,
+                current: None,
+                fading_out: Vec::new(),
+                paused: false,
+                pause_gain: 1.0,
+                mix: Vec::new(),
+                scratch: Vec::new(),
+            })),
+        }
+    }
+
+    // The mixer voice that plays whatever this player has on. Play it once;
+    // it never finishes by itself.
+    pub fn voice(&self) -> Box<dyn SoundSource> {
+        Box::new(MusicVoice { player: self.clone() })
+    }
+
+    // Switches to `stream`, crossfading from the current track over
+    // `crossfade_seconds` (0 cuts straight over).
+    pub fn play(&self, stream: OggStream, crossfade_seconds: f32) {
+        self.play_track(Track::Stream(Box::new(stream)), crossfade_seconds);
+    }
+
+    // Like play, for music rendered by anything else.
+    pub fn play_source(&self, source: Box<dyn SoundSource>, crossfade_seconds: f32) {
+        self.play_track(Track::Source(source), crossfade_seconds);
+    }
+
+    fn play_track(&self, track: Track, crossfade_seconds: f32) {
+        let mut state = self.state.lock().unwrap();
+        let frames = (crossfade_seconds * state.sample_rate as f32) as u32;
+        let mut deck = Deck {
+            track,
+            gain: 1.0,
+            target: 1.0,
+            step: 0.0,
+        };
+        if frames > 0 {
+            deck.gain = 0.0;
+            deck.fade_to(1.0, frames);
+        }
+        state.fade_out_current(frames);
+        state.current = Some(deck);
+    }
+
+    #[cfg(test)]
+    pub fn stop(&self, fade_seconds: f32) {
+        let mut state = self.state.lock().unwrap();
+        let frames = (fade_seconds * state.sample_rate as f32) as u32;
+        state.fade_out_current(frames);
+    }
+
+    pub fn pause(&self) {
+        self.state.lock().unwrap().paused = true;
+    }
+
+    pub fn resume(&self) {
+        self.state.lock().unwrap().paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().paused
+    }
+
+    #[cfg(test)]
+    pub fn is_playing(&self) -> bool {
+        self.state.lock().unwrap().current.is_some()
+    }
+}
+
+struct MusicVoice {
+    player: MusicPlayer,
+}
+
+impl SoundSource for MusicVoice {
+    fn render(&mut self, out: &mut [f32], sample_rate: u32) -> bool {
+        let mut guard = self.player.state.lock().unwrap();
+        let state = &mut *guard;
+        if state.paused && state.pause_gain == 0.0 {
+            // hold position: nothing is decoded while paused
+            return true;
+        }
+
+        state.mix.clear();
+        state.mix.resize(out.len(), 0.0);
+        let done = match state.current.as_mut() {
+            Some(deck) => !deck.render(&mut state.mix, &mut state.scratch, sample_rate),
+            None => false,
+        };
+        if done {
+            state.current = None;
+        }
+        let mut i = 0;
+        while i < state.fading_out.len() {
+            if state.fading_out[i].render(&mut state.mix, &mut state.scratch, sample_rate) {
+                i += 1;
+            } else {
+                state.fading_out.remove(i);
+            }
+        }
+
+        let pause_step = 1.0 / (PAUSE_FADE_SECONDS * sample_rate as f32);
+        let pause_target = if state.paused { 0.0 } else { 1.0 };
+        for (o, m) in out.chunks_mut(2).zip(state.mix.chunks(2)) {
+            if state.pause_gain < pause_target {
+                state.pause_gain = (state.pause_gain + pause_step).min(1.0);
+            } else if state.pause_gain > pause_target {
+                state.pause_gain = (state.pause_gain - pause_step).max(0.0);
+            }
+            o[0] += m[0] * state.pause_gain;
+            o[1] += m[1] * state.pause_gain;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a 10 second mono beep at 44100Hz, from rodio's examples
+    fn beep() -> OggStream {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/beep.ogg");
+        OggStream::open(&path).unwrap()
+    }
+
+    // Reads up to `max_frames` at the file's own rate.
+    fn read_frames(stream: &mut OggStream, max_frames: usize) -> Vec<f32> {
+        let rate = stream.sample_rate;
+        let mut all = Vec::new();
+        while all.len() < max_frames * 2 {
+            let mut buffer = vec![0.0; 1000];
+            let frames = stream.read(&mut buffer, rate).unwrap();
+            all.extend_from_slice(&buffer[..frames * 2]);
+            if frames < 500 {
+                break;
+            }
+        }
+        all.truncate(max_frames * 2);
+        all
+    }
+
+    #[test]
+    fn decodes_whole_file() {
+        let mut stream = beep();
+        assert_eq!(stream.sample_rate, 44100);
+        let all = read_frames(&mut stream, usize::MAX / 2);
+        assert_eq!(all.len() / 2, 441000);
+        assert!(stream.is_finished());
+    }
+
+    fn assert_loops(start: usize, end: usize) {
+        let full = read_frames(&mut beep(), end);
+        let mut stream = beep();
+        stream.set_loop(start as u64, Some(end as u64));
+        let length = end - start;
+        let looped = read_frames(&mut stream, end + length * 3);
+        assert_eq!(looped[..end * 2], full[..end * 2]);
+        for pass in 0..3 {
+            let at = (end + length * pass) * 2;
+            assert!(
+                looped[at..at + length * 2] == full[start * 2..end * 2],
+                "loop {}..{} differs on pass {}",
+                start,
+                end,
+                pass
+            );
+        }
+    }
+
+    #[test]
+    fn loop_is_sample_exact() {
+        // reopened from the start of the file
+        assert_loops(100, 5000);
+        // reached by seeking, which lands mid-packet in this file
+        assert_loops(100_000, 150_000);
+        // crossing page boundaries
+        assert_loops(20_000, 230_000);
+    }
+
+    #[test]
+    fn loops_whole_file() {
+        let full = read_frames(&mut beep(), 441_000);
+        let mut stream = beep();
+        stream.set_looping(true);
+        let looped = read_frames(&mut stream, 441_000 * 2 + 10);
+        assert!(looped[441_000 * 2..441_000 * 4] == full[..]);
+        assert!(!stream.is_finished());
+    }
+
+    #[test]
+    fn resamples_to_mixer_rate() {
+        let mut stream = beep();
+        let mut all = Vec::new();
+        loop {
+            let mut buffer = vec![0.0; 2000];
+            let frames = stream.read(&mut buffer, 48000).unwrap();
+            all.extend_from_slice(&buffer[..frames * 2]);
+            if frames < 1000 {
+                break;
+            }
+        }
+        let expected = 441_000 * 48000 / 44100;
+        assert!((all.len() / 2).abs_diff(expected) < 4, "{}", all.len() / 2);
+    }
+
+    fn render(voice: &mut Box<dyn SoundSource>, frames: usize) -> Vec<f32> {
+        let mut out = vec![0.0; frames * 2];
+        voice.render(&mut out, 44100);
+        out
+    }
+
+    #[test]
+    fn switching_mid_fade_keeps_fading_tracks() {
+        let player = MusicPlayer::new(44100);
+        let mut voice = player.voice();
+        player.play(beep(), 0.0);
+        render(&mut voice, 4410);
+
+        player.play(beep(), 0.5);
+        render(&mut voice, 4410);
+        let gain = player.state.lock().unwrap().fading_out[0].gain;
+        assert!((gain - 0.8).abs() < 0.01, "{}", gain);
+
+        // the first track carries on down from where it was
+        player.play(beep(), 0.5);
+        {
+            let state = player.state.lock().unwrap();
+            assert_eq!(state.fading_out.len(), 2);
+            assert_eq!(state.fading_out[0].gain, gain);
+        }
+        render(&mut voice, 22050);
+        assert_eq!(player.state.lock().unwrap().fading_out.len(), 0);
+        assert!(player.is_playing());
+
+        player.stop(0.0);
+        assert!(!player.is_playing());
+        assert!(render(&mut voice, 100).iter().all(|&s| s == 0.0));
+    }
+
+    // Pauses `player` 1000 frames in and resumes after the fade, checking
+    // the output against `expected`, what it plays without pausing.
+    fn assert_pause_holds_position(player: &MusicPlayer, voice: &mut Box<dyn SoundSource>, expected: &[f32]) {
+        let step = 1.0 / (PAUSE_FADE_SECONDS * 44100.0);
+        let assert_ramped = |out: &[f32], expected: &[f32], gain: &dyn Fn(usize) -> f32| {
+            for (i, (o, e)) in out.chunks(2).zip(expected.chunks(2)).enumerate() {
+                let g = gain(i + 1).clamp(0.0, 1.0);
+                assert!((o[0] - e[0] * g).abs() < 1e-4 && (o[1] - e[1] * g).abs() < 1e-4, "frame {}", i);
+            }
+        };
+
+        assert!(render(voice, 1000)[..] == expected[..2000]);
+        player.pause();
+        // the fade out carries on from frame 1000 and the stream stops
+        // wherever it reaches silence, here at the end of this render
+        assert_ramped(&render(voice, 2000), &expected[2000..6000], &|i| 1.0 - i as f32 * step);
+        assert!(render(voice, 1000).iter().all(|&s| s == 0.0));
+        player.resume();
+        assert!(player.is_playing() && !player.is_paused());
+        assert_ramped(&render(voice, 1000), &expected[6000..8000], &|i| i as f32 * step);
+    }
+
+    #[test]
+    fn pause_holds_position() {
+        let player = MusicPlayer::new(44100);
+        let mut voice = player.voice();
+        player.play(beep(), 0.0);
+        assert_pause_holds_position(&player, &mut voice, &read_frames(&mut beep(), 4000));
+    }
+
+    #[test]
+    fn pauses_other_sources() {
+        let player = MusicPlayer::new(44100);
+        let mut voice = player.voice();
+        player.play_source(Box::new(crate::rmh::mixer::Sine::new(441.0, 0.5)), 0.0);
+        let mut expected = vec![0.0; 8000];
+        crate::rmh::mixer::Sine::new(441.0, 0.5).render(&mut expected, 44100);
+        assert_pause_holds_position(&player, &mut voice, &expected);
+    }
+}
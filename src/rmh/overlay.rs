@@ -0,0 +1,231 @@
+// Debug overlays drawn over the game's frame: frame timings and the sound
+// card's cursors. Only the Windows layer has a window to show them in.
+
+use super::audio::CursorSnapshot;
+use super::perf::{self, FrameHistory, FRAME_HISTORY_LEN};
+use super::render;
+use super::text::{self, GlyphSource};
+
+const UPDATE_COLOR: u32 = 0xFF4C_AF50;
+const RENDER_COLOR: u32 = 0xFF21_96F3;
+const AUDIO_COLOR: u32 = 0xFFFF_C107;
+const PRESENT_COLOR: u32 = 0xFFE9_1E63;
+const OTHER_COLOR: u32 = 0xFF60_6060;
+
+// Stacked bar per frame (update, render, audio, present, and whatever is left
+// of the frame total on top) with a line at target_ms, plus min/avg/max.
+pub fn draw_perf_overlay(
+    mem: &mut [u32],
+    w: i32,
+    h: i32,
+    font: &mut dyn GlyphSource,
+    history: &FrameHistory,
+    target_ms: f32,
+) {
+    let bar_width = 2;
+    let graph_width = FRAME_HISTORY_LEN as i32 * bar_width;
+    let graph_height = 80;
+    let line_height = font.line_height();
+    let padding = 4;
+
+    let x0 = padding;
+    let y0 = padding;
+    let panel_height = graph_height + line_height * 6 + padding * 3;
+    render::draw_rect(mem, w, h, x0 - padding, y0 - padding, x0 + graph_width + padding, y0 + panel_height, 0xC000_0000);
+
+    let colors = [0xFFFF_FFFF, UPDATE_COLOR, RENDER_COLOR, AUDIO_COLOR, PRESENT_COLOR];
+    let mut y = y0;
+    text::draw_text(mem, w, h, font, x0, y, perf::SUMMARY_HEADER, 0xFFA0_A0A0);
+    y += line_height;
+    for (line, color) in perf::summary(history).iter().zip(colors.iter()) {
+        text::draw_text(mem, w, h, font, x0, y, line, *color);
+        y += line_height;
+    }
+
+    let graph_bottom = y + padding + graph_height;
+    let ms_to_px = graph_height as f32 / (target_ms * 2.0).max(1.0);
+
+    for (i, t) in history.iter().enumerate() {
+        let x = x0 + i as i32 * bar_width;
+        let mut bottom = graph_bottom as f32;
+        let mut stack = |ms: f32, color: u32| {
+            let top = (bottom - ms.max(0.0) * ms_to_px).max((graph_bottom - graph_height) as f32);
+            render::draw_rect(mem, w, h, x, top as i32, x + bar_width, bottom as i32, color);
+            bottom = top;
+        };
+        stack(t.update_ms, UPDATE_COLOR);
+        stack(t.render_ms, RENDER_COLOR);
+        stack(t.audio_ms, AUDIO_COLOR);
+        stack(t.present_ms, PRESENT_COLOR);
+        stack(t.total_ms - t.update_ms - t.render_ms - t.audio_ms - t.present_ms, OTHER_COLOR);
+    }
+
+    let target_y = graph_bottom - (target_ms * ms_to_px) as i32;
+    render::draw_rect(mem, w, h, x0, target_y, x0 + graph_width, target_y + 1, 0xFFFF_FFFF);
+}
+
+pub const AUDIO_SYNC_HISTORY_LEN: usize = 30;
+
+// Cursor snapshots of the last AUDIO_SYNC_HISTORY_LEN frames, for the audio
+// sync view.
+pub struct AudioSyncHistory {
+    snapshots: Vec<CursorSnapshot>,
+    next: usize,
+}
+
+impl AudioSyncHistory {
+    pub fn new() -> AudioSyncHistory {
+        AudioSyncHistory {
+            snapshots: Vec::with_capacity(AUDIO_SYNC_HISTORY_LEN),
+            next: 0,
+        }
+    }
+
+    pub fn push(&mut self, snapshot: CursorSnapshot) {
+        if self.snapshots.len() < AUDIO_SYNC_HISTORY_LEN {
+            self.snapshots.push(snapshot);
+        } else {
+            self.snapshots[self.next] = snapshot;
+        }
+        self.next = (self.next + 1) % AUDIO_SYNC_HISTORY_LEN;
+    }
+
+    // oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &CursorSnapshot> {
+        let split = if self.snapshots.len() < AUDIO_SYNC_HISTORY_LEN { 0 } else { self.next };
+        self.snapshots[split..].iter().chain(self.snapshots[..split].iter())
+    }
+}
+
+impl Default for AudioSyncHistory {
+    fn default() -> Self {
+        AudioSyncHistory::new()
+    }
+}
+
+const PLAY_CURSOR_COLOR: u32 = 0xFFFF_FFFF;
+const WRITE_CURSOR_COLOR: u32 = 0xFFFF_4040;
+const TRACKER_COLOR: u32 = 0xFF40_FF40;
+const WRITTEN_COLOR: u32 = 0x80FF_C107;
+const FLIP_COLOR: u32 = 0xFF40_A0FF;
+
+// One row per frame, newest at the bottom. The row spans the whole device
+// buffer; the written region is shaded and the play cursor, write cursor, our
+// sample tracker and the expected flip position are drawn as ticks.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_audio_sync(
+    mem: &mut [u32],
+    w: i32,
+    h: i32,
+    font: &mut dyn GlyphSource,
+    history: &AudioSyncHistory,
+    buf_size_bytes: u32,
+    x0: i32,
+    y0: i32,
+    width: i32,
+) {
+    let row_height = 4;
+    let padding = 4;
+    let line_height = font.line_height();
+    let n_rows = AUDIO_SYNC_HISTORY_LEN as i32;
+    let panel_height = line_height + padding + n_rows * row_height;
+
+    render::draw_rect(mem, w, h, x0 - padding, y0 - padding, x0 + width + padding, y0 + panel_height + padding, 0xC000_0000);
+
+    let legend = [("play ", PLAY_CURSOR_COLOR), ("write ", WRITE_CURSOR_COLOR), ("tracker ", TRACKER_COLOR), ("flip ", FLIP_COLOR), ("written", WRITTEN_COLOR | 0xFF00_0000)];
+    let mut x = x0;
+    for (label, color) in &legend {
+        let (label_width, _) = text::draw_text(mem, w, h, font, x, y0, label, *color);
+        x += label_width;
+    }
+
+    let to_x = |byte: u32| x0 + (byte as u64 * width as u64 / buf_size_bytes.max(1) as u64) as i32;
+    let mut y = y0 + line_height + padding;
+    for s in history.iter() {
+        render::draw_rect(mem, w, h, x0, y, x0 + width, y + row_height - 1, 0xFF30_3030);
+
+        // the written region may wrap around the end of the buffer
+        let end = s.byte_to_lock + s.bytes_to_write;
+        if end <= buf_size_bytes {
+            render::draw_rect(mem, w, h, to_x(s.byte_to_lock), y, to_x(end), y + row_height - 1, WRITTEN_COLOR);
+        } else {
+            render::draw_rect(mem, w, h, to_x(s.byte_to_lock), y, x0 + width, y + row_height - 1, WRITTEN_COLOR);
+            render::draw_rect(mem, w, h, x0, y, to_x(end - buf_size_bytes), y + row_height - 1, WRITTEN_COLOR);
+        }
+
+        for (byte, color) in &[(s.play_cursor, PLAY_CURSOR_COLOR), (s.write_cursor, WRITE_CURSOR_COLOR), (s.byte_to_lock, TRACKER_COLOR), (s.expected_flip_byte, FLIP_COLOR)] {
+            let cx = to_x(*byte);
+            render::draw_rect(mem, w, h, cx, y - 1, cx + 1, y + row_height, *color);
+        }
+        y += row_height;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rmh::perf::FrameTimings;
+    use crate::rmh::text::BitmapFont;
+
+    const W: i32 = 320;
+    const H: i32 = 200;
+
+    #[test]
+    fn perf_overlay_stacks_each_frame_under_the_target_line() {
+        let mut font = BitmapFont::builtin();
+        let mut history = FrameHistory::new();
+        for _ in 0..10 {
+            history.push(FrameTimings { total_ms: 16.0, update_ms: 4.0, render_ms: 4.0, audio_ms: 2.0, present_ms: 1.0 });
+        }
+        let mut mem = vec![0xFF00_0000; (W * H) as usize];
+        draw_perf_overlay(&mut mem, W, H, &mut font, &history, 20.0);
+
+        let pixel = |x: i32, y: i32| mem[(y * W + x) as usize];
+        let graph_bottom = 4 + font.line_height() * 6 + 4 + 80;
+        // 2px per ms at a 20ms target: update fills the bottom 8 rows
+        assert_eq!(pixel(4, graph_bottom - 1), UPDATE_COLOR);
+        assert_eq!(pixel(5, graph_bottom - 8), UPDATE_COLOR);
+        assert_eq!(pixel(4, graph_bottom - 9), RENDER_COLOR);
+        assert_eq!(pixel(4, graph_bottom - 31), OTHER_COLOR);
+        // no bars past the frames we have, but the target line goes all the way
+        assert_ne!(pixel(24, graph_bottom - 1), UPDATE_COLOR);
+        assert_eq!(pixel(4 + FRAME_HISTORY_LEN as i32 * 2 - 1, graph_bottom - 40), 0xFFFF_FFFF);
+    }
+
+    #[test]
+    fn audio_sync_shades_the_written_region_across_the_wrap() {
+        let mut font = BitmapFont::builtin();
+        let mut history = AudioSyncHistory::new();
+        for i in 0..=AUDIO_SYNC_HISTORY_LEN as u32 {
+            history.push(CursorSnapshot { play_cursor: i, ..CursorSnapshot::default() });
+        }
+        // oldest first, the first one dropped
+        assert_eq!(history.iter().count(), AUDIO_SYNC_HISTORY_LEN);
+        assert_eq!(history.iter().next().unwrap().play_cursor, 1);
+
+        let mut history = AudioSyncHistory::new();
+        history.push(CursorSnapshot {
+            play_cursor: 200,
+            write_cursor: 400,
+            byte_to_lock: 800,
+            bytes_to_write: 400,
+            expected_flip_byte: 600,
+            ..CursorSnapshot::default()
+        });
+        let mut mem = vec![0xFF00_0000; (W * H) as usize];
+        // 1000 bytes over 100px
+        draw_audio_sync(&mut mem, W, H, &mut font, &history, 1000, 10, 10, 100);
+
+        let row = 10 + font.line_height() + 4;
+        let pixel = |x: i32| mem[((row + 1) * W + x) as usize];
+        assert_eq!(pixel(10 + 20), PLAY_CURSOR_COLOR);
+        assert_eq!(pixel(10 + 40), WRITE_CURSOR_COLOR);
+        assert_eq!(pixel(10 + 60), FLIP_COLOR);
+        assert_eq!(pixel(10 + 80), TRACKER_COLOR);
+        // written from 800 to the end and on from the start up to 200
+        let unwritten = pixel(10 + 70);
+        assert_ne!(pixel(10 + 90), unwritten);
+        assert_ne!(pixel(10 + 10), unwritten);
+        assert_eq!(pixel(10 + 90), pixel(10 + 10));
+    }
+}
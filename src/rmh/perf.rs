@@ -0,0 +1,86 @@
+pub const FRAME_HISTORY_LEN: usize = 120;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameTimings {
+    pub total_ms: f32,
+    pub update_ms: f32,
+    pub render_ms: f32,
+    pub audio_ms: f32,
+    pub present_ms: f32,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Stats {
+    pub min: f32,
+    pub avg: f32,
+    pub max: f32,
+}
+
+// Rolling window over the last FRAME_HISTORY_LEN frames.
+pub struct FrameHistory {
+    frames: Vec<FrameTimings>,
+    next: usize,
+}
+
+impl FrameHistory {
+    pub fn new() -> FrameHistory {
+        FrameHistory {
+            frames: Vec::with_capacity(FRAME_HISTORY_LEN),
+            next: 0,
+        }
+    }
+
+    pub fn push(&mut self, timings: FrameTimings) {
+        if self.frames.len() < FRAME_HISTORY_LEN {
+            self.frames.push(timings);
+        } else {
+            self.frames[self.next] = timings;
+        }
+        self.next = (self.next + 1) % FRAME_HISTORY_LEN;
+    }
+
+    // oldest first
+    #[cfg(any(target_os="windows", test))]
+    pub fn iter(&self) -> impl Iterator<Item = &FrameTimings> {
+        let split = if self.frames.len() < FRAME_HISTORY_LEN { 0 } else { self.next };
+        self.frames[split..].iter().chain(self.frames[..split].iter())
+    }
+
+    pub fn stats(&self, field: impl Fn(&FrameTimings) -> f32) -> Stats {
+        if self.frames.is_empty() {
+            return Stats::default();
+        }
+        let mut stats = Stats { min: f32::MAX, avg: 0.0, max: f32::MIN };
+        for t in &self.frames {
+            let v = field(t);
+            stats.min = stats.min.min(v);
+            stats.max = stats.max.max(v);
+            stats.avg += v;
+        }
+        stats.avg /= self.frames.len() as f32;
+        stats
+    }
+}
+
+impl Default for FrameHistory {
+    fn default() -> Self {
+        FrameHistory::new()
+    }
+}
+
+pub const SUMMARY_HEADER: &str = "ms        min   avg   max";
+
+// One line of min/avg/max per timing, in the order of FrameTimings' fields.
+pub fn summary(history: &FrameHistory) -> [String; 5] {
+    [
+        stats_line("frame  ", history.stats(|t| t.total_ms)),
+        stats_line("update ", history.stats(|t| t.update_ms)),
+        stats_line("render ", history.stats(|t| t.render_ms)),
+        stats_line("audio  ", history.stats(|t| t.audio_ms)),
+        stats_line("present", history.stats(|t| t.present_ms)),
+    ]
+}
+
+fn stats_line(label: &str, s: Stats) -> String {
+    format!("{} {:5.1} {:5.1} {:5.1}", label, s.min, s.avg, s.max)
+}
@@ -0,0 +1,200 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// Number of finished frames kept around for export.
+pub const MAX_FRAMES: usize = 300;
+
+#[derive(Clone, Copy, Debug)]
+pub struct TimedEvent {
+    pub name: &'static str,
+    pub thread_id: u32,
+    pub begin: Instant,
+    pub end: Instant,
+}
+
+struct Recorder {
+    frames: Vec<Arc<[TimedEvent]>>,
+    next_frame: usize,
+}
+
+static RECORDER: Mutex<Recorder> = Mutex::new(Recorder {
+    frames: Vec::new(),
+    next_frame: 0,
+});
+// Every thread that has timed something records into its own buffer, so a
+// block only ever waits on frame_end, never on another thread's blocks.
+static THREADS: Mutex<Vec<Arc<Mutex<Vec<TimedEvent>>>>> = Mutex::new(Vec::new());
+static NEXT_THREAD_ID: AtomicU32 = AtomicU32::new(1);
+
+thread_local! {
+    static THREAD_ID: u32 = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+    static EVENTS: Arc<Mutex<Vec<TimedEvent>>> = {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        THREADS.lock().unwrap().push(events.clone());
+        events
+    };
+}
+
+// Records the time between its creation and drop. Use through timed_block!.
+pub struct TimedBlock {
+    name: &'static str,
+    begin: Instant,
+}
+
+impl TimedBlock {
+    pub fn new(name: &'static str) -> TimedBlock {
+        TimedBlock { name, begin: Instant::now() }
+    }
+}
+
+impl Drop for TimedBlock {
+    fn drop(&mut self) {
+        let end = Instant::now();
+        let thread_id = THREAD_ID.try_with(|id| *id).unwrap_or(0);
+        // gone while the thread exits; the event is lost with it
+        let _ = EVENTS.try_with(|events| {
+            events.lock().unwrap().push(TimedEvent {
+                name: self.name,
+                thread_id,
+                begin: self.begin,
+                end,
+            });
+        });
+    }
+}
+
+// Times the rest of the enclosing scope under `name`.
+#[macro_export]
+macro_rules! timed_block {
+    ($name:expr) => {
+        let _timed_block = $crate::rmh::profile::TimedBlock::new($name);
+    };
+}
+
+// Closes the events all threads recorded since the previous call into one
+// frame.
+pub fn frame_end() {
+    let mut frame = Vec::new();
+    {
+        let mut threads = THREADS.lock().unwrap();
+        for events in threads.iter() {
+            frame.append(&mut events.lock().unwrap());
+        }
+        // only the list still holds buffers of threads that have exited
+        threads.retain(|events| Arc::strong_count(events) > 1);
+    }
+    frame.sort_by_key(|e| e.begin);
+
+    let mut recorder = RECORDER.lock().unwrap();
+    let frame = Arc::from(frame);
+    if recorder.frames.len() < MAX_FRAMES {
+        recorder.frames.push(frame);
+    } else {
+        let next = recorder.next_frame;
+        recorder.frames[next] = frame;
+    }
+    recorder.next_frame = (recorder.next_frame + 1) % MAX_FRAMES;
+}
+
+// Finished frames, oldest first. Frames are shared, not copied.
+pub fn frames() -> Vec<Arc<[TimedEvent]>> {
+    let recorder = RECORDER.lock().unwrap();
+    let split = if recorder.frames.len() < MAX_FRAMES { 0 } else { recorder.next_frame };
+    recorder.frames[split..].iter().chain(recorder.frames[..split].iter()).cloned().collect()
+}
+
+// Chrome about:tracing / Perfetto JSON, one complete ("X") event per block
+// and an instant event marking the start of each frame. Timestamps are in
+// microseconds from the earliest recorded event.
+pub fn write_chrome_trace(out: &mut dyn Write, frames: &[Arc<[TimedEvent]>]) -> std::io::Result<()> {
+    let epoch = frames.iter().flat_map(|f| f.iter()).map(|e| e.begin).min();
+    let micros = |t: Instant| epoch.map_or(0, |epoch| t.duration_since(epoch).as_micros() as u64);
+
+    writeln!(out, "{{\"traceEvents\":[")?;
+    let mut first = true;
+    for (i, frame) in frames.iter().enumerate() {
+        let frame_start = frame.iter().map(|e| e.begin).min();
+        if let Some(start) = frame_start {
+            write!(
+                out,
+                "{}{{\"name\":\"frame {}\",\"ph\":\"i\",\"s\":\"g\",\"ts\":{},\"pid\":1,\"tid\":0}}",
+                if first { "" } else { ",\n" },
+                i,
+                micros(start)
+            )?;
+            first = false;
+        }
+        for e in frame.iter() {
+            write!(
+                out,
+                "{}{{\"name\":\"{}\",\"cat\":\"rmh\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":{}}}",
+                if first { "" } else { ",\n" },
+                escape_json(e.name),
+                micros(e.begin),
+                micros(e.end) - micros(e.begin),
+                e.thread_id
+            )?;
+            first = false;
+        }
+    }
+    writeln!(out, "\n]}}")
+}
+
+pub fn export_chrome_trace(path: &std::path::Path) -> std::io::Result<()> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    write_chrome_trace(&mut file, &frames())?;
+    file.flush()
+}
+
+fn escape_json(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn writes_chrome_trace_json() {
+        let epoch = Instant::now();
+        let at = |micros| epoch + Duration::from_micros(micros);
+        let event = |name, thread_id, begin, end| TimedEvent { name, thread_id, begin: at(begin), end: at(end) };
+        let frames: Vec<Arc<[TimedEvent]>> = vec![
+            Arc::from(vec![event("update", 1, 0, 100), event("audio pull", 2, 20, 50)]),
+            Arc::from(Vec::new()),
+            Arc::from(vec![event("say \"hi\"", 1, 1000, 1500)]),
+        ];
+
+        let mut out = Vec::new();
+        write_chrome_trace(&mut out, &frames).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"traceEvents\":[\n\
+             {\"name\":\"frame 0\",\"ph\":\"i\",\"s\":\"g\",\"ts\":0,\"pid\":1,\"tid\":0},\n\
+             {\"name\":\"update\",\"cat\":\"rmh\",\"ph\":\"X\",\"ts\":0,\"dur\":100,\"pid\":1,\"tid\":1},\n\
+             {\"name\":\"audio pull\",\"cat\":\"rmh\",\"ph\":\"X\",\"ts\":20,\"dur\":30,\"pid\":1,\"tid\":2},\n\
+             {\"name\":\"frame 2\",\"ph\":\"i\",\"s\":\"g\",\"ts\":1000,\"pid\":1,\"tid\":0},\n\
+             {\"name\":\"say \\\"hi\\\"\",\"cat\":\"rmh\",\"ph\":\"X\",\"ts\":1000,\"dur\":500,\"pid\":1,\"tid\":1}\n\
+             ]}\n"
+        );
+    }
+
+    #[test]
+    fn empty_trace_is_valid() {
+        let mut out = Vec::new();
+        write_chrome_trace(&mut out, &[]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "{\"traceEvents\":[\n\n]}\n");
+    }
+}
@@ -0,0 +1,435 @@
+// Framebuffer pixels are 0xAARRGGBB, the same layout win32_u32_argb builds.
+// Bitmaps store their pixels with premultiplied alpha.
+//
+// Stored pixels are sRGB. Anything that mixes colors (filtering, blending,
+// gradients) converts to linear first and back to sRGB when writing, using a
+// gamma 2 approximation (square on read, square root on write) which is close
+// enough to the real curve and much cheaper than powf per channel.
+
+use std::ops::{Add, Mul, Neg, Sub};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct V2 {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl V2 {
+    pub fn new(x: f32, y: f32) -> V2 {
+        V2 { x, y }
+    }
+
+    pub fn from_angle(radians: f32, len: f32) -> V2 {
+        V2 { x: radians.cos() * len, y: radians.sin() * len }
+    }
+
+    pub fn perp(self) -> V2 {
+        V2 { x: -self.y, y: self.x }
+    }
+}
+
+impl Add for V2 {
+    type Output = V2;
+    fn add(self, other: V2) -> V2 {
+        V2 { x: self.x + other.x, y: self.y + other.y }
+    }
+}
+
+impl Sub for V2 {
+    type Output = V2;
+    fn sub(self, other: V2) -> V2 {
+        V2 { x: self.x - other.x, y: self.y - other.y }
+    }
+}
+
+impl Mul<f32> for V2 {
+    type Output = V2;
+    fn mul(self, s: f32) -> V2 {
+        V2 { x: self.x * s, y: self.y * s }
+    }
+}
+
+impl Neg for V2 {
+    type Output = V2;
+    fn neg(self) -> V2 {
+        V2 { x: -self.x, y: -self.y }
+    }
+}
+
+pub struct Bitmap {
+    pub width: i32,
+    pub height: i32,
+    pub pixels: Vec<u32>,
+}
+
+impl Bitmap {
+    pub fn new(width: i32, height: i32) -> Bitmap {
+        Bitmap {
+            width,
+            height,
+            pixels: vec![0; (width * height) as usize],
+        }
+    }
+
+    // takes straight (non premultiplied) 0xAARRGGBB pixels
+    pub fn from_argb(width: i32, height: i32, argb: &[u32]) -> Bitmap {
+        debug_assert!(argb.len() == (width * height) as usize);
+        let pixels = argb.iter().map(|&p| premultiply(p)).collect();
+        Bitmap { width, height, pixels }
+    }
+
+    // uncompressed or RLE truecolor/grayscale TGA, the format BMFont and
+    // most texture tools can export
+    pub fn from_tga(bytes: &[u8]) -> Result<Bitmap, String> {
+        if bytes.len() < 18 {
+            return Err("tga: truncated header".to_string());
+        }
+        let id_len = bytes[0] as usize;
+        let color_map_type = bytes[1];
+        let image_type = bytes[2];
+        let width = u16::from_le_bytes([bytes[12], bytes[13]]) as i32;
+        let height = u16::from_le_bytes([bytes[14], bytes[15]]) as i32;
+        let bpp = bytes[16];
+        let descriptor = bytes[17];
+
+        if color_map_type != 0 {
+            return Err("tga: color mapped images are not supported".to_string());
+        }
+        let rle = match image_type {
+            2 | 3 => false,
+            10 | 11 => true,
+            _ => return Err(format!("tga: unsupported image type {}", image_type)),
+        };
+        let bytes_pp = match (image_type, bpp) {
+            (2, 24) | (10, 24) => 3,
+            (2, 32) | (10, 32) => 4,
+            (3, 8) | (11, 8) => 1,
+            _ => return Err(format!("tga: unsupported pixel depth {}", bpp)),
+        };
+
+        let read_pixel = |p: &[u8]| -> u32 {
+            match bytes_pp {
+                1 => 0xFF00_0000 | (p[0] as u32) << 16 | (p[0] as u32) << 8 | p[0] as u32,
+                3 => 0xFF00_0000 | (p[2] as u32) << 16 | (p[1] as u32) << 8 | p[0] as u32,
+                _ => (p[3] as u32) << 24 | (p[2] as u32) << 16 | (p[1] as u32) << 8 | p[0] as u32,
+            }
+        };
+
+        if width == 0 || height == 0 {
+            return Ok(Bitmap::new(width, height));
+        }
+
+        let n_pixels = (width * height) as usize;
+        let mut argb = Vec::with_capacity(n_pixels);
+        let mut data = bytes.get(18 + id_len..).ok_or("tga: truncated data")?;
+        while argb.len() < n_pixels {
+            if !rle {
+                let p = data.get(..bytes_pp).ok_or("tga: truncated data")?;
+                argb.push(read_pixel(p));
+                data = &data[bytes_pp..];
+                continue;
+            }
+            let packet = *data.first().ok_or("tga: truncated data")?;
+            data = &data[1..];
+            let count = (packet & 0x7F) as usize + 1;
+            if packet & 0x80 != 0 {
+                let p = data.get(..bytes_pp).ok_or("tga: truncated data")?;
+                let pixel = read_pixel(p);
+                argb.resize(argb.len() + count, pixel);
+                data = &data[bytes_pp..];
+            } else {
+                for _ in 0..count {
+                    let p = data.get(..bytes_pp).ok_or("tga: truncated data")?;
+                    argb.push(read_pixel(p));
+                    data = &data[bytes_pp..];
+                }
+            }
+        }
+        argb.truncate(n_pixels);
+
+        // rows are stored bottom-up unless bit 5 of the descriptor is set
+        if descriptor & 0x20 == 0 {
+            let row_len = width as usize;
+            let rows: Vec<&[u32]> = argb.chunks(row_len).rev().collect();
+            argb = rows.concat();
+        }
+
+        Ok(Bitmap::from_argb(width, height, &argb))
+    }
+
+    fn texel(&self, x: i32, y: i32) -> [f32; 4] {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return [0.0; 4];
+        }
+        srgb255_to_linear1(unpack(self.pixels[(y * self.width + x) as usize]))
+    }
+
+    // tx, ty are in texel space, with texel centers at integer + 0.5.
+    // Result is premultiplied linear color in 0..1.
+    fn sample_bilinear(&self, tx: f32, ty: f32) -> [f32; 4] {
+        let fx = tx - 0.5;
+        let fy = ty - 0.5;
+        let x0 = fx.floor();
+        let y0 = fy.floor();
+        let u = fx - x0;
+        let v = fy - y0;
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let a = self.texel(x0, y0);
+        let b = self.texel(x0 + 1, y0);
+        let c = self.texel(x0, y0 + 1);
+        let d = self.texel(x0 + 1, y0 + 1);
+
+        let mut out = [0.0; 4];
+        for i in 0..4 {
+            let top = a[i] + (b[i] - a[i]) * u;
+            let bottom = c[i] + (d[i] - c[i]) * u;
+            out[i] = top + (bottom - top) * v;
+        }
+        out
+    }
+}
+
+// alpha is applied in linear space, so filtering premultiplied texels later
+// gives the same result as filtering straight ones
+fn premultiply(argb: u32) -> u32 {
+    let [a, r, g, b] = srgb255_to_linear1(unpack(argb));
+    pack(linear1_to_srgb255([a, r * a, g * a, b * a]))
+}
+
+pub fn srgb255_to_linear1(c: [f32; 4]) -> [f32; 4] {
+    let inv_255 = 1.0 / 255.0;
+    let (r, g, b) = (c[1] * inv_255, c[2] * inv_255, c[3] * inv_255);
+    [c[0] * inv_255, r * r, g * g, b * b]
+}
+
+pub fn linear1_to_srgb255(c: [f32; 4]) -> [f32; 4] {
+    [
+        c[0] * 255.0,
+        c[1].max(0.0).sqrt() * 255.0,
+        c[2].max(0.0).sqrt() * 255.0,
+        c[3].max(0.0).sqrt() * 255.0,
+    ]
+}
+
+pub(crate) fn blend_linear(dst: [f32; 4], src: [f32; 4]) -> [f32; 4] {
+    let inv_a = 1.0 - src[0];
+    [
+        src[0] + dst[0] * inv_a,
+        src[1] + dst[1] * inv_a,
+        src[2] + dst[2] * inv_a,
+        src[3] + dst[3] * inv_a,
+    ]
+}
+
+pub(crate) fn unpack(argb: u32) -> [f32; 4] {
+    [
+        ((argb >> 24) & 0xFF) as f32,
+        ((argb >> 16) & 0xFF) as f32,
+        ((argb >> 8) & 0xFF) as f32,
+        (argb & 0xFF) as f32,
+    ]
+}
+
+pub(crate) fn pack(c: [f32; 4]) -> u32 {
+    let to_u8 = |v: f32| (v + 0.5).clamp(0.0, 255.0) as u32;
+    (to_u8(c[0]) << 24) | (to_u8(c[1]) << 16) | (to_u8(c[2]) << 8) | to_u8(c[3])
+}
+
+// Fills [x0, x1) x [y0, y1), blending with what is already there. `color` is
+// straight sRGB 0xAARRGGBB.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_rect(mem: &mut [u32], w: i32, h: i32, x0: i32, y0: i32, x1: i32, y1: i32, color: u32) {
+    let c = srgb255_to_linear1(unpack(color));
+    let src = [c[0], c[1] * c[0], c[2] * c[0], c[3] * c[0]];
+    let opaque = pack(linear1_to_srgb255(src));
+    for y in y0.max(0)..y1.min(h) {
+        for x in x0.max(0)..x1.min(w) {
+            let idx = (y * w + x) as usize;
+            if src[0] >= 1.0 {
+                mem[idx] = opaque;
+            } else {
+                let dst = srgb255_to_linear1(unpack(mem[idx]));
+                mem[idx] = pack(linear1_to_srgb255(blend_linear(dst, src)));
+            }
+        }
+    }
+}
+
+// Draws `bitmap` mapped onto the parallelogram spanned by x_axis and y_axis
+// starting at origin, so the same call covers translation, rotation, scaling
+// and shearing. Pixels are sampled at their centers, which keeps slow moving
+// sprites from snapping to whole pixels.
+pub fn draw_bitmap_quad(
+    mem: &mut [u32],
+    w: i32,
+    h: i32,
+    origin: V2,
+    x_axis: V2,
+    y_axis: V2,
+    bitmap: &Bitmap,
+) {
+    let det = x_axis.x * y_axis.y - x_axis.y * y_axis.x;
+    if det.abs() < f32::EPSILON || bitmap.width <= 0 || bitmap.height <= 0 {
+        return;
+    }
+
+    let corners = [origin, origin + x_axis, origin + y_axis, origin + x_axis + y_axis];
+    let mut min_x = f32::MAX;
+    let mut min_y = f32::MAX;
+    let mut max_x = f32::MIN;
+    let mut max_y = f32::MIN;
+    for c in &corners {
+        min_x = min_x.min(c.x);
+        min_y = min_y.min(c.y);
+        max_x = max_x.max(c.x);
+        max_y = max_y.max(c.y);
+    }
+
+    // one extra pixel on each side for the half texel of filtered edge
+    let x_start = ((min_x.floor() as i32) - 1).max(0);
+    let y_start = ((min_y.floor() as i32) - 1).max(0);
+    let x_end = ((max_x.ceil() as i32) + 1).min(w);
+    let y_end = ((max_y.ceil() as i32) + 1).min(h);
+
+    let bw = bitmap.width as f32;
+    let bh = bitmap.height as f32;
+
+    for y in y_start..y_end {
+        for x in x_start..x_end {
+            let d = V2::new(x as f32 + 0.5, y as f32 + 0.5) - origin;
+            let u = (d.x * y_axis.y - d.y * y_axis.x) / det;
+            let v = (x_axis.x * d.y - x_axis.y * d.x) / det;
+
+            let tx = u * bw;
+            let ty = v * bh;
+            if tx < -0.5 || ty < -0.5 || tx > bw + 0.5 || ty > bh + 0.5 {
+                continue;
+            }
+
+            let src = bitmap.sample_bilinear(tx, ty);
+            let idx = (y * w + x) as usize;
+            let dst = srgb255_to_linear1(unpack(mem[idx]));
+            mem[idx] = pack(linear1_to_srgb255(blend_linear(dst, src)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: u32 = 0xFF00_0000;
+
+    // opaque and every texel different
+    fn sprite(width: i32, height: i32) -> Bitmap {
+        let argb: Vec<u32> = (0..width * height).map(|i| 0xFF00_0000 | (i as u32 * 0x0F0D0B)).collect();
+        Bitmap::from_argb(width, height, &argb)
+    }
+
+    fn draw(w: i32, h: i32, origin: V2, x_axis: V2, y_axis: V2, bitmap: &Bitmap) -> Vec<u32> {
+        let mut mem = vec![BLACK; (w * h) as usize];
+        draw_bitmap_quad(&mut mem, w, h, origin, x_axis, y_axis, bitmap);
+        mem
+    }
+
+    #[test]
+    fn copies_texels_at_pixel_centers() {
+        let bitmap = sprite(2, 2);
+        let mem = draw(4, 4, V2::new(1.0, 1.0), V2::new(2.0, 0.0), V2::new(0.0, 2.0), &bitmap);
+        let expected = [
+            BLACK, BLACK, BLACK, BLACK,
+            BLACK, bitmap.pixels[0], bitmap.pixels[1], BLACK,
+            BLACK, bitmap.pixels[2], bitmap.pixels[3], BLACK,
+            BLACK, BLACK, BLACK, BLACK,
+        ];
+        assert_eq!(mem, expected);
+    }
+
+    #[test]
+    fn rotates_with_the_axes() {
+        // a quarter turn: u runs down the screen and v to the left
+        let bitmap = sprite(2, 2);
+        let mem = draw(4, 4, V2::new(3.0, 1.0), V2::new(0.0, 2.0), V2::new(-2.0, 0.0), &bitmap);
+        assert_eq!(mem[4 + 2], bitmap.pixels[0]);
+        assert_eq!(mem[4 + 1], bitmap.pixels[2]);
+        assert_eq!(mem[2 * 4 + 2], bitmap.pixels[1]);
+        assert_eq!(mem[2 * 4 + 1], bitmap.pixels[3]);
+    }
+
+    #[test]
+    fn filters_between_texels_in_linear_space() {
+        let bitmap = Bitmap::from_argb(2, 1, &[BLACK, 0xFFFF_FFFF]);
+        let c = bitmap.sample_bilinear(1.0, 0.5);
+        assert_eq!(c, [1.0, 0.5, 0.5, 0.5]);
+        // half a texel past the edge fades out to transparent
+        assert_eq!(bitmap.sample_bilinear(2.5, 0.5), [0.0; 4]);
+        assert_eq!(bitmap.sample_bilinear(2.0, 0.5)[0], 0.5);
+    }
+
+    #[test]
+    fn clips_at_every_edge() {
+        // drawn into a 4x4 target so the sprite hangs over each edge in turn,
+        // compared against the same draw into a bigger one
+        let bitmap = sprite(4, 4);
+        let spin = std::f32::consts::FRAC_PI_6;
+        let axes = [
+            (V2::new(4.0, 0.0), V2::new(0.0, 4.0)),
+            (V2::from_angle(spin, 4.0), V2::from_angle(spin, 4.0).perp()),
+        ];
+        let origins = [V2::new(-2.5, 0.0), V2::new(2.5, 0.0), V2::new(0.0, -2.5), V2::new(0.0, 2.5), V2::new(-2.0, -2.0)];
+        for &(x_axis, y_axis) in &axes {
+            for &origin in &origins {
+                let small = draw(4, 4, origin, x_axis, y_axis, &bitmap);
+                let big = draw(12, 12, origin + V2::new(4.0, 4.0), x_axis, y_axis, &bitmap);
+                for y in 0..4 {
+                    for x in 0..4 {
+                        assert_eq!(small[y * 4 + x], big[(y + 4) * 12 + x + 4], "{:?} at {}, {}", origin, x, y);
+                    }
+                }
+                assert!(small.iter().any(|&p| p != BLACK), "{:?} drew nothing", origin);
+            }
+        }
+
+        // entirely off screen, or flat
+        let mem = draw(4, 4, V2::new(-20.0, 5.0), V2::new(4.0, 0.0), V2::new(0.0, 4.0), &bitmap);
+        assert!(mem.iter().all(|&p| p == BLACK));
+        let mem = draw(4, 4, V2::new(0.0, 0.0), V2::new(4.0, 0.0), V2::new(8.0, 0.0), &bitmap);
+        assert!(mem.iter().all(|&p| p == BLACK));
+    }
+
+    #[test]
+    fn srgb_round_trips_every_value() {
+        for v in 0..=255u32 {
+            let argb = (v << 24) | (v << 16) | ((255 - v) << 8) | (v / 2);
+            assert_eq!(pack(linear1_to_srgb255(srgb255_to_linear1(unpack(argb)))), argb);
+        }
+    }
+
+    #[test]
+    fn premultiplies_in_linear_space() {
+        let bitmap = Bitmap::from_argb(3, 1, &[0xFFFF_8000, 0x80FF_FFFF, 0x00FF_FFFF]);
+        // half of linear white is 181 in sRGB, not 128
+        assert_eq!(bitmap.pixels, [0xFFFF_8000, 0x80B5_B5B5, 0]);
+    }
+
+    #[test]
+    fn blends_rects_in_linear_space() {
+        let mut mem = vec![BLACK; 4];
+        draw_rect(&mut mem, 2, 2, 0, 0, 1, 2, 0x80FF_FFFF);
+        assert_eq!(mem, [0xFFB5_B5B5, BLACK, 0xFFB5_B5B5, BLACK]);
+
+        // opaque colors replace what's there; the rect is clipped to the target
+        draw_rect(&mut mem, 2, 2, 1, -5, 9, 1, 0xFF12_3456);
+        assert_eq!(mem, [0xFFB5_B5B5, 0xFF12_3456, 0xFFB5_B5B5, BLACK]);
+
+        // over a transparent target the result stays premultiplied
+        let mut mem = vec![0; 1];
+        draw_rect(&mut mem, 1, 1, 0, 0, 1, 1, 0x80FF_FFFF);
+        assert_eq!(mem, [0x80B5_B5B5]);
+        assert_eq!(
+            blend_linear([0.5, 0.25, 0.0, 0.5], [0.5, 0.5, 0.25, 0.0]),
+            [0.75, 0.625, 0.25, 0.25]
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use super::input::MAX_CONTROLLERS;
+
+// Where the motor strengths end up. The platform implements this with its
+// controller API; elsewhere it can be a no-op or a RecordingRumble.
+pub trait RumbleSink {
+    // strengths are 0..1
+    fn set_motors(&mut self, slot: usize, left: f32, right: f32);
+}
+
+pub struct NullRumble;
+
+impl RumbleSink for NullRumble {
+    fn set_motors(&mut self, _slot: usize, _left: f32, _right: f32) {}
+}
+
+// Keeps every call so the rumble a piece of gameplay produced can be checked.
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingRumble {
+    pub calls: Vec<(usize, f32, f32)>,
+}
+
+#[cfg(test)]
+impl RumbleSink for RecordingRumble {
+    fn set_motors(&mut self, slot: usize, left: f32, right: f32) {
+        self.calls.push((slot, left, right));
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Effect {
+    left: f32,
+    right: f32,
+    seconds_left: f32,
+}
+
+// Game side of force feedback: gameplay queues timed effects per controller
+// slot and the platform calls update() once per frame to push the combined
+// motor strengths out. Overlapping effects on a slot take the strongest
+// value per motor, so a big hit isn't cut short by a small one.
+pub struct Rumble {
+    effects: Vec<Vec<Effect>>,
+    // what the sink was last told, so it's only called on changes
+    applied: [(f32, f32); MAX_CONTROLLERS],
+}
+
+impl Rumble {
+    pub fn new() -> Rumble {
+        Rumble {
+            effects: vec![Vec::new(); MAX_CONTROLLERS],
+            applied: [(0.0, 0.0); MAX_CONTROLLERS],
+        }
+    }
+
+    pub fn request(&mut self, slot: usize, left: f32, right: f32, seconds: f32) {
+        if slot >= MAX_CONTROLLERS || seconds <= 0.0 {
+            return;
+        }
+        self.effects[slot].push(Effect {
+            left: left.clamp(0.0, 1.0),
+            right: right.clamp(0.0, 1.0),
+            seconds_left: seconds,
+        });
+    }
+
+    pub fn stop(&mut self, slot: usize) {
+        if slot < MAX_CONTROLLERS {
+            self.effects[slot].clear();
+        }
+    }
+
+    // when the window loses focus
+    #[cfg(any(target_os="windows", test))]
+    pub fn stop_all(&mut self) {
+        for effects in self.effects.iter_mut() {
+            effects.clear();
+        }
+    }
+
+    // Current strengths for a slot, before the next update ages them.
+    pub fn motors(&self, slot: usize) -> (f32, f32) {
+        if slot >= MAX_CONTROLLERS {
+            return (0.0, 0.0);
+        }
+        self.effects[slot].iter().fold((0.0f32, 0.0f32), |(l, r), e| {
+            (l.max(e.left), r.max(e.right))
+        })
+    }
+
+    // Sends this frame's strengths and then ages the effects by dt seconds,
+    // so an effect lasts at least one frame however short it is.
+    pub fn update(&mut self, dt: f32, sink: &mut dyn RumbleSink) {
+        for slot in 0..MAX_CONTROLLERS {
+            let motors = self.motors(slot);
+            if motors != self.applied[slot] {
+                sink.set_motors(slot, motors.0, motors.1);
+                self.applied[slot] = motors;
+            }
+
+            for effect in self.effects[slot].iter_mut() {
+                effect.seconds_left -= dt;
+            }
+            self.effects[slot].retain(|e| e.seconds_left > 0.0);
+        }
+    }
+}
+
+impl Default for Rumble {
+    fn default() -> Self {
+        Rumble::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_is_sent_on_next_update() {
+        let mut rumble = Rumble::new();
+        let mut sink = RecordingRumble::default();
+        rumble.request(1, 0.3, 1.5, 0.1);
+        assert_eq!(rumble.motors(1), (0.3, 1.0));
+        rumble.update(0.016, &mut sink);
+        assert_eq!(sink.calls, vec![(1, 0.3, 1.0)]);
+
+        // nothing changed, nothing sent
+        rumble.update(0.016, &mut sink);
+        assert_eq!(sink.calls.len(), 1);
+    }
+
+    #[test]
+    fn strongest_effect_wins() {
+        let mut rumble = Rumble::new();
+        rumble.request(0, 0.8, 0.1, 1.0);
+        rumble.request(0, 0.2, 0.5, 1.0);
+        assert_eq!(rumble.motors(0), (0.8, 0.5));
+    }
+
+    #[test]
+    fn effects_expire_in_update() {
+        let mut rumble = Rumble::new();
+        let mut sink = RecordingRumble::default();
+        rumble.request(0, 0.5, 0.5, 0.05);
+        rumble.request(0, 0.2, 0.0, 0.2);
+        // sent, then aged past the short effect
+        rumble.update(0.1, &mut sink);
+        assert_eq!(rumble.motors(0), (0.2, 0.0));
+        rumble.update(0.1, &mut sink);
+        rumble.update(0.1, &mut sink);
+        assert_eq!(sink.calls, vec![(0, 0.5, 0.5), (0, 0.2, 0.0), (0, 0.0, 0.0)]);
+        assert_eq!(rumble.motors(0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn short_effect_lasts_one_frame() {
+        let mut rumble = Rumble::new();
+        let mut sink = RecordingRumble::default();
+        rumble.request(2, 1.0, 1.0, 0.001);
+        rumble.update(0.016, &mut sink);
+        rumble.update(0.016, &mut sink);
+        assert_eq!(sink.calls, vec![(2, 1.0, 1.0), (2, 0.0, 0.0)]);
+    }
+
+    #[test]
+    fn stop_silences_one_slot() {
+        let mut rumble = Rumble::new();
+        let mut sink = RecordingRumble::default();
+        rumble.request(0, 1.0, 0.0, 5.0);
+        rumble.request(3, 0.0, 1.0, 5.0);
+        rumble.update(0.016, &mut sink);
+        rumble.stop(0);
+        rumble.update(0.016, &mut sink);
+        assert_eq!(sink.calls, vec![(0, 1.0, 0.0), (3, 0.0, 1.0), (0, 0.0, 0.0)]);
+
+        rumble.stop_all();
+        rumble.update(0.016, &mut sink);
+        assert_eq!(sink.calls.last(), Some(&(3, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn out_of_range_slots_are_ignored() {
+        let mut rumble = Rumble::new();
+        let mut sink = RecordingRumble::default();
+        rumble.request(MAX_CONTROLLERS, 1.0, 1.0, 1.0);
+        rumble.stop(MAX_CONTROLLERS);
+        assert_eq!(rumble.motors(MAX_CONTROLLERS), (0.0, 0.0));
+        rumble.update(0.016, &mut sink);
+        assert!(sink.calls.is_empty());
+    }
+}
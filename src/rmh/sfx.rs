@@ -0,0 +1,701 @@
+// Procedural sound effects in the style of DrPetter's sfxr: a handful of
+// normalized parameters (mostly 0..1, slides -1..1) drive an oscillator with
+// frequency slide, vibrato, arpeggio, an attack/sustain/decay envelope,
+// low/high pass filters and a phaser. Presets roll random parameters in the
+// right ranges for a kind of sound, from a seed so results are repeatable.
+//
+// The synthesis runs at sfxr's 44100 Hz so the parameters sound the same as
+// in the original tool, and is resampled to the mixer rate afterwards.
+
+use std::sync::Arc;
+
+const SFXR_RATE: u32 = 44100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SfxWave {
+    Square,
+    Saw,
+    Sine,
+    Noise,
+}
+
+impl SfxWave {
+    pub fn name(self) -> &'static str {
+        match self {
+            SfxWave::Square => "square",
+            SfxWave::Saw => "saw",
+            SfxWave::Sine => "sine",
+            SfxWave::Noise => "noise",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<SfxWave> {
+        match name {
+            "square" => Some(SfxWave::Square),
+            "saw" => Some(SfxWave::Saw),
+            "sine" => Some(SfxWave::Sine),
+            "noise" => Some(SfxWave::Noise),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SfxPreset {
+    Pickup,
+    Laser,
+    Explosion,
+    Powerup,
+    Hit,
+    Jump,
+    Blip,
+}
+
+pub const ALL_SFX_PRESETS: &[SfxPreset] = &[
+    SfxPreset::Pickup,
+    SfxPreset::Laser,
+    SfxPreset::Explosion,
+    SfxPreset::Powerup,
+    SfxPreset::Hit,
+    SfxPreset::Jump,
+    SfxPreset::Blip,
+];
+
+// xorshift64*, enough for rolling parameters and noise.
+#[derive(Clone, Debug)]
+pub struct SfxRng {
+    state: u64,
+}
+
+impl SfxRng {
+    pub fn new(seed: u64) -> SfxRng {
+        // the state must never be 0
+        SfxRng { state: (seed ^ 0x9E37_79B9_7F4A_7C15) | 1 }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // 0..range: the top 24 bits over 2^24 never reach 1
+    pub fn float(&mut self, range: f32) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32 * range
+    }
+
+    // 0..=n, sfxr's rnd(n)
+    fn roll(&mut self, n: u64) -> u64 {
+        self.next() % (n + 1)
+    }
+
+    fn coin(&mut self) -> bool {
+        self.next() & 1 == 1
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SfxParams {
+    pub wave: SfxWave,
+    pub base_freq: f32,
+    // the sound stops when sliding down to this frequency
+    pub freq_limit: f32,
+    pub freq_slide: f32,
+    pub freq_delta_slide: f32,
+    pub vibrato_depth: f32,
+    pub vibrato_speed: f32,
+    pub arp_mod: f32,
+    pub arp_speed: f32,
+    pub duty: f32,
+    pub duty_slide: f32,
+    pub repeat_speed: f32,
+    pub env_attack: f32,
+    pub env_sustain: f32,
+    pub env_punch: f32,
+    pub env_decay: f32,
+    pub lpf_freq: f32,
+    pub lpf_slide: f32,
+    pub lpf_resonance: f32,
+    pub hpf_freq: f32,
+    pub hpf_slide: f32,
+    pub phaser_offset: f32,
+    pub phaser_slide: f32,
+    pub volume: f32,
+}
+
+impl Default for SfxParams {
+    fn default() -> SfxParams {
+        SfxParams {
+            wave: SfxWave::Square,
+            base_freq: 0.3,
+            freq_limit: 0.0,
+            freq_slide: 0.0,
+            freq_delta_slide: 0.0,
+            vibrato_depth: 0.0,
+            vibrato_speed: 0.0,
+            arp_mod: 0.0,
+            arp_speed: 0.0,
+            duty: 0.0,
+            duty_slide: 0.0,
+            repeat_speed: 0.0,
+            env_attack: 0.0,
+            env_sustain: 0.3,
+            env_punch: 0.0,
+            env_decay: 0.4,
+            lpf_freq: 1.0,
+            lpf_slide: 0.0,
+            lpf_resonance: 0.0,
+            hpf_freq: 0.0,
+            hpf_slide: 0.0,
+            phaser_offset: 0.0,
+            phaser_slide: 0.0,
+            volume: 0.5,
+        }
+    }
+}
+
+impl SfxParams {
+    fn fields_mut(&mut self) -> [(&'static str, &mut f32); 23] {
+        [
+            ("base_freq", &mut self.base_freq),
+            ("freq_limit", &mut self.freq_limit),
+            ("freq_slide", &mut self.freq_slide),
+            ("freq_delta_slide", &mut self.freq_delta_slide),
+            ("vibrato_depth", &mut self.vibrato_depth),
+            ("vibrato_speed", &mut self.vibrato_speed),
+            ("arp_mod", &mut self.arp_mod),
+            ("arp_speed", &mut self.arp_speed),
+            ("duty", &mut self.duty),
+            ("duty_slide", &mut self.duty_slide),
+            ("repeat_speed", &mut self.repeat_speed),
+            ("env_attack", &mut self.env_attack),
+            ("env_sustain", &mut self.env_sustain),
+            ("env_punch", &mut self.env_punch),
+            ("env_decay", &mut self.env_decay),
+            ("lpf_freq", &mut self.lpf_freq),
+            ("lpf_slide", &mut self.lpf_slide),
+            ("lpf_resonance", &mut self.lpf_resonance),
+            ("hpf_freq", &mut self.hpf_freq),
+            ("hpf_slide", &mut self.hpf_slide),
+            ("phaser_offset", &mut self.phaser_offset),
+            ("phaser_slide", &mut self.phaser_slide),
+            ("volume", &mut self.volume),
+        ]
+    }
+
+    // Same order and names as fields_mut().
+    fn fields(&self) -> [(&'static str, f32); 23] {
+        [
+            ("base_freq", self.base_freq),
+            ("freq_limit", self.freq_limit),
+            ("freq_slide", self.freq_slide),
+            ("freq_delta_slide", self.freq_delta_slide),
+            ("vibrato_depth", self.vibrato_depth),
+            ("vibrato_speed", self.vibrato_speed),
+            ("arp_mod", self.arp_mod),
+            ("arp_speed", self.arp_speed),
+            ("duty", self.duty),
+            ("duty_slide", self.duty_slide),
+            ("repeat_speed", self.repeat_speed),
+            ("env_attack", self.env_attack),
+            ("env_sustain", self.env_sustain),
+            ("env_punch", self.env_punch),
+            ("env_decay", self.env_decay),
+            ("lpf_freq", self.lpf_freq),
+            ("lpf_slide", self.lpf_slide),
+            ("lpf_resonance", self.lpf_resonance),
+            ("hpf_freq", self.hpf_freq),
+            ("hpf_slide", self.hpf_slide),
+            ("phaser_offset", self.phaser_offset),
+            ("phaser_slide", self.phaser_slide),
+            ("volume", self.volume),
+        ]
+    }
+
+    // The preset generators from sfxr.
+    pub fn preset(preset: SfxPreset, rng: &mut SfxRng) -> SfxParams {
+        let mut p = SfxParams::default();
+        match preset {
+            SfxPreset::Pickup => {
+                p.base_freq = 0.4 + rng.float(0.5);
+                p.env_sustain = rng.float(0.1);
+                p.env_decay = 0.1 + rng.float(0.4);
+                p.env_punch = 0.3 + rng.float(0.3);
+                if rng.coin() {
+                    p.arp_speed = 0.5 + rng.float(0.2);
+                    p.arp_mod = 0.2 + rng.float(0.4);
+                }
+            }
+            SfxPreset::Laser => {
+                p.wave = match rng.roll(2) {
+                    0 => SfxWave::Square,
+                    1 => SfxWave::Saw,
+                    _ if rng.coin() => SfxWave::Square,
+                    _ => SfxWave::Sine,
+                };
+                p.base_freq = 0.5 + rng.float(0.5);
+                p.freq_limit = (p.base_freq - 0.2 - rng.float(0.6)).max(0.2);
+                p.freq_slide = -0.15 - rng.float(0.2);
+                if rng.roll(2) == 0 {
+                    p.base_freq = 0.3 + rng.float(0.6);
+                    p.freq_limit = rng.float(0.1);
+                    p.freq_slide = -0.35 - rng.float(0.3);
+                }
+                if rng.coin() {
+                    p.duty = rng.float(0.5);
+                    p.duty_slide = rng.float(0.2);
+                } else {
+                    p.duty = 0.4 + rng.float(0.5);
+                    p.duty_slide = -rng.float(0.7);
+                }
+                p.env_sustain = 0.1 + rng.float(0.2);
+                p.env_decay = rng.float(0.4);
+                if rng.coin() {
+                    p.env_punch = rng.float(0.3);
+                }
+                if rng.roll(2) == 0 {
+                    p.phaser_offset = rng.float(0.2);
+                    p.phaser_slide = -rng.float(0.2);
+                }
+                if rng.coin() {
+                    p.hpf_freq = rng.float(0.3);
+                }
+            }
+            SfxPreset::Explosion => {
+                p.wave = SfxWave::Noise;
+                if rng.coin() {
+                    p.base_freq = 0.1 + rng.float(0.4);
+                    p.freq_slide = -0.1 + rng.float(0.4);
+                } else {
+                    p.base_freq = 0.2 + rng.float(0.7);
+                    p.freq_slide = -0.2 - rng.float(0.2);
+                }
+                p.base_freq *= p.base_freq;
+                if rng.roll(4) == 0 {
+                    p.freq_slide = 0.0;
+                }
+                if rng.roll(2) == 0 {
+                    p.repeat_speed = 0.3 + rng.float(0.5);
+                }
+                p.env_sustain = 0.1 + rng.float(0.3);
+                p.env_decay = rng.float(0.5);
+                if rng.coin() {
+                    p.phaser_offset = -0.3 + rng.float(0.9);
+                    p.phaser_slide = -rng.float(0.3);
+                }
+                p.env_punch = 0.2 + rng.float(0.6);
+                if rng.coin() {
+                    p.vibrato_depth = rng.float(0.7);
+                    p.vibrato_speed = rng.float(0.6);
+                }
+                if rng.roll(2) == 0 {
+                    p.arp_speed = 0.6 + rng.float(0.3);
+                    p.arp_mod = 0.8 - rng.float(1.6);
+                }
+            }
+            SfxPreset::Powerup => {
+                if rng.coin() {
+                    p.wave = SfxWave::Saw;
+                } else {
+                    p.duty = rng.float(0.6);
+                }
+                p.base_freq = 0.2 + rng.float(0.3);
+                if rng.coin() {
+                    p.freq_slide = 0.1 + rng.float(0.4);
+                    p.repeat_speed = 0.4 + rng.float(0.4);
+                } else {
+                    p.freq_slide = 0.05 + rng.float(0.2);
+                    if rng.coin() {
+                        p.vibrato_depth = rng.float(0.7);
+                        p.vibrato_speed = rng.float(0.6);
+                    }
+                }
+                p.env_sustain = rng.float(0.4);
+                p.env_decay = 0.1 + rng.float(0.4);
+            }
+            SfxPreset::Hit => {
+                p.wave = match rng.roll(2) {
+                    0 => SfxWave::Square,
+                    1 => SfxWave::Saw,
+                    _ => SfxWave::Noise,
+                };
+                if p.wave == SfxWave::Square {
+                    p.duty = rng.float(0.6);
+                }
+                p.base_freq = 0.2 + rng.float(0.6);
+                p.freq_slide = -0.3 - rng.float(0.4);
+                p.env_sustain = rng.float(0.1);
+                p.env_decay = 0.1 + rng.float(0.2);
+                if rng.coin() {
+                    p.hpf_freq = rng.float(0.3);
+                }
+            }
+            SfxPreset::Jump => {
+                p.duty = rng.float(0.6);
+                p.base_freq = 0.3 + rng.float(0.3);
+                p.freq_slide = 0.1 + rng.float(0.2);
+                p.env_sustain = 0.1 + rng.float(0.3);
+                p.env_decay = 0.1 + rng.float(0.2);
+                if rng.coin() {
+                    p.hpf_freq = rng.float(0.3);
+                }
+                if rng.coin() {
+                    p.lpf_freq = 1.0 - rng.float(0.6);
+                }
+            }
+            SfxPreset::Blip => {
+                if rng.coin() {
+                    p.wave = SfxWave::Saw;
+                } else {
+                    p.duty = rng.float(0.6);
+                }
+                p.base_freq = 0.2 + rng.float(0.4);
+                p.env_sustain = 0.1 + rng.float(0.1);
+                p.env_decay = rng.float(0.2);
+                p.hpf_freq = 0.1;
+            }
+        }
+        p
+    }
+
+    pub fn to_config(self) -> String {
+        let mut out = format!("wave = {}\n", self.wave.name());
+        for (name, value) in self.fields().iter() {
+            out.push_str(&format!("{} = {}\n", name, value));
+        }
+        out
+    }
+
+    // Missing parameters keep their defaults. Blank lines and lines starting
+    // with '#' are skipped.
+    pub fn from_config(config: &str) -> Result<SfxParams, String> {
+        let mut params = SfxParams::default();
+        for (line_no, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |e: String| format!("line {}: {}", line_no + 1, e);
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            let value = parts.next().ok_or_else(|| err("expected '='".to_string()))?.trim();
+
+            if name == "wave" {
+                params.wave = SfxWave::from_name(value).ok_or_else(|| err(format!("unknown wave '{}'", value)))?;
+                continue;
+            }
+            let mut fields = params.fields_mut();
+            let field = fields.iter_mut().find(|(n, _)| *n == name).ok_or_else(|| err(format!("unknown parameter '{}'", name)))?;
+            *field.1 = value.parse().map_err(|_| err(format!("bad number '{}'", value)))?;
+        }
+        Ok(params)
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<SfxParams, String> {
+        let config = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        SfxParams::from_config(&config).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &std::path::Path) -> Result<(), String> {
+        std::fs::write(path, self.to_config()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Renders the whole effect as interleaved stereo at `sample_rate`, ready
+    // for mixer::Clip. `seed` drives the noise.
+    pub fn render(&self, sample_rate: u32, seed: u64) -> Arc<Vec<f32>> {
+        let mono = Generator::new(self, seed).run();
+        let step = SFXR_RATE as f64 / sample_rate as f64;
+        let frames = (mono.len() as f64 / step) as usize;
+        let mut out = Vec::with_capacity(frames * 2);
+        for i in 0..frames {
+            let pos = i as f64 * step;
+            let j = pos as usize;
+            let t = (pos - j as f64) as f32;
+            let a = mono[j];
+            let b = mono.get(j + 1).copied().unwrap_or(0.0);
+            let s = a + (b - a) * t;
+            out.push(s);
+            out.push(s);
+        }
+        Arc::new(out)
+    }
+}
+
+// sfxr's synth state, a fairly direct port of its SynthSample loop.
+struct Generator<'a> {
+    p: &'a SfxParams,
+    rng: SfxRng,
+
+    phase: i32,
+    fperiod: f64,
+    fmaxperiod: f64,
+    fslide: f64,
+    fdslide: f64,
+    square_duty: f32,
+    square_slide: f32,
+    arp_mod: f64,
+    arp_time: i32,
+    arp_limit: i32,
+
+    env_stage: usize,
+    env_time: i32,
+    env_length: [i32; 3],
+
+    fltp: f32,
+    fltdp: f32,
+    fltw: f32,
+    fltw_d: f32,
+    fltdmp: f32,
+    fltphp: f32,
+    flthp: f32,
+    flthp_d: f32,
+
+    vib_phase: f32,
+    vib_speed: f32,
+    vib_amp: f32,
+
+    fphase: f32,
+    fdphase: f32,
+    ipp: usize,
+    phaser_buffer: Vec<f32>,
+    noise_buffer: [f32; 32],
+
+    rep_time: i32,
+    rep_limit: i32,
+}
+
+// Never produce more than this many samples, whatever the parameters.
+const MAX_SFX_SECONDS: usize = 10;
+
+impl<'a> Generator<'a> {
+    fn new(p: &'a SfxParams, seed: u64) -> Generator<'a> {
+        let mut g = Generator {
+            p,
+            rng: SfxRng::new(seed),
+            phase: 0,
+            fperiod: 0.0,
+            fmaxperiod: 0.0,
+            fslide: 0.0,
+            fdslide: 0.0,
+            square_duty: 0.0,
+            square_slide: 0.0,
+            arp_mod: 0.0,
+            arp_time: 0,
+            arp_limit: 0,
+            env_stage: 0,
+            env_time: 0,
+            env_length: [
+                (p.env_attack * p.env_attack * 100000.0) as i32,
+                (p.env_sustain * p.env_sustain * 100000.0) as i32,
+                (p.env_decay * p.env_decay * 100000.0) as i32,
+            ],
+            fltp: 0.0,
+            fltdp: 0.0,
+            fltw: p.lpf_freq.powi(3) * 0.1,
+            fltw_d: 1.0 + p.lpf_slide * 0.0001,
+            fltdmp: (5.0 / (1.0 + p.lpf_resonance.powi(2) * 20.0) * (0.01 + p.lpf_freq.powi(3) * 0.1)).min(0.8),
+            fltphp: 0.0,
+            flthp: p.hpf_freq.powi(2) * 0.1,
+            flthp_d: 1.0 + p.hpf_slide * 0.0003,
+            vib_phase: 0.0,
+            vib_speed: p.vibrato_speed.powi(2) * 0.01,
+            vib_amp: p.vibrato_depth * 0.5,
+            fphase: p.phaser_offset.powi(2) * 1020.0 * p.phaser_offset.signum(),
+            fdphase: p.phaser_slide.powi(2) * p.phaser_slide.signum(),
+            ipp: 0,
+            phaser_buffer: vec![0.0; 1024],
+            noise_buffer: [0.0; 32],
+            rep_time: 0,
+            rep_limit: if p.repeat_speed == 0.0 { 0 } else { ((1.0 - p.repeat_speed).powi(2) * 20000.0 + 32.0) as i32 },
+        };
+        g.reset_pitch();
+        for n in g.noise_buffer.iter_mut() {
+            *n = g.rng.float(2.0) - 1.0;
+        }
+        g
+    }
+
+    // The part of the state that repeat_speed restarts.
+    fn reset_pitch(&mut self) {
+        let p = self.p;
+        self.fperiod = 100.0 / (p.base_freq as f64 * p.base_freq as f64 + 0.001);
+        self.fmaxperiod = 100.0 / (p.freq_limit as f64 * p.freq_limit as f64 + 0.001);
+        self.fslide = 1.0 - (p.freq_slide as f64).powi(3) * 0.01;
+        self.fdslide = -(p.freq_delta_slide as f64).powi(3) * 0.000001;
+        self.square_duty = 0.5 - p.duty * 0.5;
+        self.square_slide = -p.duty_slide * 0.00005;
+        self.arp_mod = if p.arp_mod >= 0.0 {
+            1.0 - (p.arp_mod as f64).powi(2) * 0.9
+        } else {
+            1.0 + (p.arp_mod as f64).powi(2) * 10.0
+        };
+        self.arp_time = 0;
+        self.arp_limit = if p.arp_speed == 1.0 { 0 } else { ((1.0 - p.arp_speed).powi(2) * 20000.0 + 32.0) as i32 };
+    }
+
+    fn run(mut self) -> Vec<f32> {
+        let p = self.p;
+        let mut out = Vec::new();
+        while out.len() < SFXR_RATE as usize * MAX_SFX_SECONDS {
+            self.rep_time += 1;
+            if self.rep_limit != 0 && self.rep_time >= self.rep_limit {
+                self.rep_time = 0;
+                self.reset_pitch();
+            }
+
+            self.arp_time += 1;
+            if self.arp_limit != 0 && self.arp_time >= self.arp_limit {
+                self.arp_limit = 0;
+                self.fperiod *= self.arp_mod;
+            }
+            self.fslide += self.fdslide;
+            self.fperiod *= self.fslide;
+            if self.fperiod > self.fmaxperiod {
+                self.fperiod = self.fmaxperiod;
+                if p.freq_limit > 0.0 {
+                    break;
+                }
+            }
+            let mut rfperiod = self.fperiod as f32;
+            if self.vib_amp > 0.0 {
+                self.vib_phase += self.vib_speed;
+                rfperiod = self.fperiod as f32 * (1.0 + self.vib_phase.sin() * self.vib_amp);
+            }
+            let period = (rfperiod as i32).max(8);
+            self.square_duty = (self.square_duty + self.square_slide).clamp(0.0, 0.5);
+
+            self.env_time += 1;
+            if self.env_time > self.env_length[self.env_stage] {
+                self.env_time = 0;
+                self.env_stage += 1;
+                if self.env_stage == 3 {
+                    break;
+                }
+            }
+            let env_fraction = self.env_time as f32 / self.env_length[self.env_stage].max(1) as f32;
+            let env_vol = match self.env_stage {
+                0 => env_fraction,
+                1 => 1.0 + (1.0 - env_fraction) * 2.0 * p.env_punch,
+                _ => 1.0 - env_fraction,
+            };
+
+            self.fphase += self.fdphase;
+            let iphase = (self.fphase as i32).unsigned_abs().min(1023) as usize;
+            if self.flthp_d != 0.0 {
+                self.flthp = (self.flthp * self.flthp_d).clamp(0.00001, 0.1);
+            }
+
+            // 8x supersampling
+            let mut ssample = 0.0;
+            for _ in 0..8 {
+                self.phase += 1;
+                if self.phase >= period {
+                    self.phase %= period;
+                    if p.wave == SfxWave::Noise {
+                        for n in self.noise_buffer.iter_mut() {
+                            *n = self.rng.float(2.0) - 1.0;
+                        }
+                    }
+                }
+                let fp = self.phase as f32 / period as f32;
+                let mut sample = match p.wave {
+                    SfxWave::Square => if fp < self.square_duty { 0.5 } else { -0.5 },
+                    SfxWave::Saw => 1.0 - fp * 2.0,
+                    SfxWave::Sine => (fp * 2.0 * std::f32::consts::PI).sin(),
+                    SfxWave::Noise => self.noise_buffer[(self.phase * 32 / period) as usize],
+                };
+
+                // low pass
+                let pp = self.fltp;
+                self.fltw = (self.fltw * self.fltw_d).clamp(0.0, 0.1);
+                if p.lpf_freq != 1.0 {
+                    self.fltdp += (sample - self.fltp) * self.fltw;
+                    self.fltdp -= self.fltdp * self.fltdmp;
+                } else {
+                    self.fltp = sample;
+                    self.fltdp = 0.0;
+                }
+                self.fltp += self.fltdp;
+                // high pass
+                self.fltphp += self.fltp - pp;
+                self.fltphp -= self.fltphp * self.flthp;
+                sample = self.fltphp;
+                // phaser
+                self.phaser_buffer[self.ipp & 1023] = sample;
+                sample += self.phaser_buffer[(self.ipp + 1024 - iphase) & 1023];
+                self.ipp = (self.ipp + 1) & 1023;
+
+                ssample += sample * env_vol;
+            }
+            out.push((ssample / 8.0 * p.volume).clamp(-1.0, 1.0));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_repeat_for_a_seed() {
+        for &preset in ALL_SFX_PRESETS {
+            for seed in 0..20 {
+                let a = SfxParams::preset(preset, &mut SfxRng::new(seed));
+                let b = SfxParams::preset(preset, &mut SfxRng::new(seed));
+                assert_eq!(a, b, "{:?} {}", preset, seed);
+                assert_eq!(a.render(48000, seed), b.render(48000, seed), "{:?} {}", preset, seed);
+            }
+        }
+        let laser = |seed| SfxParams::preset(SfxPreset::Laser, &mut SfxRng::new(seed));
+        assert_ne!(laser(1), laser(2));
+    }
+
+    #[test]
+    fn presets_render_audible_sounds() {
+        for &preset in ALL_SFX_PRESETS {
+            for seed in 0..20 {
+                let samples = SfxParams::preset(preset, &mut SfxRng::new(seed)).render(48000, seed);
+                assert!(samples.len() > 100 && samples.len() < 48000 * 2 * 10, "{:?} {}: {}", preset, seed, samples.len());
+                assert!(samples.iter().all(|s| s.is_finite() && s.abs() <= 1.0), "{:?} {}", preset, seed);
+                let peak = samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+                assert!(peak > 0.01, "{:?} {}: peak {}", preset, seed, peak);
+            }
+        }
+    }
+
+    #[test]
+    fn render_length_follows_envelope() {
+        // attack 0, sustain 0.3 and decay 0.4 last 0.3^2 and 0.4^2 times
+        // 100000 samples at sfxr's rate
+        let params = SfxParams::default();
+        let full = params.render(SFXR_RATE, 0);
+        assert_eq!(full.len() / 2, 9000 + 16000 + 2);
+        let half = params.render(SFXR_RATE / 2, 0);
+        assert!((full.len() / 2).abs_diff(half.len()) <= 2);
+    }
+
+    #[test]
+    fn config_round_trip() {
+        for &preset in ALL_SFX_PRESETS {
+            for seed in 0..20 {
+                let params = SfxParams::preset(preset, &mut SfxRng::new(seed));
+                assert_eq!(SfxParams::from_config(&params.to_config()), Ok(params), "{:?} {}", preset, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn config_defaults_and_errors() {
+        let params = SfxParams::from_config("wave = sine\n# comment\n\nbase_freq = 0.5\n").unwrap();
+        assert_eq!(params.wave, SfxWave::Sine);
+        assert_eq!(params.base_freq, 0.5);
+        assert_eq!(params.env_decay, SfxParams::default().env_decay);
+
+        assert_eq!(SfxParams::from_config("wave = noise\n\nbase_freq = x"), Err("line 3: bad number 'x'".to_string()));
+        assert_eq!(SfxParams::from_config("bogus = 1"), Err("line 1: unknown parameter 'bogus'".to_string()));
+        assert_eq!(SfxParams::from_config("wave = tri"), Err("line 1: unknown wave 'tri'".to_string()));
+        assert_eq!(SfxParams::from_config("volume"), Err("line 1: expected '='".to_string()));
+    }
+}
@@ -0,0 +1,227 @@
+// Procedural instrument voices: a basic oscillator shaped by an ADSR
+// envelope. Small enough to run dozens of them per frame, which is all the
+// chiptune music and sound effects need.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Sine,
+    // duty cycle 0..1, 0.5 being a plain square
+    Pulse(f32),
+    Triangle,
+    Saw,
+    Noise,
+}
+
+// Times in seconds, sustain as a level 0..1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Envelope {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Instrument {
+    pub waveform: Waveform,
+    pub envelope: Envelope,
+    pub volume: f32,
+}
+
+pub fn key_to_frequency(key: f32) -> f32 {
+    440.0 * 2f32.powf((key - 69.0) / 12.0)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Done,
+}
+
+pub struct SynthVoice {
+    pub instrument: Instrument,
+    pub frequency: f32,
+    pub velocity: f32,
+    // 0..1 through one period
+    phase: f32,
+    stage: Stage,
+    level: f32,
+    // level when release started, so the release slope doesn't depend on
+    // where in the envelope the note was let go
+    release_from: f32,
+    noise: u32,
+    noise_value: f32,
+}
+
+impl SynthVoice {
+    pub fn new(instrument: Instrument, frequency: f32, velocity: f32) -> SynthVoice {
+        SynthVoice {
+            instrument,
+            frequency,
+            velocity,
+            phase: 0.0,
+            stage: Stage::Attack,
+            level: 0.0,
+            release_from: 0.0,
+            noise: 0x1234_5678,
+            noise_value: 0.0,
+        }
+    }
+
+    pub fn release(&mut self) {
+        if self.stage != Stage::Done {
+            self.stage = Stage::Release;
+            self.release_from = self.level;
+        }
+    }
+
+    pub fn is_released(&self) -> bool {
+        matches!(self.stage, Stage::Release | Stage::Done)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.stage == Stage::Done
+    }
+
+    fn advance_envelope(&mut self, dt: f32) {
+        let env = &self.instrument.envelope;
+        match self.stage {
+            Stage::Attack => {
+                self.level += if env.attack > 0.0 { dt / env.attack } else { 1.0 };
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.level -= if env.decay > 0.0 { (1.0 - env.sustain) * dt / env.decay } else { 1.0 };
+                if self.level <= env.sustain {
+                    self.level = env.sustain;
+                    self.stage = if env.sustain > 0.0 { Stage::Sustain } else { Stage::Done };
+                }
+            }
+            Stage::Sustain => {}
+            Stage::Release => {
+                self.level -= if env.release > 0.0 { self.release_from * dt / env.release } else { 1.0 };
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.stage = Stage::Done;
+                }
+            }
+            Stage::Done => {}
+        }
+    }
+
+    fn oscillator(&mut self) -> f32 {
+        let p = self.phase;
+        match self.instrument.waveform {
+            Waveform::Sine => (p * std::f32::consts::PI * 2.0).sin(),
+            Waveform::Pulse(duty) => if p < duty { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (p - 0.5).abs(),
+            Waveform::Saw => 2.0 * p - 1.0,
+            Waveform::Noise => self.noise_value,
+        }
+    }
+
+    // Adds the voice into `out` (interleaved stereo) with the given
+    // left/right gains.
+    pub fn render(&mut self, out: &mut [f32], sample_rate: u32, left: f32, right: f32) {
+        let dt = 1.0 / sample_rate as f32;
+        let step = self.frequency * dt;
+        let gain = self.instrument.volume * self.velocity;
+        for frame in out.chunks_mut(2) {
+            if self.stage == Stage::Done {
+                break;
+            }
+            let sample = self.oscillator() * self.level * gain;
+            frame[0] += sample * left;
+            frame[1] += sample * right;
+
+            self.phase += step;
+            if self.phase >= 1.0 {
+                self.phase -= self.phase.floor();
+                // a new noise value each period, so noise has a pitch too
+                self.noise ^= self.noise << 13;
+                self.noise ^= self.noise >> 17;
+                self.noise ^= self.noise << 5;
+                self.noise_value = (self.noise >> 8) as f32 / (1 << 23) as f32 - 1.0;
+            }
+            self.advance_envelope(dt);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 1000;
+
+    // a pulse that is always high, so the output is the envelope level
+    fn voice(attack: f32, decay: f32, sustain: f32, release: f32) -> SynthVoice {
+        let instrument = Instrument {
+            waveform: Waveform::Pulse(1.0),
+            envelope: Envelope { attack, decay, sustain, release },
+            volume: 1.0,
+        };
+        SynthVoice::new(instrument, 10.0, 1.0)
+    }
+
+    fn levels(voice: &mut SynthVoice, frames: usize) -> Vec<f32> {
+        let mut out = vec![0.0; frames * 2];
+        voice.render(&mut out, RATE, 1.0, 0.0);
+        out.chunks(2).map(|f| f[0]).collect()
+    }
+
+    fn assert_near(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-4, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn zero_times_jump_between_stages() {
+        let mut v = voice(0.0, 0.0, 0.5, 0.0);
+        // each frame plays the level before the envelope moves on
+        assert_eq!(levels(&mut v, 4), [0.0, 1.0, 0.5, 0.5]);
+        assert!(!v.is_released());
+
+        v.release();
+        assert!(v.is_released() && !v.is_finished());
+        assert_eq!(levels(&mut v, 3), [0.5, 0.0, 0.0]);
+        assert!(v.is_finished());
+    }
+
+    #[test]
+    fn zero_sustain_ends_after_decay() {
+        let mut v = voice(0.0, 0.0, 0.0, 1.0);
+        assert_eq!(levels(&mut v, 3), [0.0, 1.0, 0.0]);
+        assert!(v.is_finished());
+
+        // 10ms of decay without ever being released
+        let mut v = voice(0.0, 0.01, 0.0, 1.0);
+        levels(&mut v, 6);
+        assert!(!v.is_finished());
+        levels(&mut v, 6);
+        assert!(v.is_finished());
+        assert!(v.is_released());
+    }
+
+    #[test]
+    fn release_starts_from_the_current_level() {
+        let mut v = voice(0.01, 0.0, 1.0, 0.01);
+        assert_near(&levels(&mut v, 6), &[0.0, 0.1, 0.2, 0.3, 0.4, 0.5]);
+
+        // let go half way up: 10ms down from 0.6 rather than from full
+        v.release();
+        assert_near(&levels(&mut v, 4), &[0.6, 0.54, 0.48, 0.42]);
+        levels(&mut v, 8);
+        assert!(v.is_finished());
+        v.release();
+        assert!(v.is_finished());
+    }
+}
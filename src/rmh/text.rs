@@ -0,0 +1,616 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::render::{self, Bitmap};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Glyph {
+    pub page: usize,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    pub x_offset: i32,
+    pub y_offset: i32,
+    pub x_advance: i32,
+}
+
+// Pages hold glyph coverage as premultiplied white, so only the alpha
+// channel matters when drawing.
+pub struct BitmapFont {
+    pub pages: Vec<Bitmap>,
+    pub glyphs: HashMap<char, Glyph>,
+    pub kerning: HashMap<(char, char), i32>,
+    pub line_height: i32,
+}
+
+const BUILTIN_FIRST_CHAR: u8 = 32;
+const BUILTIN_GLYPH_SIZE: i32 = 8;
+
+impl BitmapFont {
+    // 8x8 monospaced ASCII font that needs no asset files, for debug text
+    pub fn builtin() -> BitmapFont {
+        let n_glyphs = BUILTIN_GLYPHS.len() as i32;
+        let mut atlas = Bitmap::new(n_glyphs * BUILTIN_GLYPH_SIZE, BUILTIN_GLYPH_SIZE);
+        let mut glyphs = HashMap::new();
+
+        for (i, rows) in BUILTIN_GLYPHS.iter().enumerate() {
+            let gx = i as i32 * BUILTIN_GLYPH_SIZE;
+            for (y, row) in rows.iter().enumerate() {
+                for x in 0..BUILTIN_GLYPH_SIZE {
+                    if row & (1 << x) != 0 {
+                        atlas.pixels[(y as i32 * atlas.width + gx + x) as usize] = 0xFFFF_FFFF;
+                    }
+                }
+            }
+            glyphs.insert((BUILTIN_FIRST_CHAR + i as u8) as char, Glyph {
+                page: 0,
+                x: gx,
+                y: 0,
+                width: BUILTIN_GLYPH_SIZE,
+                height: BUILTIN_GLYPH_SIZE,
+                x_offset: 0,
+                y_offset: 0,
+                x_advance: BUILTIN_GLYPH_SIZE,
+            });
+        }
+
+        BitmapFont {
+            pages: vec![atlas],
+            glyphs,
+            kerning: HashMap::new(),
+            line_height: BUILTIN_GLYPH_SIZE + 2,
+        }
+    }
+
+    // Loads a BMFont text descriptor; page images are resolved relative to it
+    // and must be TGA files.
+    pub fn load(fnt_path: &Path) -> Result<BitmapFont, String> {
+        let fnt = std::fs::read_to_string(fnt_path)
+            .map_err(|e| format!("{}: {}", fnt_path.display(), e))?;
+        let dir = fnt_path.parent().unwrap_or_else(|| Path::new(""));
+
+        BitmapFont::from_fnt(&fnt, &mut |file| {
+            let page_path = dir.join(file);
+            let bytes = std::fs::read(&page_path)
+                .map_err(|e| format!("{}: {}", page_path.display(), e))?;
+            Bitmap::from_tga(&bytes)
+        })
+    }
+
+    pub fn from_fnt(
+        fnt: &str,
+        load_page: &mut dyn FnMut(&str) -> Result<Bitmap, String>,
+    ) -> Result<BitmapFont, String> {
+        let mut pages = Vec::new();
+        let mut glyphs = HashMap::new();
+        let mut kerning = HashMap::new();
+        let mut line_height = 0;
+        let mut coverage_from_alpha = true;
+
+        for (line_no, line) in fnt.lines().enumerate() {
+            let (tag, attrs) = parse_fnt_line(line);
+            let int = |key: &str| -> Result<i32, String> {
+                attrs.get(key)
+                    .ok_or_else(|| format!("fnt line {}: missing {}", line_no + 1, key))?
+                    .parse::<i32>()
+                    .map_err(|e| format!("fnt line {}: {} {}", line_no + 1, key, e))
+            };
+            let to_char = |id: i32| std::char::from_u32(id as u32)
+                .ok_or_else(|| format!("fnt line {}: invalid char id {}", line_no + 1, id));
+
+            match tag {
+                "common" => {
+                    line_height = int("lineHeight")?;
+                    if int("packed").unwrap_or(0) != 0 {
+                        return Err("fnt: packed channels are not supported".to_string());
+                    }
+                    // 0 = glyph, 1 = outline, 2 = glyph + outline, 3 = zero, 4 = one
+                    coverage_from_alpha = int("alphaChnl").unwrap_or(0) <= 2;
+                }
+                "page" => {
+                    let id = int("id")? as usize;
+                    let file = attrs.get("file")
+                        .ok_or_else(|| format!("fnt line {}: missing file", line_no + 1))?;
+                    let page = coverage_page(&load_page(file)?, coverage_from_alpha);
+                    if pages.len() <= id {
+                        pages.resize_with(id + 1, || Bitmap::new(0, 0));
+                    }
+                    pages[id] = page;
+                }
+                "char" => {
+                    glyphs.insert(to_char(int("id")?)?, Glyph {
+                        page: int("page")? as usize,
+                        x: int("x")?,
+                        y: int("y")?,
+                        width: int("width")?,
+                        height: int("height")?,
+                        x_offset: int("xoffset")?,
+                        y_offset: int("yoffset")?,
+                        x_advance: int("xadvance")?,
+                    });
+                }
+                "kerning" => {
+                    kerning.insert((to_char(int("first")?)?, to_char(int("second")?)?), int("amount")?);
+                }
+                _ => {}
+            }
+        }
+
+        for g in glyphs.values() {
+            let page = pages.get(g.page).ok_or_else(|| format!("fnt: missing page {}", g.page))?;
+            if g.x < 0 || g.y < 0 || g.x + g.width > page.width || g.y + g.height > page.height {
+                return Err(format!("fnt: glyph outside of page {}", g.page));
+            }
+        }
+
+        Ok(BitmapFont { pages, glyphs, kerning, line_height })
+    }
+}
+
+impl GlyphSource for BitmapFont {
+    fn glyph(&mut self, c: char) -> Option<Glyph> {
+        self.glyphs.get(&c).copied()
+    }
+
+    fn kerning(&self, prev: char, c: char) -> i32 {
+        self.kerning.get(&(prev, c)).copied().unwrap_or(0)
+    }
+
+    fn line_height(&self) -> i32 {
+        self.line_height
+    }
+
+    fn page(&self, page: usize) -> &Bitmap {
+        &self.pages[page]
+    }
+}
+
+// splits `char id=65 x=3 file="a b.tga"` into the tag and its attributes
+fn parse_fnt_line(line: &str) -> (&str, HashMap<&str, &str>) {
+    let line = line.trim();
+    let (tag, mut rest) = match line.find(' ') {
+        Some(i) => (&line[..i], line[i..].trim_start()),
+        None => (line, ""),
+    };
+
+    let mut attrs = HashMap::new();
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim();
+        let after = &rest[eq + 1..];
+        let (value, remaining) = if let Some(quoted) = after.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            (&quoted[..end], quoted.get(end + 1..).unwrap_or(""))
+        } else {
+            let end = after.find(' ').unwrap_or(after.len());
+            (&after[..end], &after[end..])
+        };
+        attrs.insert(key, value);
+        rest = remaining.trim_start();
+    }
+
+    (tag, attrs)
+}
+
+fn coverage_page(page: &Bitmap, from_alpha: bool) -> Bitmap {
+    let pixels = page.pixels.iter().map(|&p| {
+        let c = render::unpack(p);
+        let coverage = if from_alpha { c[0] } else { c[1] } / 255.0;
+        render::pack(render::linear1_to_srgb255([coverage; 4]))
+    }).collect();
+    Bitmap { width: page.width, height: page.height, pixels }
+}
+
+// Anything text can be laid out and drawn with: fixed bitmap fonts, or
+// TrueType fonts rasterized on demand at some pixel size.
+pub trait GlyphSource {
+    fn glyph(&mut self, c: char) -> Option<Glyph>;
+    fn kerning(&self, prev: char, c: char) -> i32;
+    fn line_height(&self) -> i32;
+    fn page(&self, page: usize) -> &Bitmap;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PlacedGlyph {
+    pub glyph: Glyph,
+    pub x: i32,
+    pub y: i32,
+}
+
+pub struct TextLayout {
+    pub glyphs: Vec<PlacedGlyph>,
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Clone, Copy)]
+struct LineGlyph {
+    c: char,
+    glyph: Glyph,
+    x: i32,
+}
+
+// Breaks lines on '\n' and, when max_width is given, wraps at the last space
+// that fits (or mid-word if a single word is too long). Lines are aligned
+// inside max_width, or inside the widest line when there is no limit.
+pub fn layout_text(
+    font: &mut dyn GlyphSource,
+    text: &str,
+    max_width: Option<i32>,
+    align: Align,
+) -> TextLayout {
+    let mut lines: Vec<(Vec<LineGlyph>, i32)> = Vec::new();
+
+    for paragraph in text.split('\n') {
+        let mut line: Vec<LineGlyph> = Vec::new();
+        let mut pen_x = 0;
+        let mut prev = None;
+        let mut break_at = None;
+
+        for c in paragraph.chars() {
+            let glyph = match font.glyph(c).or_else(|| font.glyph('?')) {
+                Some(g) => g,
+                None => continue,
+            };
+            let mut x = pen_x + prev.map_or(0, |p| font.kerning(p, c));
+
+            if let Some(max_width) = max_width {
+                if c != ' ' && !line.is_empty() && x + glyph.x_offset + glyph.width > max_width {
+                    let split = break_at.unwrap_or(line.len());
+                    let rest = line.split_off(split);
+                    let shift = rest.first().map_or(x, |g| g.x);
+                    finish_line(&mut lines, line);
+                    line = rest.into_iter().map(|g| LineGlyph { x: g.x - shift, ..g }).collect();
+                    x -= shift;
+                    break_at = None;
+                }
+            }
+
+            line.push(LineGlyph { c, glyph, x });
+            pen_x = x + glyph.x_advance;
+            if c == ' ' {
+                break_at = Some(line.len());
+            }
+            prev = Some(c);
+        }
+        finish_line(&mut lines, line);
+    }
+
+    let widest = lines.iter().map(|(_, w)| *w).max().unwrap_or(0);
+    let box_width = max_width.unwrap_or(widest);
+    let line_height = font.line_height();
+
+    let mut glyphs = Vec::new();
+    for (i, (line, line_width)) in lines.iter().enumerate() {
+        let offset = match align {
+            Align::Left => 0,
+            Align::Center => (box_width - line_width) / 2,
+            Align::Right => box_width - line_width,
+        };
+        for g in line {
+            glyphs.push(PlacedGlyph { glyph: g.glyph, x: g.x + offset, y: i as i32 * line_height });
+        }
+    }
+
+    TextLayout {
+        glyphs,
+        width: widest,
+        height: lines.len() as i32 * line_height,
+    }
+}
+
+// trailing spaces don't count towards the line width
+fn finish_line(lines: &mut Vec<(Vec<LineGlyph>, i32)>, mut line: Vec<LineGlyph>) {
+    while matches!(line.last(), Some(g) if g.c == ' ') {
+        line.pop();
+    }
+    let width = line.last().map_or(0, |g| g.x + g.glyph.x_advance);
+    lines.push((line, width));
+}
+
+// `color` is straight sRGB 0xAARRGGBB; x, y is the top-left of the layout box
+#[allow(clippy::too_many_arguments)]
+pub fn draw_layout(
+    mem: &mut [u32],
+    w: i32,
+    h: i32,
+    font: &dyn GlyphSource,
+    layout: &TextLayout,
+    x: i32,
+    y: i32,
+    color: u32,
+) {
+    let color = render::srgb255_to_linear1(render::unpack(color));
+    for g in &layout.glyphs {
+        draw_glyph(mem, w, h, font.page(g.glyph.page), &g.glyph, x + g.x, y + g.y, color);
+    }
+}
+
+// Single call for debug text: no wrapping, left aligned. Returns the layout
+// size so callers can stack lines or draw a backdrop.
+#[allow(clippy::too_many_arguments)]
+pub fn draw_text(
+    mem: &mut [u32],
+    w: i32,
+    h: i32,
+    font: &mut dyn GlyphSource,
+    x: i32,
+    y: i32,
+    text: &str,
+    color: u32,
+) -> (i32, i32) {
+    let layout = layout_text(font, text, None, Align::Left);
+    draw_layout(mem, w, h, font, &layout, x, y, color);
+    (layout.width, layout.height)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn draw_glyph(
+    mem: &mut [u32],
+    w: i32,
+    h: i32,
+    page: &Bitmap,
+    glyph: &Glyph,
+    pen_x: i32,
+    pen_y: i32,
+    linear_color: [f32; 4],
+) {
+    let dst_x = pen_x + glyph.x_offset;
+    let dst_y = pen_y + glyph.y_offset;
+    let x_start = dst_x.max(0);
+    let y_start = dst_y.max(0);
+    let x_end = (dst_x + glyph.width).min(w);
+    let y_end = (dst_y + glyph.height).min(h);
+
+    for y in y_start..y_end {
+        let src_row = (glyph.y + y - dst_y) * page.width;
+        for x in x_start..x_end {
+            let coverage = (page.pixels[(src_row + glyph.x + x - dst_x) as usize] >> 24) as f32 / 255.0;
+            if coverage <= 0.0 {
+                continue;
+            }
+            let a = linear_color[0] * coverage;
+            let src = [a, linear_color[1] * a, linear_color[2] * a, linear_color[3] * a];
+            let idx = (y * w + x) as usize;
+            let dst = render::srgb255_to_linear1(render::unpack(mem[idx]));
+            mem[idx] = render::pack(render::linear1_to_srgb255(render::blend_linear(dst, src)));
+        }
+    }
+}
+
+// font8x8_basic by Daniel Hepper (public domain), ASCII 32 to 127.
+// One byte per row, least significant bit is the leftmost pixel.
+const BUILTIN_GLYPHS: [[u8; 8]; 96] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x18, 0x3C, 0x3C, 0x18, 0x18, 0x00, 0x18, 0x00], // '!'
+    [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '"'
+    [0x36, 0x36, 0x7F, 0x36, 0x7F, 0x36, 0x36, 0x00], // '#'
+    [0x0C, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x0C, 0x00], // '$'
+    [0x00, 0x63, 0x33, 0x18, 0x0C, 0x66, 0x63, 0x00], // '%'
+    [0x1C, 0x36, 0x1C, 0x6E, 0x3B, 0x33, 0x6E, 0x00], // '&'
+    [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00], // "'"
+    [0x18, 0x0C, 0x06, 0x06, 0x06, 0x0C, 0x18, 0x00], // '('
+    [0x06, 0x0C, 0x18, 0x18, 0x18, 0x0C, 0x06, 0x00], // ')'
+    [0x00, 0x66, 0x3C, 0xFF, 0x3C, 0x66, 0x00, 0x00], // '*'
+    [0x00, 0x0C, 0x0C, 0x3F, 0x0C, 0x0C, 0x00, 0x00], // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ','
+    [0x00, 0x00, 0x00, 0x3F, 0x00, 0x00, 0x00, 0x00], // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C, 0x00], // '.'
+    [0x60, 0x30, 0x18, 0x0C, 0x06, 0x03, 0x01, 0x00], // '/'
+    [0x3E, 0x63, 0x73, 0x7B, 0x6F, 0x67, 0x3E, 0x00], // '0'
+    [0x0C, 0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x3F, 0x00], // '1'
+    [0x1E, 0x33, 0x30, 0x1C, 0x06, 0x33, 0x3F, 0x00], // '2'
+    [0x1E, 0x33, 0x30, 0x1C, 0x30, 0x33, 0x1E, 0x00], // '3'
+    [0x38, 0x3C, 0x36, 0x33, 0x7F, 0x30, 0x78, 0x00], // '4'
+    [0x3F, 0x03, 0x1F, 0x30, 0x30, 0x33, 0x1E, 0x00], // '5'
+    [0x1C, 0x06, 0x03, 0x1F, 0x33, 0x33, 0x1E, 0x00], // '6'
+    [0x3F, 0x33, 0x30, 0x18, 0x0C, 0x0C, 0x0C, 0x00], // '7'
+    [0x1E, 0x33, 0x33, 0x1E, 0x33, 0x33, 0x1E, 0x00], // '8'
+    [0x1E, 0x33, 0x33, 0x3E, 0x30, 0x18, 0x0E, 0x00], // '9'
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x00], // ':'
+    [0x00, 0x0C, 0x0C, 0x00, 0x00, 0x0C, 0x0C, 0x06], // ';'
+    [0x18, 0x0C, 0x06, 0x03, 0x06, 0x0C, 0x18, 0x00], // '<'
+    [0x00, 0x00, 0x3F, 0x00, 0x00, 0x3F, 0x00, 0x00], // '='
+    [0x06, 0x0C, 0x18, 0x30, 0x18, 0x0C, 0x06, 0x00], // '>'
+    [0x1E, 0x33, 0x30, 0x18, 0x0C, 0x00, 0x0C, 0x00], // '?'
+    [0x3E, 0x63, 0x7B, 0x7B, 0x7B, 0x03, 0x1E, 0x00], // '@'
+    [0x0C, 0x1E, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x00], // 'A'
+    [0x3F, 0x66, 0x66, 0x3E, 0x66, 0x66, 0x3F, 0x00], // 'B'
+    [0x3C, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3C, 0x00], // 'C'
+    [0x1F, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1F, 0x00], // 'D'
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x46, 0x7F, 0x00], // 'E'
+    [0x7F, 0x46, 0x16, 0x1E, 0x16, 0x06, 0x0F, 0x00], // 'F'
+    [0x3C, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7C, 0x00], // 'G'
+    [0x33, 0x33, 0x33, 0x3F, 0x33, 0x33, 0x33, 0x00], // 'H'
+    [0x1E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'I'
+    [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E, 0x00], // 'J'
+    [0x67, 0x66, 0x36, 0x1E, 0x36, 0x66, 0x67, 0x00], // 'K'
+    [0x0F, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7F, 0x00], // 'L'
+    [0x63, 0x77, 0x7F, 0x7F, 0x6B, 0x63, 0x63, 0x00], // 'M'
+    [0x63, 0x67, 0x6F, 0x7B, 0x73, 0x63, 0x63, 0x00], // 'N'
+    [0x1C, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1C, 0x00], // 'O'
+    [0x3F, 0x66, 0x66, 0x3E, 0x06, 0x06, 0x0F, 0x00], // 'P'
+    [0x1E, 0x33, 0x33, 0x33, 0x3B, 0x1E, 0x38, 0x00], // 'Q'
+    [0x3F, 0x66, 0x66, 0x3E, 0x36, 0x66, 0x67, 0x00], // 'R'
+    [0x1E, 0x33, 0x07, 0x0E, 0x38, 0x33, 0x1E, 0x00], // 'S'
+    [0x3F, 0x2D, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'T'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3F, 0x00], // 'U'
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // 'V'
+    [0x63, 0x63, 0x63, 0x6B, 0x7F, 0x77, 0x63, 0x00], // 'W'
+    [0x63, 0x63, 0x36, 0x1C, 0x1C, 0x36, 0x63, 0x00], // 'X'
+    [0x33, 0x33, 0x33, 0x1E, 0x0C, 0x0C, 0x1E, 0x00], // 'Y'
+    [0x7F, 0x63, 0x31, 0x18, 0x4C, 0x66, 0x7F, 0x00], // 'Z'
+    [0x1E, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1E, 0x00], // '['
+    [0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x40, 0x00], // '\\'
+    [0x1E, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1E, 0x00], // ']'
+    [0x08, 0x1C, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00], // '^'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF], // '_'
+    [0x0C, 0x0C, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00], // '`'
+    [0x00, 0x00, 0x1E, 0x30, 0x3E, 0x33, 0x6E, 0x00], // 'a'
+    [0x07, 0x06, 0x06, 0x3E, 0x66, 0x66, 0x3B, 0x00], // 'b'
+    [0x00, 0x00, 0x1E, 0x33, 0x03, 0x33, 0x1E, 0x00], // 'c'
+    [0x38, 0x30, 0x30, 0x3E, 0x33, 0x33, 0x6E, 0x00], // 'd'
+    [0x00, 0x00, 0x1E, 0x33, 0x3F, 0x03, 0x1E, 0x00], // 'e'
+    [0x1C, 0x36, 0x06, 0x0F, 0x06, 0x06, 0x0F, 0x00], // 'f'
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x1F], // 'g'
+    [0x07, 0x06, 0x36, 0x6E, 0x66, 0x66, 0x67, 0x00], // 'h'
+    [0x0C, 0x00, 0x0E, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'i'
+    [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1E], // 'j'
+    [0x07, 0x06, 0x66, 0x36, 0x1E, 0x36, 0x67, 0x00], // 'k'
+    [0x0E, 0x0C, 0x0C, 0x0C, 0x0C, 0x0C, 0x1E, 0x00], // 'l'
+    [0x00, 0x00, 0x33, 0x7F, 0x7F, 0x6B, 0x63, 0x00], // 'm'
+    [0x00, 0x00, 0x1F, 0x33, 0x33, 0x33, 0x33, 0x00], // 'n'
+    [0x00, 0x00, 0x1E, 0x33, 0x33, 0x33, 0x1E, 0x00], // 'o'
+    [0x00, 0x00, 0x3B, 0x66, 0x66, 0x3E, 0x06, 0x0F], // 'p'
+    [0x00, 0x00, 0x6E, 0x33, 0x33, 0x3E, 0x30, 0x78], // 'q'
+    [0x00, 0x00, 0x3B, 0x6E, 0x66, 0x06, 0x0F, 0x00], // 'r'
+    [0x00, 0x00, 0x3E, 0x03, 0x1E, 0x30, 0x1F, 0x00], // 's'
+    [0x08, 0x0C, 0x3E, 0x0C, 0x0C, 0x2C, 0x18, 0x00], // 't'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6E, 0x00], // 'u'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x1E, 0x0C, 0x00], // 'v'
+    [0x00, 0x00, 0x63, 0x6B, 0x7F, 0x7F, 0x36, 0x00], // 'w'
+    [0x00, 0x00, 0x63, 0x36, 0x1C, 0x36, 0x63, 0x00], // 'x'
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x3E, 0x30, 0x1F], // 'y'
+    [0x00, 0x00, 0x3F, 0x19, 0x0C, 0x26, 0x3F, 0x00], // 'z'
+    [0x38, 0x0C, 0x0C, 0x07, 0x0C, 0x0C, 0x38, 0x00], // '{'
+    [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00], // '|'
+    [0x07, 0x0C, 0x0C, 0x38, 0x0C, 0x0C, 0x07, 0x00], // '}'
+    [0x6E, 0x3B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // '~'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // DEL
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FNT: &str = "info face=\"Test\" size=8
+common lineHeight=10 base=8 scaleW=4 scaleH=2 pages=1 packed=0 alphaChnl=0
+page id=0 file=\"test font.tga\"
+chars count=2
+char id=65 x=0 y=0 width=2 height=2 xoffset=0 yoffset=1 xadvance=3 page=0 chnl=15
+char id=66 x=2 y=0 width=2 height=1 xoffset=1 yoffset=0 xadvance=4 page=0 chnl=15
+kernings count=1
+kerning first=65 second=66 amount=-1
+";
+
+    fn load(fnt: &str, page: Bitmap) -> Result<BitmapFont, String> {
+        BitmapFont::from_fnt(fnt, &mut |file| {
+            assert_eq!(file, "test font.tga");
+            Ok(Bitmap { width: page.width, height: page.height, pixels: page.pixels.clone() })
+        })
+    }
+
+    fn page() -> Bitmap {
+        Bitmap { width: 4, height: 2, pixels: vec![0x80FF_0000, 0xFF00_0000, 0, 0x0000_00FF, 0, 0, 0, 0] }
+    }
+
+    #[test]
+    fn parses_bmfont() {
+        let mut font = load(FNT, page()).unwrap();
+        assert_eq!(font.line_height(), 10);
+        let a = font.glyph('A').unwrap();
+        assert_eq!((a.x, a.y, a.width, a.height, a.y_offset, a.x_advance), (0, 0, 2, 2, 1, 3));
+        let b = font.glyph('B').unwrap();
+        assert_eq!((b.x, b.width, b.height, b.x_offset, b.x_advance), (2, 2, 1, 1, 4));
+        assert!(font.glyph('C').is_none());
+        assert_eq!(font.kerning('A', 'B'), -1);
+        assert_eq!(font.kerning('B', 'A'), 0);
+
+        // coverage comes from the alpha channel and is stored as
+        // premultiplied white
+        let alphas: Vec<u32> = font.page(0).pixels.iter().map(|p| p >> 24).collect();
+        assert_eq!(alphas, [0x80, 0xFF, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(font.page(0).pixels[1], 0xFFFF_FFFF);
+    }
+
+    #[test]
+    fn reads_coverage_from_color_when_alpha_is_constant() {
+        let font = load(&FNT.replace("alphaChnl=0", "alphaChnl=4"), page()).unwrap();
+        let alphas: Vec<u32> = font.pages[0].pixels.iter().map(|p| p >> 24).collect();
+        assert_eq!(alphas, [0xFF, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rejects_bad_fonts() {
+        let outside = FNT.replace("char id=66 x=2", "char id=66 x=3");
+        assert!(load(&outside, page()).err().unwrap().contains("outside"));
+        let missing = FNT.replace("width=2 height=1", "height=1");
+        assert!(load(&missing, page()).err().unwrap().contains("line 6: missing width"));
+        let packed = FNT.replace("packed=0", "packed=1");
+        assert!(load(&packed, page()).is_err());
+    }
+
+    // top-left of each glyph's pen position, in layout order
+    fn positions(layout: &TextLayout) -> Vec<(i32, i32)> {
+        layout.glyphs.iter().map(|g| (g.x, g.y)).collect()
+    }
+
+    // the builtin font advances 8 pixels per glyph, lines are 10 apart
+    fn layout(text: &str, max_width: Option<i32>, align: Align) -> TextLayout {
+        layout_text(&mut BitmapFont::builtin(), text, max_width, align)
+    }
+
+    #[test]
+    fn wraps_at_the_last_space_that_fits() {
+        let layout = layout("ab cd ef", Some(40), Align::Left);
+        assert_eq!(positions(&layout), [(0, 0), (8, 0), (16, 0), (24, 0), (32, 0), (0, 10), (8, 10)]);
+        assert_eq!((layout.width, layout.height), (40, 20));
+    }
+
+    #[test]
+    fn splits_words_longer_than_a_line() {
+        let layout = layout("abcdefg", Some(24), Align::Left);
+        assert_eq!(positions(&layout), [(0, 0), (8, 0), (16, 0), (0, 10), (8, 10), (16, 10), (0, 20)]);
+        assert_eq!((layout.width, layout.height), (24, 30));
+    }
+
+    #[test]
+    fn breaks_on_newlines() {
+        let layout = layout("a\n\nbc", None, Align::Left);
+        assert_eq!(positions(&layout), [(0, 0), (0, 20), (8, 20)]);
+        assert_eq!((layout.width, layout.height), (16, 30));
+    }
+
+    #[test]
+    fn aligns_lines_in_the_box() {
+        let right = layout("a\nbcd ", Some(40), Align::Right);
+        // the trailing space doesn't count
+        assert_eq!(positions(&right)[..4], [(32, 0), (16, 10), (24, 10), (32, 10)]);
+        let center = layout("a\nbcd", Some(40), Align::Center);
+        assert_eq!(positions(&center), [(16, 0), (8, 10), (16, 10), (24, 10)]);
+        // without a limit the widest line is the box
+        let right = layout("a\nbcd", None, Align::Right);
+        assert_eq!(positions(&right), [(16, 0), (0, 10), (8, 10), (16, 10)]);
+        assert_eq!(right.width, 24);
+    }
+
+    #[test]
+    fn missing_glyphs_fall_back_to_question_mark() {
+        let mut font = BitmapFont::builtin();
+        let question = font.glyphs[&'?'];
+        let layout = layout_text(&mut font, "a\u{e9}", None, Align::Left);
+        assert_eq!(layout.glyphs[1].glyph.x, question.x);
+        assert_eq!(layout.glyphs[1].x, 8);
+
+        // and are left out when there's no '?' either
+        font.glyphs.remove(&'?');
+        let layout = layout_text(&mut font, "a\u{e9}b", None, Align::Left);
+        assert_eq!(positions(&layout), [(0, 0), (8, 0)]);
+    }
+
+    #[test]
+    fn applies_kerning() {
+        let mut font = BitmapFont::builtin();
+        font.kerning.insert(('A', 'V'), -2);
+        let layout = layout_text(&mut font, "AVA", None, Align::Left);
+        assert_eq!(positions(&layout), [(0, 0), (6, 0), (14, 0)]);
+        assert_eq!(layout.width, 22);
+    }
+}
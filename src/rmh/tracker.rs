@@ -0,0 +1,657 @@
+// ProTracker MOD playback. A module is a set of 8 bit samples plus patterns
+// of 64 rows, one note cell per channel per row, played in the order of the
+// song's position table. Rows last `speed` ticks and ticks last 2.5 / tempo
+// seconds; effects are applied on tick boundaries like the Amiga replayers
+// do, and channels are resampled from Amiga periods to our output rate.
+//
+// Covers 4 to 32 channel MODs (M.K., FLTn, nCHN, nnCH) and 15 sample
+// Soundtracker files. FastTracker XM is not supported.
+
+use std::sync::Arc;
+
+use super::mixer::SoundSource;
+
+const ROWS_PER_PATTERN: usize = 64;
+// PAL Amiga clock / 2: a period of p plays samples at this / p Hz
+const AMIGA_CLOCK: f64 = 3_546_894.6;
+const MIN_PERIOD: i32 = 113;
+const MAX_PERIOD: i32 = 856;
+
+#[derive(Clone, Debug, Default)]
+pub struct Sample {
+    pub data: Vec<f32>,
+    // -8..7, in eighths of a semitone
+    pub finetune: i8,
+    // 0..64
+    pub volume: u8,
+    pub loop_start: usize,
+    // 0 for one-shot samples
+    pub loop_len: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Cell {
+    // 1 based, 0 keeps the channel's sample
+    pub sample: u8,
+    // 0 for no note
+    pub period: u16,
+    pub effect: u8,
+    pub param: u8,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Module {
+    pub title: String,
+    pub channels: usize,
+    pub samples: Vec<Sample>,
+    pub orders: Vec<u8>,
+    pub restart: usize,
+    // ROWS_PER_PATTERN * channels cells each, row by row
+    pub patterns: Vec<Vec<Cell>>,
+}
+
+fn text(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    bytes[..end].iter().map(|&b| if (32..127).contains(&b) { b as char } else { ' ' }).collect::<String>().trim_end().to_string()
+}
+
+fn be16(bytes: &[u8]) -> usize {
+    ((bytes[0] as usize) << 8) | bytes[1] as usize
+}
+
+// Channel count from the signature at offset 1080, None for the old 15
+// sample format that has none.
+fn channels_from_signature(sig: &[u8]) -> Option<usize> {
+    match sig {
+        b"M.K." | b"M!K!" | b"M&K!" | b"N.T." | b"FLT4" | b"4CHN" => return Some(4),
+        b"FLT8" | b"OCTA" | b"CD81" => return Some(8),
+        _ => {}
+    }
+    let digit = |b: u8| if b.is_ascii_digit() { Some((b - b'0') as usize) } else { None };
+    if &sig[1..] == b"CHN" {
+        return digit(sig[0]);
+    }
+    if &sig[2..] == b"CH" || &sig[2..] == b"CN" {
+        return Some(digit(sig[0])? * 10 + digit(sig[1])?);
+    }
+    None
+}
+
+impl Module {
+    pub fn parse(data: &[u8]) -> Result<Module, String> {
+        if data.len() < 600 {
+            return Err("too short for a module".to_string());
+        }
+        let signature = data.get(1080..1084).and_then(channels_from_signature);
+        let (n_samples, channels) = match signature {
+            Some(channels) if (1..=32).contains(&channels) => (31, channels),
+            Some(channels) => return Err(format!("unsupported channel count {}", channels)),
+            None => (15, 4),
+        };
+
+        let mut module = Module {
+            title: text(&data[..20]),
+            channels,
+            ..Module::default()
+        };
+
+        let mut lengths = Vec::with_capacity(n_samples);
+        for i in 0..n_samples {
+            let h = &data[20 + i * 30..20 + (i + 1) * 30];
+            let length = be16(&h[22..24]) * 2;
+            let mut loop_start = be16(&h[26..28]) * 2;
+            let mut loop_len = be16(&h[28..30]) * 2;
+            // a loop of one word is ProTracker's way of saying no loop
+            if loop_len <= 2 || loop_start >= length {
+                loop_start = 0;
+                loop_len = 0;
+            }
+            loop_len = loop_len.min(length - loop_start);
+            lengths.push(length);
+            module.samples.push(Sample {
+                data: Vec::new(),
+                finetune: (((h[24] & 0x0F) << 4) as i8) >> 4,
+                volume: h[25].min(64),
+                loop_start,
+                loop_len,
+            });
+        }
+
+        let song = 20 + n_samples * 30;
+        let song_len = (data[song] as usize).clamp(1, 128);
+        module.restart = data[song + 1] as usize;
+        if module.restart >= song_len {
+            module.restart = 0;
+        }
+        module.orders = data[song + 2..song + 2 + song_len].to_vec();
+        let n_patterns = data[song + 2..song + 130].iter().max().copied().unwrap_or(0) as usize + 1;
+
+        let mut pos = song + 130 + if signature.is_some() { 4 } else { 0 };
+        let pattern_size = ROWS_PER_PATTERN * channels * 4;
+        for p in 0..n_patterns {
+            let bytes = data.get(pos..pos + pattern_size).ok_or_else(|| format!("pattern {} is cut off", p))?;
+            let cells = bytes.chunks(4).map(|c| Cell {
+                sample: (c[0] & 0xF0) | (c[2] >> 4),
+                period: (((c[0] & 0x0F) as u16) << 8) | c[1] as u16,
+                effect: c[2] & 0x0F,
+                param: c[3],
+            });
+            module.patterns.push(cells.collect());
+            pos += pattern_size;
+        }
+
+        // samples follow the patterns; tolerate files cut short at the end
+        for (sample, length) in module.samples.iter_mut().zip(lengths) {
+            let end = (pos + length).min(data.len());
+            if pos < end {
+                sample.data = data[pos..end].iter().map(|&b| b as i8 as f32 / 128.0).collect();
+            }
+            pos = end;
+            if sample.loop_start + sample.loop_len > sample.data.len() {
+                sample.loop_len = sample.data.len().saturating_sub(sample.loop_start);
+            }
+        }
+        Ok(module)
+    }
+
+    pub fn load(path: &std::path::Path) -> Result<Module, String> {
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Module::parse(&data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn cell(&self, order: usize, row: usize, channel: usize) -> Cell {
+        let pattern = self.orders[order] as usize;
+        self.patterns[pattern][row * self.channels + channel]
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Channel {
+    // 0 when nothing has played yet
+    sample: usize,
+    // sample frame being played, fractional
+    pos: f64,
+    playing: bool,
+    period: i32,
+    finetune: i8,
+    volume: i32,
+    pan: f32,
+    cell: Cell,
+
+    // period and volume changes that only last for this tick
+    period_offset: i32,
+    volume_offset: i32,
+    // semitones added by arpeggio this tick
+    arpeggio: i32,
+
+    porta_target: i32,
+    porta_speed: i32,
+    vibrato_pos: u8,
+    vibrato_speed: u8,
+    vibrato_depth: u8,
+    tremolo_pos: u8,
+    tremolo_speed: u8,
+    tremolo_depth: u8,
+    offset_memory: u8,
+    loop_row: usize,
+    loop_count: u8,
+}
+
+// The sine table the Amiga replayers use for vibrato and tremolo, one half
+// period; the other half is its negative.
+const VIBRATO_TABLE: [i32; 32] = [
+    0, 24, 49, 74, 97, 120, 141, 161, 180, 197, 212, 224, 235, 244, 250, 253,
+    255, 253, 250, 244, 235, 224, 212, 197, 180, 161, 141, 120, 97, 74, 49, 24,
+];
+
+fn vibrato_value(pos: u8) -> i32 {
+    let v = VIBRATO_TABLE[(pos & 31) as usize];
+    if pos & 32 == 0 { v } else { -v }
+}
+
+pub struct ModPlayer {
+    module: Arc<Module>,
+    pub looping: bool,
+    speed: u32,
+    tempo: u32,
+    tick: u32,
+    order: usize,
+    row: usize,
+    // output frames left in the current tick
+    tick_frames_left: usize,
+    // rows still to repeat because of pattern delay (EEx), and whether the
+    // current row is such a repeat, which plays effects but no new notes
+    pattern_delay: u32,
+    repeating_row: bool,
+    jump_to: Option<(usize, usize)>,
+    channels: Vec<Channel>,
+    finished: bool,
+}
+
+impl ModPlayer {
+    pub fn new(module: Arc<Module>) -> ModPlayer {
+        // Amiga style hard panning, LRRL, softened so headphones aren't awful
+        let channels = (0..module.channels)
+            .map(|i| Channel {
+                pan: if i % 4 == 0 || i % 4 == 3 { 0.2 } else { 0.8 },
+                ..Channel::default()
+            })
+            .collect();
+        ModPlayer {
+            finished: module.orders.is_empty(),
+            module,
+            looping: false,
+            speed: 6,
+            tempo: 125,
+            tick: 0,
+            order: 0,
+            row: 0,
+            tick_frames_left: 0,
+            pattern_delay: 0,
+            repeating_row: false,
+            jump_to: None,
+            channels,
+        }
+    }
+
+    fn trigger(&mut self, c: usize, cell: Cell) {
+        let module = &self.module;
+        let ch = &mut self.channels[c];
+        if cell.period == 0 || ch.sample == 0 {
+            return;
+        }
+        ch.period = cell.period as i32;
+        ch.finetune = module.samples[ch.sample - 1].finetune;
+        ch.pos = 0.0;
+        ch.playing = true;
+        ch.vibrato_pos = 0;
+        ch.tremolo_pos = 0;
+        if cell.effect == 0x9 {
+            if cell.param > 0 {
+                ch.offset_memory = cell.param;
+            }
+            ch.pos = ch.offset_memory as f64 * 256.0;
+        }
+    }
+
+    fn play_row(&mut self) {
+        let module = self.module.clone();
+        for c in 0..self.channels.len() {
+            let cell = module.cell(self.order, self.row, c);
+            let ch = &mut self.channels[c];
+            ch.cell = cell;
+            if cell.sample > 0 && (cell.sample as usize) <= module.samples.len() {
+                ch.sample = cell.sample as usize;
+                ch.volume = module.samples[ch.sample - 1].volume as i32;
+            }
+
+            let x = cell.param >> 4;
+            let y = cell.param & 0x0F;
+            let is_porta = cell.effect == 0x3 || cell.effect == 0x5;
+            let is_delayed = cell.effect == 0xE && x == 0xD && y > 0;
+            if is_porta {
+                if cell.period > 0 {
+                    ch.porta_target = cell.period as i32;
+                }
+            } else if !is_delayed {
+                self.trigger(c, cell);
+            }
+
+            let ch = &mut self.channels[c];
+            match cell.effect {
+                0x3 if cell.param > 0 => ch.porta_speed = cell.param as i32,
+                0x4 => {
+                    if x > 0 {
+                        ch.vibrato_speed = x;
+                    }
+                    if y > 0 {
+                        ch.vibrato_depth = y;
+                    }
+                }
+                0x7 => {
+                    if x > 0 {
+                        ch.tremolo_speed = x;
+                    }
+                    if y > 0 {
+                        ch.tremolo_depth = y;
+                    }
+                }
+                0xB => self.jump_to = Some((cell.param as usize, 0)),
+                0xC => ch.volume = (cell.param as i32).min(64),
+                0xD => {
+                    let next = self.jump_to.map(|(order, _)| order).unwrap_or(self.order + 1);
+                    self.jump_to = Some((next, (x * 10 + y) as usize));
+                }
+                0xE => match x {
+                    0x1 => ch.period = (ch.period - y as i32).max(MIN_PERIOD),
+                    0x2 => ch.period = (ch.period + y as i32).min(MAX_PERIOD),
+                    0x6 => {
+                        if y == 0 {
+                            ch.loop_row = self.row;
+                        } else if ch.loop_count == 0 {
+                            ch.loop_count = y;
+                            self.jump_to = Some((self.order, ch.loop_row));
+                        } else {
+                            ch.loop_count -= 1;
+                            if ch.loop_count > 0 {
+                                self.jump_to = Some((self.order, ch.loop_row));
+                            }
+                        }
+                    }
+                    0xA => ch.volume = (ch.volume + y as i32).min(64),
+                    0xB => ch.volume = (ch.volume - y as i32).max(0),
+                    0xC if y == 0 => ch.volume = 0,
+                    0xE => self.pattern_delay = y as u32,
+                    _ => {}
+                },
+                0xF if cell.param > 0 => {
+                    if cell.param < 32 {
+                        self.speed = cell.param as u32;
+                    } else {
+                        self.tempo = cell.param as u32;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Effects that act on every tick but the first of a row.
+    fn update_effects(&mut self) {
+        let tick = self.tick;
+        for c in 0..self.channels.len() {
+            let ch = &mut self.channels[c];
+            let cell = ch.cell;
+            let x = cell.param >> 4;
+            let y = cell.param & 0x0F;
+            let volume_slide = |ch: &mut Channel| {
+                ch.volume = if x > 0 { ch.volume + x as i32 } else { ch.volume - y as i32 }.clamp(0, 64);
+            };
+            let tone_porta = |ch: &mut Channel| {
+                if ch.period < ch.porta_target {
+                    ch.period = (ch.period + ch.porta_speed).min(ch.porta_target);
+                } else if ch.period > ch.porta_target && ch.porta_target > 0 {
+                    ch.period = (ch.period - ch.porta_speed).max(ch.porta_target);
+                }
+            };
+            let vibrato = |ch: &mut Channel| {
+                ch.period_offset = vibrato_value(ch.vibrato_pos) * ch.vibrato_depth as i32 / 128;
+                ch.vibrato_pos = ch.vibrato_pos.wrapping_add(ch.vibrato_speed) & 63;
+            };
+
+            match cell.effect {
+                0x0 if cell.param > 0 => {
+                    ch.arpeggio = match tick % 3 {
+                        1 => x as i32,
+                        2 => y as i32,
+                        _ => 0,
+                    }
+                }
+                0x1 => ch.period = (ch.period - cell.param as i32).max(MIN_PERIOD),
+                0x2 => ch.period = (ch.period + cell.param as i32).min(MAX_PERIOD),
+                0x3 => tone_porta(ch),
+                0x4 => vibrato(ch),
+                0x5 => {
+                    tone_porta(ch);
+                    volume_slide(ch);
+                }
+                0x6 => {
+                    vibrato(ch);
+                    volume_slide(ch);
+                }
+                0x7 => {
+                    ch.volume_offset = vibrato_value(ch.tremolo_pos) * ch.tremolo_depth as i32 / 64;
+                    ch.tremolo_pos = ch.tremolo_pos.wrapping_add(ch.tremolo_speed) & 63;
+                }
+                0xA => volume_slide(ch),
+                0xE => match x {
+                    0x9 if tick.checked_rem(y as u32) == Some(0) => ch.pos = 0.0,
+                    0xC if tick == y as u32 => ch.volume = 0,
+                    0xD if tick == y as u32 => self.trigger(c, cell),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    }
+
+    fn next_row(&mut self) {
+        let (order, row) = match self.jump_to.take() {
+            Some(jump) => jump,
+            None if self.row + 1 < ROWS_PER_PATTERN => (self.order, self.row + 1),
+            None => (self.order + 1, 0),
+        };
+        self.row = row.min(ROWS_PER_PATTERN - 1);
+        self.order = order;
+        if self.order >= self.module.orders.len() {
+            if self.looping {
+                self.order = self.module.restart;
+            } else {
+                self.finished = true;
+            }
+        }
+    }
+
+    fn process_tick(&mut self) {
+        for ch in self.channels.iter_mut() {
+            ch.period_offset = 0;
+            ch.volume_offset = 0;
+            ch.arpeggio = 0;
+        }
+        if self.tick == 0 && !self.repeating_row {
+            self.play_row();
+        } else {
+            self.update_effects();
+        }
+
+        self.tick += 1;
+        if self.tick >= self.speed {
+            self.tick = 0;
+            self.repeating_row = self.pattern_delay > 0;
+            if self.repeating_row {
+                self.pattern_delay -= 1;
+            } else {
+                self.next_row();
+            }
+        }
+    }
+
+    fn render_channels(&mut self, out: &mut [f32], sample_rate: u32) {
+        let module = &self.module;
+        let gain = 2.0 / module.channels.max(2) as f32;
+        for ch in self.channels.iter_mut() {
+            if !ch.playing || ch.sample == 0 {
+                continue;
+            }
+            let sample = &module.samples[ch.sample - 1];
+            let period = (ch.period + ch.period_offset).max(MIN_PERIOD / 2) as f64;
+            let semitones = ch.arpeggio as f64 + ch.finetune as f64 / 8.0;
+            let step = AMIGA_CLOCK / period * 2f64.powf(semitones / 12.0) / sample_rate as f64;
+            let volume = (ch.volume + ch.volume_offset).clamp(0, 64) as f32 / 64.0 * gain;
+            let left = volume * (1.0 - ch.pan);
+            let right = volume * ch.pan;
+            let loop_end = sample.loop_start + sample.loop_len;
+
+            for frame in out.chunks_mut(2) {
+                if sample.loop_len > 0 && ch.pos >= loop_end as f64 {
+                    ch.pos -= (((ch.pos - loop_end as f64) / sample.loop_len as f64).floor() + 1.0) * sample.loop_len as f64;
+                }
+                let i = ch.pos as usize;
+                if i >= sample.data.len() {
+                    ch.playing = false;
+                    break;
+                }
+                let next = match sample.data.get(i + 1) {
+                    Some(&s) if sample.loop_len == 0 || i + 1 < loop_end => s,
+                    _ if sample.loop_len > 0 => sample.data[sample.loop_start],
+                    _ => 0.0,
+                };
+                let t = (ch.pos - i as f64) as f32;
+                let s = sample.data[i] + (next - sample.data[i]) * t;
+                frame[0] += s * left;
+                frame[1] += s * right;
+                ch.pos += step;
+            }
+        }
+    }
+}
+
+impl SoundSource for ModPlayer {
+    fn render(&mut self, out: &mut [f32], sample_rate: u32) -> bool {
+        let frames = out.len() / 2;
+        let mut pos = 0;
+        while pos < frames {
+            if self.tick_frames_left == 0 {
+                if self.finished {
+                    return false;
+                }
+                self.process_tick();
+                self.tick_frames_left = (sample_rate as f64 * 2.5 / self.tempo as f64).round() as usize;
+            }
+            let n = self.tick_frames_left.min(frames - pos);
+            self.render_channels(&mut out[pos * 2..(pos + n) * 2], sample_rate);
+            self.tick_frames_left -= n;
+            pos += n;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 50 Hz output makes every tick at the default tempo exactly one frame
+    const RATE: u32 = 50;
+
+    fn fx(effect: u8, param: u8) -> Cell {
+        Cell { effect, param, ..Cell::default() }
+    }
+
+    // A 4 channel M.K. module with one looped 32 byte square wave sample.
+    // `cells` are (pattern, row, channel, cell); patterns run up to the
+    // highest one in `orders`.
+    fn mod_file(orders: &[u8], restart: u8, cells: &[(usize, usize, usize, Cell)]) -> Vec<u8> {
+        let mut data = b"test song".to_vec();
+        data.resize(20, 0);
+        for i in 0..31 {
+            let mut header = [0u8; 30];
+            if i == 0 {
+                header[..6].copy_from_slice(b"square");
+                header[22..24].copy_from_slice(&16u16.to_be_bytes());
+                header[24] = 0x0F; // finetune -1
+                header[25] = 48;
+                header[26..28].copy_from_slice(&4u16.to_be_bytes());
+                header[28..30].copy_from_slice(&8u16.to_be_bytes());
+            }
+            data.extend_from_slice(&header);
+        }
+        data.push(orders.len() as u8);
+        data.push(restart);
+        let mut table = orders.to_vec();
+        table.resize(128, 0);
+        data.extend_from_slice(&table);
+        data.extend_from_slice(b"M.K.");
+
+        let n_patterns = *orders.iter().max().unwrap() as usize + 1;
+        let mut patterns = vec![[0u8; ROWS_PER_PATTERN * 4 * 4]; n_patterns];
+        for &(pattern, row, channel, c) in cells {
+            let at = (row * 4 + channel) * 4;
+            patterns[pattern][at..at + 4].copy_from_slice(&[
+                (c.sample & 0xF0) | (c.period >> 8) as u8,
+                c.period as u8,
+                (c.sample << 4) | c.effect,
+                c.param,
+            ]);
+        }
+        for pattern in &patterns {
+            data.extend_from_slice(pattern);
+        }
+        data.extend((0..32).map(|i| if i % 8 < 4 { 0x40 } else { 0xC0 }));
+        data
+    }
+
+    fn player(data: &[u8]) -> ModPlayer {
+        ModPlayer::new(Arc::new(Module::parse(data).unwrap()))
+    }
+
+    // the (order, row) of each of the next n ticks, rendering one at a time
+    fn positions(player: &mut ModPlayer, n: usize) -> Vec<(usize, usize)> {
+        (0..n).map(|_| {
+            let position = (player.order, player.row);
+            assert!(player.render(&mut [0.0; 2], RATE));
+            position
+        }).collect()
+    }
+
+    #[test]
+    fn reads_channel_count_from_signature() {
+        assert_eq!(channels_from_signature(b"M.K."), Some(4));
+        assert_eq!(channels_from_signature(b"FLT4"), Some(4));
+        assert_eq!(channels_from_signature(b"OCTA"), Some(8));
+        assert_eq!(channels_from_signature(b"6CHN"), Some(6));
+        assert_eq!(channels_from_signature(b"12CH"), Some(12));
+        assert_eq!(channels_from_signature(b"32CN"), Some(32));
+        assert_eq!(channels_from_signature(b"xCHN"), None);
+        assert_eq!(channels_from_signature(b"\0\0\0\0"), None);
+    }
+
+    #[test]
+    fn parses_mk_module() {
+        let note = Cell { sample: 1, period: 428, effect: 0xC, param: 0x20 };
+        let module = Module::parse(&mod_file(&[0, 1, 0], 1, &[(1, 3, 2, note)])).unwrap();
+        assert_eq!(module.title, "test song");
+        assert_eq!(module.channels, 4);
+        assert_eq!(module.samples.len(), 31);
+        assert_eq!(module.orders, [0, 1, 0]);
+        assert_eq!(module.restart, 1);
+        assert_eq!(module.patterns.len(), 2);
+        assert_eq!(module.cell(1, 3, 2), note);
+        assert_eq!(module.cell(0, 3, 2), Cell::default());
+
+        let sample = &module.samples[0];
+        assert_eq!((sample.finetune, sample.volume), (-1, 48));
+        assert_eq!((sample.loop_start, sample.loop_len), (8, 16));
+        assert_eq!(sample.data.len(), 32);
+        assert_eq!((sample.data[0], sample.data[4]), (0.5, -0.5));
+
+        assert!(Module::parse(&[0; 100]).is_err());
+    }
+
+    #[test]
+    fn follows_breaks_jumps_and_loops() {
+        let data = mod_file(&[0, 1, 2], 0, &[
+            (0, 0, 0, fx(0xF, 1)),
+            // break to row 12 of the next order; the parameter is decimal
+            (0, 1, 3, fx(0xD, 0x12)),
+            // rows 12 and 13 play three times
+            (1, 12, 1, fx(0xE, 0x60)),
+            (1, 13, 1, fx(0xE, 0x62)),
+            (1, 14, 0, fx(0xB, 2)),
+        ]);
+        let mut player = player(&data);
+        assert_eq!(positions(&mut player, 11), [
+            (0, 0), (0, 1),
+            (1, 12), (1, 13), (1, 12), (1, 13), (1, 12), (1, 13),
+            (1, 14), (2, 0), (2, 1),
+        ]);
+    }
+
+    #[test]
+    fn stops_or_loops_at_the_end() {
+        let data = mod_file(&[0, 1], 1, &[
+            (0, 0, 0, fx(0xF, 1)),
+            (0, 0, 1, fx(0xD, 0)),
+            (1, 0, 0, fx(0xD, 0)),
+        ]);
+
+        let mut player = player(&data);
+        assert_eq!(positions(&mut player, 2), [(0, 0), (1, 0)]);
+        assert!(!player.render(&mut [0.0; 2], RATE));
+
+        // looping goes back to the restart position, where speed is still 1
+        let mut player = self::player(&data);
+        player.looping = true;
+        assert_eq!(positions(&mut player, 4), [(0, 0), (1, 0), (1, 0), (1, 0)]);
+    }
+}
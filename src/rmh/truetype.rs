@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+
+use super::render::{self, Bitmap};
+use super::text::{Glyph, GlyphSource};
+
+const ATLAS_WIDTH: i32 = 512;
+const ATLAS_START_HEIGHT: i32 = 128;
+const ATLAS_MAX_HEIGHT: i32 = 4096;
+const GLYPH_PADDING: i32 = 1;
+
+// Glyphs are rasterized the first time they are asked for at a given pixel
+// size and packed into a single atlas page in rows ("shelves").
+pub struct TrueTypeFont {
+    font: FontVec,
+    atlas: Bitmap,
+    cache: HashMap<(char, u32), Option<Glyph>>,
+    shelf_x: i32,
+    shelf_y: i32,
+    shelf_height: i32,
+}
+
+impl TrueTypeFont {
+    pub fn load(path: &Path) -> Result<TrueTypeFont, String> {
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        TrueTypeFont::from_bytes(bytes)
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<TrueTypeFont, String> {
+        let font = FontVec::try_from_vec(bytes).map_err(|e| format!("ttf: {}", e))?;
+        Ok(TrueTypeFont {
+            font,
+            atlas: Bitmap::new(ATLAS_WIDTH, ATLAS_START_HEIGHT),
+            cache: HashMap::new(),
+            shelf_x: 0,
+            shelf_y: 0,
+            shelf_height: 0,
+        })
+    }
+
+    pub fn sized(&mut self, px: u32) -> SizedFont<'_> {
+        SizedFont { font: self, px }
+    }
+
+    fn rasterize(&mut self, c: char, px: u32) -> Option<Glyph> {
+        let scaled = self.font.as_scaled(PxScale::from(px as f32));
+        let id = scaled.glyph_id(c);
+        if id.0 == 0 {
+            return None;
+        }
+
+        let mut glyph = Glyph {
+            x_advance: scaled.h_advance(id).round() as i32,
+            ..Glyph::default()
+        };
+
+        // positioned on the baseline so bounds come out relative to the line top
+        let outlined = match scaled.outline_glyph(id.with_scale_and_position(px as f32, point(0.0, scaled.ascent()))) {
+            Some(o) => o,
+            None => return Some(glyph),
+        };
+        let bounds = outlined.px_bounds();
+        glyph.width = bounds.width() as i32;
+        glyph.height = bounds.height() as i32;
+        glyph.x_offset = bounds.min.x as i32;
+        glyph.y_offset = bounds.min.y as i32;
+
+        let (x, y) = self.allocate(glyph.width, glyph.height)?;
+        glyph.x = x;
+        glyph.y = y;
+
+        let atlas = &mut self.atlas;
+        outlined.draw(|gx, gy, coverage| {
+            let (gx, gy) = (gx as i32, gy as i32);
+            if gx < glyph.width && gy < glyph.height {
+                let idx = ((y + gy) * atlas.width + x + gx) as usize;
+                atlas.pixels[idx] = render::pack(render::linear1_to_srgb255([coverage.min(1.0); 4]));
+            }
+        });
+
+        Some(glyph)
+    }
+
+    fn allocate(&mut self, width: i32, height: i32) -> Option<(i32, i32)> {
+        if width + GLYPH_PADDING > self.atlas.width {
+            return None;
+        }
+        if self.shelf_x + width + GLYPH_PADDING > self.atlas.width {
+            self.shelf_y += self.shelf_height;
+            self.shelf_x = 0;
+            self.shelf_height = 0;
+        }
+        while self.shelf_y + height + GLYPH_PADDING > self.atlas.height {
+            if self.atlas.height >= ATLAS_MAX_HEIGHT {
+                return None;
+            }
+            // rows are contiguous, so growing downwards keeps existing glyphs in place
+            self.atlas.height *= 2;
+            let n_pixels = (self.atlas.width * self.atlas.height) as usize;
+            self.atlas.pixels.resize(n_pixels, 0);
+        }
+
+        let pos = (self.shelf_x, self.shelf_y);
+        self.shelf_x += width + GLYPH_PADDING;
+        self.shelf_height = self.shelf_height.max(height + GLYPH_PADDING);
+        Some(pos)
+    }
+}
+
+pub struct SizedFont<'a> {
+    font: &'a mut TrueTypeFont,
+    px: u32,
+}
+
+impl GlyphSource for SizedFont<'_> {
+    fn glyph(&mut self, c: char) -> Option<Glyph> {
+        let key = (c, self.px);
+        if let Some(glyph) = self.font.cache.get(&key) {
+            return *glyph;
+        }
+        let glyph = self.font.rasterize(c, self.px);
+        self.font.cache.insert(key, glyph);
+        glyph
+    }
+
+    fn kerning(&self, prev: char, c: char) -> i32 {
+        let scaled = self.font.font.as_scaled(PxScale::from(self.px as f32));
+        scaled.kern(scaled.glyph_id(prev), scaled.glyph_id(c)).round() as i32
+    }
+
+    fn line_height(&self) -> i32 {
+        let scaled = self.font.font.as_scaled(PxScale::from(self.px as f32));
+        (scaled.ascent() - scaled.descent() + scaled.line_gap()).ceil() as i32
+    }
+
+    fn page(&self, _page: usize) -> &Bitmap {
+        &self.font.atlas
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rmh::text::{layout_text, Align};
+
+    // DejaVu Sans ExtraLight, which has a kern table (see testdata/DejaVu-LICENSE.txt)
+    fn dejavu() -> TrueTypeFont {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/DejaVuSans-ExtraLight.ttf");
+        TrueTypeFont::load(&path).unwrap()
+    }
+
+    fn coverage(atlas: &Bitmap, g: &Glyph) -> u32 {
+        let mut sum = 0;
+        for y in g.y..g.y + g.height {
+            for x in g.x..g.x + g.width {
+                sum += atlas.pixels[(y * atlas.width + x) as usize] >> 24;
+            }
+        }
+        sum
+    }
+
+    #[test]
+    fn rasterizes_each_glyph_once() {
+        let mut font = dejavu();
+        let mut sized = font.sized(16);
+        let a = sized.glyph('A').unwrap();
+        assert!(a.width > 0 && a.height > 0 && a.x_advance > 0);
+        let b = sized.glyph('B').unwrap();
+        assert_eq!(b.x, a.width + GLYPH_PADDING);
+        let again = sized.glyph('A').unwrap();
+        assert_eq!((again.x, again.y), (a.x, a.y));
+        assert!(sized.line_height() >= 16);
+
+        // a space has an advance but nothing to draw
+        let space = sized.glyph(' ').unwrap();
+        assert!(space.width == 0 && space.x_advance > 0);
+        assert!(coverage(&font.atlas, &a) > 0);
+        assert_eq!(font.shelf_x, a.width + b.width + 2 * GLYPH_PADDING);
+    }
+
+    #[test]
+    fn missing_glyphs_fall_back_to_question_mark() {
+        let mut font = dejavu();
+        let mut sized = font.sized(16);
+        assert!(sized.glyph('\u{e000}').is_none());
+        let question = sized.glyph('?').unwrap();
+        let layout = layout_text(&mut sized, "\u{e000}", None, Align::Left);
+        assert_eq!(layout.glyphs.len(), 1);
+        assert_eq!((layout.glyphs[0].glyph.x, layout.glyphs[0].glyph.y), (question.x, question.y));
+    }
+
+    #[test]
+    fn kerns_pairs() {
+        let mut font = dejavu();
+        let mut sized = font.sized(32);
+        let kern = sized.kerning('A', 'V');
+        assert!(kern < 0, "{}", kern);
+        assert_eq!(sized.kerning('H', 'H'), 0);
+
+        let advance = sized.glyph('A').unwrap().x_advance;
+        let layout = layout_text(&mut sized, "AV", None, Align::Left);
+        assert_eq!(layout.glyphs[1].x, advance + kern);
+    }
+
+    #[test]
+    fn packs_the_atlas_without_overlaps() {
+        let mut font = dejavu();
+        let first = font.sized(48).glyph('@').unwrap();
+        let first_coverage = coverage(&font.atlas, &first);
+
+        let mut glyphs = vec![first];
+        for c in (33u8..127).map(char::from) {
+            glyphs.extend(font.sized(48).glyph(c));
+        }
+        // it had to grow, keeping what was already there
+        assert!(font.atlas.height > ATLAS_START_HEIGHT);
+        assert_eq!(coverage(&font.atlas, &first), first_coverage);
+
+        let glyphs: Vec<Glyph> = glyphs.into_iter().filter(|g| g.width > 0).collect();
+        for (i, a) in glyphs.iter().enumerate() {
+            assert!(a.x + a.width <= font.atlas.width && a.y + a.height <= font.atlas.height);
+            for b in &glyphs[i + 1..] {
+                // '@' was asked for twice and comes from the cache the second time
+                if (a.x, a.y) == (b.x, b.y) {
+                    continue;
+                }
+                let apart = a.x + a.width + GLYPH_PADDING <= b.x
+                    || b.x + b.width + GLYPH_PADDING <= a.x
+                    || a.y + a.height + GLYPH_PADDING <= b.y
+                    || b.y + b.height + GLYPH_PADDING <= a.y;
+                assert!(apart, "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_glyphs_wider_than_the_atlas() {
+        let mut font = dejavu();
+        assert_eq!(font.allocate(ATLAS_WIDTH, 10), None);
+        assert_eq!(font.allocate(10, ATLAS_MAX_HEIGHT), None);
+        assert_eq!(font.allocate(10, 10), Some((0, 0)));
+    }
+}
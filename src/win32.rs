@@ -10,7 +10,6 @@ use bindings::{Windows::Win32::Graphics::Gdi::{
         IDirectSound, IDirectSoundBuffer, DSBCAPS_PRIMARYBUFFER, DSBCAPS_GLOBALFOCUS, DSBLOCK_ENTIREBUFFER, DSBLOCK_FROMWRITECURSOR,
         DSBUFFERDESC, DirectSoundCreate, DSBPLAY_LOOPING, DSBSTATUS_LOOPING, DSBCAPS_GETCURRENTPOSITION2
     },
-    Windows::Win32::UI::KeyboardAndMouseInput::GetKeyState,
     Windows::Win32::Media::Multimedia::{ WAVEFORMATEX, WAVE_FORMAT_PCM }, Windows::Win32::System::Diagnostics::Debug::GetLastError,
     Windows::Win32::{Media::Audio::DirectMusic::DSSCL_PRIORITY, System::SystemServices::{
         GetModuleHandleW, LoadLibraryW, GetProcAddress, LRESULT, PWSTR, HANDLE
@@ -19,13 +18,18 @@ use bindings::{Windows::Win32::Graphics::Gdi::{
         RegisterClassExW, TranslateMessage, SetWindowLongPtrW, GetWindowLongPtrW, PeekMessageW, CW_USEDEFAULT, HWND,
         LPARAM, MSG, WINDOW_EX_STYLE, WM_ACTIVATEAPP, WM_CLOSE, WM_PAINT, WM_SIZE,
         WNDCLASSEXW, WNDCLASS_STYLES, WPARAM, WS_OVERLAPPEDWINDOW, WS_VISIBLE, GWLP_USERDATA, WM_CREATE,
-        CREATESTRUCTW, WM_DESTROY, PM_REMOVE, CS_HREDRAW, CS_VREDRAW, WM_KEYDOWN, WM_KEYUP
-    }, Windows::Win32::UI::XInput::*};
+        CREATESTRUCTW, WM_DESTROY, PM_REMOVE, CS_HREDRAW, CS_VREDRAW, WM_KEYDOWN, WM_KEYUP, WM_SYSKEYDOWN, WM_SYSKEYUP,
+        WM_MOUSEMOVE, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MBUTTONDOWN, WM_MBUTTONUP, WM_RBUTTONDOWN, WM_RBUTTONUP,
+        WM_XBUTTONDOWN, WM_XBUTTONUP, WM_MOUSEWHEEL, WM_DEVICECHANGE
+    }, Windows::Win32::UI::KeyboardAndMouseInput::{SetCapture, ReleaseCapture}, Windows::Win32::UI::XInput::*};
 
 use windows::{HRESULT, Guid, IUnknown};
 
 use widestring::WideCString;
 
+use crate::rmh::audio::SoundParams;
+use crate::rmh::keyboard::{Key, ALL_KEYS};
+
 trait PWSTRCreator {
     fn from_str(text: &'static str) -> PWSTR;
 }
@@ -70,22 +74,11 @@ fn win32_u32_argb(
     (a << 24) + (r << 16) + (g << 8) + b
 }
 
-fn circular_distance(a: u32, b: u32, circle_size: u32) -> i32 {
-
-    let ending_block = circle_size / 100 * 75;
-    let starting_block = circle_size / 100 * 25;
-
-    if a >= ending_block && b <= starting_block {
-        return a as i32 - (b + circle_size) as i32;
-    }
-
-    if b >= ending_block && a <= starting_block {
-        return (a + circle_size) as i32 - b as i32
-    }
-
-    return a as i32 - b as i32;
+fn elapsed_ms(since: std::time::Instant) -> f32 {
+    since.elapsed().as_secs_f32() * 1000.0
 }
 
+
 type DirectSoundCreateFn = extern "C" fn(
     pcguiddevice: *const Guid, 
     ppds: *mut Option<IDirectSound>, 
@@ -93,29 +86,24 @@ type DirectSoundCreateFn = extern "C" fn(
 ) -> HRESULT;
 
 type XInputGetStateFn = extern "C" fn(u32, *mut XINPUT_STATE) -> u32;
+type XInputSetStateFn = extern "C" fn(u32, *mut XINPUT_VIBRATION) -> u32;
 struct XInput {
-   get_state: XInputGetStateFn
-}
-
-struct SoundParams {
-    bits_per_sample: u16,
-    n_channels: u16,
-    n_samples_per_sec: u16,
-    buf_size_seconds: u16
+   get_state: XInputGetStateFn,
+   set_state: Option<XInputSetStateFn>,
 }
 
-impl SoundParams {
-    fn buf_size_bytes(&self) -> u32 {
-        (self.n_channels as u32 * 
-        self.bits_per_sample as u32 *
-        self.n_samples_per_sec as u32 *
-        self.buf_size_seconds as u32) / 8
-    }
-
-    fn bytes_per_sample(&self) -> u32 {
-        (self.bits_per_sample * self.n_channels / 8) as u32
+impl crate::rmh::rumble::RumbleSink for XInput {
+    fn set_motors(&mut self, slot: usize, left: f32, right: f32) {
+        if let Some(set_state) = self.set_state {
+            let mut vibration = XINPUT_VIBRATION {
+                wLeftMotorSpeed: (left * 65535.0) as u16,
+                wRightMotorSpeed: (right * 65535.0) as u16,
+            };
+            set_state(slot as u32, &mut vibration);
+        }
     }
 }
+
 struct Win32Game {
     running: bool,
     bitmap_info: BITMAPINFO,
@@ -124,14 +112,22 @@ struct Win32Game {
     window_width: u32,
     window_height: u32,
     xinput: Option<XInput>,
-    pad1: crate::rmh::Pad,
-    pad1packet: u32,
+    input: crate::rmh::Input,
+    rumble: crate::rmh::rumble::Rumble,
+    pad_packets: [u32; crate::rmh::input::MAX_CONTROLLERS],
+    pad_poller: crate::rmh::input::PadPoller,
+    deadzones: crate::rmh::input::Deadzones,
     dsound_buffer: Option<IDirectSoundBuffer>,
     dsound: Option<IDirectSound>, //necessary to hold this ref, otherwise the buffer gets deallocated
     sound_params: SoundParams,
-    sound_sample_idx: u32,
+    sound_cursor: crate::rmh::audio::CursorModel,
     sound_playing: bool,
     state: crate::rmh::GameState,
+    debug_font: crate::rmh::text::BitmapFont,
+    frame_history: crate::rmh::perf::FrameHistory,
+    show_perf_overlay: bool,
+    audio_sync_history: crate::rmh::overlay::AudioSyncHistory,
+    show_audio_sync: bool,
 }
 
 fn win32_get_game(window: HWND) -> &'static mut Win32Game {
@@ -155,7 +151,8 @@ fn win32_load_xinput(game: &mut Win32Game) {
             unsafe {
                 if let Some(addr) = GetProcAddress(dll, "XInputGetState") {
                     game.xinput = Some(XInput {
-                        get_state: std::mem::transmute_copy(&addr)
+                        get_state: std::mem::transmute_copy(&addr),
+                        set_state: GetProcAddress(dll, "XInputSetState").map(|addr| std::mem::transmute_copy(&addr)),
                     });
                 }
             }
@@ -245,35 +242,186 @@ fn win32_init_dsound(game: &mut Win32Game) {
     }
 }
 
-fn win32_get_pad_input(game: &mut Win32Game) -> bool {
+fn win32_xinput_pad(pad: &mut crate::rmh::input::Pad, gamepad: &XINPUT_GAMEPAD, deadzones: &crate::rmh::input::Deadzones) {
+    use crate::rmh::input::{apply_stick_deadzone, apply_trigger_deadzone};
+
+    let down = |button: u16| (gamepad.wButtons & button) != 0;
+    pad.up.update(down(XINPUT_GAMEPAD_DPAD_UP as u16));
+    pad.down.update(down(XINPUT_GAMEPAD_DPAD_DOWN as u16));
+    pad.left.update(down(XINPUT_GAMEPAD_DPAD_LEFT as u16));
+    pad.right.update(down(XINPUT_GAMEPAD_DPAD_RIGHT as u16));
+    pad.a.update(down(XINPUT_GAMEPAD_A as u16));
+    pad.b.update(down(XINPUT_GAMEPAD_B as u16));
+    pad.x.update(down(XINPUT_GAMEPAD_X as u16));
+    pad.y.update(down(XINPUT_GAMEPAD_Y as u16));
+    pad.left_shoulder.update(down(XINPUT_GAMEPAD_LEFT_SHOULDER as u16));
+    pad.right_shoulder.update(down(XINPUT_GAMEPAD_RIGHT_SHOULDER as u16));
+    pad.left_thumb.update(down(XINPUT_GAMEPAD_LEFT_THUMB as u16));
+    pad.right_thumb.update(down(XINPUT_GAMEPAD_RIGHT_THUMB as u16));
+    pad.start.update(down(XINPUT_GAMEPAD_START as u16));
+    pad.back.update(down(XINPUT_GAMEPAD_BACK as u16));
+
+    // thumbsticks range from -32768 to 32767
+    let axis = |v: i16| if v < 0 { v as f32 / 32768.0 } else { v as f32 / 32767.0 };
+    pad.left_stick = apply_stick_deadzone(axis(gamepad.sThumbLX), axis(gamepad.sThumbLY), deadzones.left_stick);
+    pad.right_stick = apply_stick_deadzone(axis(gamepad.sThumbRX), axis(gamepad.sThumbRY), deadzones.right_stick);
+    pad.left_trigger = apply_trigger_deadzone(gamepad.bLeftTrigger as f32 / 255.0, deadzones.trigger);
+    pad.right_trigger = apply_trigger_deadzone(gamepad.bRightTrigger as f32 / 255.0, deadzones.trigger);
+}
+
+fn win32_get_pad_input(game: &mut Win32Game) {
     if let Some(xinput) = &mut game.xinput {
-        let mut state = XINPUT_STATE::default();
-        (xinput.get_state)(0, &mut state);
-
-        if state.dwPacketNumber != game.pad1packet {
-            game.pad1packet = state.dwPacketNumber;
-            game.pad1.up = (state.Gamepad.wButtons & XINPUT_GAMEPAD_DPAD_UP as u16) != 0;
-            game.pad1.down = (state.Gamepad.wButtons & XINPUT_GAMEPAD_DPAD_DOWN as u16) != 0;
-            game.pad1.left = (state.Gamepad.wButtons & XINPUT_GAMEPAD_DPAD_LEFT as u16) != 0;
-            game.pad1.right = (state.Gamepad.wButtons & XINPUT_GAMEPAD_DPAD_RIGHT as u16) != 0;
-            return true;
+        for slot in 0..crate::rmh::input::MAX_CONTROLLERS {
+            if !game.pad_poller.should_poll(slot) {
+                continue;
+            }
+
+            let mut state = XINPUT_STATE::default();
+            // ERROR_DEVICE_NOT_CONNECTED for an empty slot
+            let connected = (xinput.get_state)(slot as u32, &mut state) == 0;
+            game.pad_poller.report(&mut game.input, slot, connected);
+            if !connected {
+                game.pad_packets[slot] = 0;
+                continue;
+            }
+
+            if state.dwPacketNumber != game.pad_packets[slot] {
+                game.pad_packets[slot] = state.dwPacketNumber;
+                let pad = game.input.controller_mut(slot);
+                win32_xinput_pad(pad, &state.Gamepad, &game.deadzones);
+            }
         }
     }
 
-    false
+    for event in game.input.pad_events.iter() {
+        match *event {
+            crate::rmh::input::PadEvent::Connected(slot) => info!("controller {} connected", slot),
+            crate::rmh::input::PadEvent::Disconnected(slot) => {
+                info!("controller {} disconnected", slot);
+                game.rumble.stop(slot);
+            }
+        }
+    }
 }
 
 
-unsafe fn win32_get_key_state(k_code: i32) -> bool {
-    (GetKeyState(k_code) & (1<<15)) != 0
+// Maps a WM_KEYDOWN/WM_KEYUP virtual key to our key, using the scan code and
+// extended-key bit of lparam to tell the left and right modifiers apart.
+fn win32_map_key(vk_code: usize, lparam: isize) -> Option<Key> {
+    let scan_code = (lparam >> 16) & 0xFF;
+    let extended = (lparam & (1 << 24)) != 0;
+
+    let key = match vk_code {
+        0x41..=0x5A => ALL_KEYS[Key::A.index() + (vk_code - 0x41)],
+        0x30..=0x39 => ALL_KEYS[Key::Num0.index() + (vk_code - 0x30)],
+        0x70..=0x7B => ALL_KEYS[Key::F1.index() + (vk_code - 0x70)],
+        0x60..=0x69 => ALL_KEYS[Key::Numpad0.index() + (vk_code - 0x60)],
+        0x10 if scan_code == 0x36 => Key::RightShift,
+        0x10 => Key::LeftShift,
+        0x11 if extended => Key::RightCtrl,
+        0x11 => Key::LeftCtrl,
+        0x12 if extended => Key::RightAlt,
+        0x12 => Key::LeftAlt,
+        0x0D if extended => Key::NumpadEnter,
+        0x0D => Key::Enter,
+        0x1B => Key::Escape,
+        0x20 => Key::Space,
+        0x09 => Key::Tab,
+        0x08 => Key::Backspace,
+        0x2D => Key::Insert,
+        0x2E => Key::Delete,
+        0x24 => Key::Home,
+        0x23 => Key::End,
+        0x21 => Key::PageUp,
+        0x22 => Key::PageDown,
+        0x26 => Key::Up,
+        0x28 => Key::Down,
+        0x25 => Key::Left,
+        0x27 => Key::Right,
+        0x14 => Key::CapsLock,
+        0x13 => Key::Pause,
+        0x2C => Key::PrintScreen,
+        0xBD => Key::Minus,
+        0xBB => Key::Equals,
+        0xDB => Key::LeftBracket,
+        0xDD => Key::RightBracket,
+        0xDC => Key::Backslash,
+        0xBA => Key::Semicolon,
+        0xDE => Key::Apostrophe,
+        0xC0 => Key::Grave,
+        0xBC => Key::Comma,
+        0xBE => Key::Period,
+        0xBF => Key::Slash,
+        0x6B => Key::NumpadAdd,
+        0x6D => Key::NumpadSubtract,
+        0x6A => Key::NumpadMultiply,
+        0x6F => Key::NumpadDivide,
+        0x6E => Key::NumpadDecimal,
+        _ => return None,
+    };
+    Some(key)
 }
 
-fn win32_get_kbd_input(game: &mut Win32Game) {
+// Returns false for anything that isn't a mouse message.
+fn win32_process_mouse_message(game: &mut Win32Game, msg: &MSG) -> bool {
+    let mouse = &mut game.input.mouse;
+    let wparam = msg.wParam.0;
+    let lparam = msg.lParam.0;
+
+    let (button, is_down) = match msg.message {
+        WM_LBUTTONDOWN => (&mut mouse.left, true),
+        WM_LBUTTONUP => (&mut mouse.left, false),
+        WM_MBUTTONDOWN => (&mut mouse.middle, true),
+        WM_MBUTTONUP => (&mut mouse.middle, false),
+        WM_RBUTTONDOWN => (&mut mouse.right, true),
+        WM_RBUTTONUP => (&mut mouse.right, false),
+        // high word of wparam: 1 for XBUTTON1, 2 for XBUTTON2
+        WM_XBUTTONDOWN | WM_XBUTTONUP => {
+            let button = if (wparam >> 16) & 0xFFFF == 1 { &mut mouse.x1 } else { &mut mouse.x2 };
+            (button, msg.message == WM_XBUTTONDOWN)
+        }
+        WM_MOUSEWHEEL => {
+            // high word of wparam is the signed delta, 120 per notch
+            mouse.wheel += ((wparam >> 16) & 0xFFFF) as i16 as f32 / 120.0;
+            return true;
+        }
+        WM_MOUSEMOVE => {
+            win32_set_mouse_position(game, lparam);
+            return true;
+        }
+        _ => return false,
+    };
+
+    button.update(is_down);
+    win32_set_mouse_position(game, lparam);
 
-    game.pad1.up = unsafe {win32_get_key_state(0x57)};
-    game.pad1.down = unsafe {win32_get_key_state(0x53)};
-    game.pad1.left = unsafe {win32_get_key_state(0x41)};
-    game.pad1.right = unsafe {win32_get_key_state(0x44)};
+    // keep receiving moves and ups while dragging outside the window
+    let mouse = &mut game.input.mouse;
+    let any_down = mouse.buttons_mut().iter().any(|b| b.is_down());
+    unsafe {
+        if any_down {
+            SetCapture(game.window);
+        } else {
+            ReleaseCapture();
+        }
+    }
+    true
+}
+
+// lparam holds signed 16 bit client coordinates.
+fn win32_set_mouse_position(game: &mut Win32Game, lparam: isize) {
+    let x = (lparam & 0xFFFF) as i16 as i32;
+    let y = ((lparam >> 16) & 0xFFFF) as i16 as i32;
+    let (x, y) = crate::rmh::input::window_to_backbuffer(
+        x,
+        y,
+        game.window_width as i32,
+        game.window_height as i32,
+        game.bitmap_info.bmiHeader.biWidth,
+        game.bitmap_info.bmiHeader.biHeight.abs(),
+    );
+    game.input.mouse.x = x;
+    game.input.mouse.y = y;
 }
 
 fn win32_render(game: &Win32Game) {
@@ -282,13 +430,13 @@ fn win32_render(game: &Win32Game) {
         let r = StretchDIBits(
             hdc,
             0,
-            game.window_height as i32,
+            0,
             game.window_width as i32,
-            -(game.window_height as i32),
+            game.window_height as i32,
             0,
             0,
             game.bitmap_info.bmiHeader.biWidth,
-            game.bitmap_info.bmiHeader.biHeight,
+            game.bitmap_info.bmiHeader.biHeight.abs(),
             &(game.bitmap_mem[0]) as *const u32 as *const std::ffi::c_void,
             &game.bitmap_info,
             DIB_RGB_COLORS,
@@ -306,7 +454,9 @@ fn win32_resize_bitmap_buffer(game: &mut Win32Game) {
         bmiHeader: BITMAPINFOHEADER {
             biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
             biWidth: game.window_width as i32,
-            biHeight: game.window_height as i32,
+            // negative for a top-down DIB: row 0 of bitmap_mem is the top
+            // row on screen, like every other buffer in the game
+            biHeight: -(game.window_height as i32),
             biPlanes: 1,
             biBitCount: 32,
             biCompression: BI_RGB as u32,
@@ -319,7 +469,7 @@ fn win32_resize_bitmap_buffer(game: &mut Win32Game) {
         bmiColors: [RGBQUAD::default()],
     };
 
-    let bitmap_size_pixels = game.bitmap_info.bmiHeader.biWidth * game.bitmap_info.bmiHeader.biHeight;
+    let bitmap_size_pixels = game.bitmap_info.bmiHeader.biWidth * game.bitmap_info.bmiHeader.biHeight.abs();
 
     game.bitmap_mem = vec![0; bitmap_size_pixels as usize];
 }
@@ -353,6 +503,19 @@ extern "system" fn window_event_handler(
         WM_ACTIVATEAPP => {
             debug!("window activated");
 
+            if wparam.0 == 0 {
+                let game = win32_get_game(window);
+                game.input.release_all();
+                game.rumble.stop_all();
+            }
+
+            LRESULT::default()
+        }
+        WM_DEVICECHANGE => {
+            // something was plugged in or out, don't wait for the backoff
+            let game = win32_get_game(window);
+            game.pad_poller.poll_all_soon();
+
             LRESULT::default()
         }
         WM_SIZE => {
@@ -385,7 +548,7 @@ extern "system" fn window_event_handler(
             let mut paint = PAINTSTRUCT::default();
             unsafe {
                 let hdc = BeginPaint(window, &mut paint);
-                PatBlt(hdc, 0, 0, game.bitmap_info.bmiHeader.biWidth,game.bitmap_info.bmiHeader.biHeight, BLACKNESS);
+                PatBlt(hdc, 0, 0, game.bitmap_info.bmiHeader.biWidth,game.bitmap_info.bmiHeader.biHeight.abs(), BLACKNESS);
                 win32_render(game);
                 EndPaint(window, &mut paint);
             }
@@ -431,13 +594,11 @@ fn main() -> windows::Result<()> {
             window_width: 720,
             window_height: 480,
             xinput: None,
-            pad1: crate::rmh::Pad{
-                up: false,
-                down: false,
-                left: false,
-                right: false,
-            },
-            pad1packet: 0,
+            input: crate::rmh::Input::default(),
+            rumble: crate::rmh::rumble::Rumble::new(),
+            pad_packets: [0; crate::rmh::input::MAX_CONTROLLERS],
+            pad_poller: crate::rmh::input::PadPoller::new(),
+            deadzones: crate::rmh::input::Deadzones::default(),
             dsound: None,
             dsound_buffer: None,
             sound_params: SoundParams {
@@ -446,13 +607,14 @@ fn main() -> windows::Result<()> {
                 n_samples_per_sec: 48000,
                 buf_size_seconds: 2,
             },
-            sound_sample_idx: 0,
+            sound_cursor: crate::rmh::audio::CursorModel::default(),
             sound_playing: false,
-            state: crate::rmh::GameState {
-                x_offset: 0,
-                y_offset: 0,
-                sine_wave_half_len: 30,
-            }
+            state: crate::rmh::GameState::new(48000),
+            debug_font: crate::rmh::text::BitmapFont::builtin(),
+            frame_history: crate::rmh::perf::FrameHistory::new(),
+            show_perf_overlay: false,
+            audio_sync_history: crate::rmh::overlay::AudioSyncHistory::new(),
+            show_audio_sync: false,
         };
 
         let hwnd = CreateWindowExW(
@@ -484,111 +646,221 @@ fn main() -> windows::Result<()> {
 
         win32_init_dsound(&mut game);
 
+        rmh::start_music(&mut game.state);
+
         let mut frame_timer = std::time::Instant::now();
         let mut frame_timer_diff = 0u128;
 
         let mut msg = MSG::default();
 
+        game.input.pads[rmh::input::KEYBOARD_PAD].is_connected = true;
+
+        let game_start = std::time::Instant::now();
+
         while game.running {
+            game.input.begin_frame();
+            game.input.time = game_start.elapsed().as_secs_f64();
+            game.input.dt = frame_timer_diff as f32 / 1000.0;
+
             while PeekMessageW(&mut msg, hwnd, 0, 0, PM_REMOVE).as_bool() {
+                let is_key_message = msg.message == WM_KEYDOWN
+                    || msg.message == WM_KEYUP
+                    || msg.message == WM_SYSKEYDOWN
+                    || msg.message == WM_SYSKEYUP;
+                if is_key_message {
+                    // bit 30: key was down before this message, bit 31: key is being released
+                    let was_down = (msg.lParam.0 & (1 << 30)) != 0;
+                    let is_down = (msg.lParam.0 & (1 << 31)) == 0;
+                    if let Some(key) = win32_map_key(msg.wParam.0, msg.lParam.0) {
+                        game.input.key_event(key, is_down, was_down && is_down);
+                    }
+                }
+                win32_process_mouse_message(&mut game, &msg);
                 TranslateMessage(&msg);
                 DispatchMessageW(&msg);
             }
 
-            if !win32_get_pad_input(&mut game) {
-                win32_get_kbd_input(&mut game);
+            game.state.actions.update_keyboard_pad(&mut game.input);
+
+            if game.input.keys.was_pressed(Key::F3) {
+                game.show_perf_overlay = !game.show_perf_overlay;
+            }
+            if game.input.keys.was_pressed(Key::F5) {
+                game.show_audio_sync = !game.show_audio_sync;
+            }
+            if game.input.keys.was_pressed(Key::F4) {
+                match rmh::profile::export_chrome_trace(std::path::Path::new("rmh_trace.json")) {
+                    Ok(()) => info!("wrote rmh_trace.json"),
+                    Err(e) => debug!("trace export failed: {}", e),
+                }
+            }
+
+            let mut timings = rmh::perf::FrameTimings::default();
+
+            {
+                timed_block!("input");
+                win32_get_pad_input(&mut game);
             }
 
-            rmh::update_state(&mut game.state, &game.pad1);
+            let section_timer = std::time::Instant::now();
+            rmh::update_state(&mut game.state, &game.input, &mut game.rumble);
+            timings.update_ms = elapsed_ms(section_timer);
+
+            let dt = frame_timer_diff as f32 / 1000.0;
+            match &mut game.xinput {
+                Some(xinput) => game.rumble.update(dt, xinput),
+                None => game.rumble.update(dt, &mut rmh::rumble::NullRumble),
+            }
 
+            let section_timer = std::time::Instant::now();
+            let bitmap_height = game.bitmap_info.bmiHeader.biHeight.abs();
             rmh::render_gfx(
                 &mut game.bitmap_mem,
                 game.bitmap_info.bmiHeader.biWidth,
-                game.bitmap_info.bmiHeader.biHeight,
+                bitmap_height,
                 game.state.x_offset,
                 game.state.y_offset,
                 &win32_u32_argb
             );
-            win32_render(&game);
-
-            frame_timer_diff = frame_timer.elapsed().as_millis();
-            debug!("loop time {}ms", frame_timer_diff);
-            frame_timer = std::time::Instant::now();
+            rmh::render_hud(
+                &mut game.state,
+                &game.input,
+                &mut game.bitmap_mem,
+                game.bitmap_info.bmiHeader.biWidth,
+                bitmap_height,
+            );
+            if game.show_perf_overlay {
+                rmh::overlay::draw_perf_overlay(
+                    &mut game.bitmap_mem,
+                    game.bitmap_info.bmiHeader.biWidth,
+                    bitmap_height,
+                    &mut game.debug_font,
+                    &game.frame_history,
+                    1000.0 / display_refresh_rate.max(1) as f32,
+                );
+            }
+            if game.show_audio_sync {
+                let width = game.bitmap_info.bmiHeader.biWidth;
+                rmh::overlay::draw_audio_sync(
+                    &mut game.bitmap_mem,
+                    width,
+                    bitmap_height,
+                    &mut game.debug_font,
+                    &game.audio_sync_history,
+                    game.sound_params.buf_size_bytes(),
+                    8,
+                    bitmap_height - 160,
+                    width - 16,
+                );
+            }
+            timings.render_ms = elapsed_ms(section_timer);
 
+            // written before the flip, while we still know how far into the
+            // frame we are
+            let section_timer = std::time::Instant::now();
             if let Some(buf) = &game.dsound_buffer {
-                
+                timed_block!("audio");
+
                 let mut play_cur = 0u32;
                 let mut write_cur = 0u32;
                 buf.GetCurrentPosition(&mut play_cur, &mut write_cur);
 
-                let mut byte_to_lock = game.sound_sample_idx * game.sound_params.bytes_per_sample();
-                let mut bytes_to_write = game.sound_params.buf_size_bytes()
-                                        / (game.sound_params.buf_size_seconds as u32 * 1000)
-                                        * frame_timer_diff as u32 ; 
-
-                let tracker_dist = circular_distance(byte_to_lock, write_cur, game.sound_params.buf_size_bytes());
+                let snapshot = game.sound_cursor.plan(
+                    &game.sound_params,
+                    play_cur,
+                    write_cur,
+                    1.0 / display_refresh_rate.max(1) as f32,
+                    frame_timer.elapsed().as_secs_f32(),
+                );
+                game.audio_sync_history.push(snapshot);
+                game.input.audio_latency = game.sound_cursor.latency_seconds(&game.sound_params);
+                let byte_to_lock = snapshot.byte_to_lock;
+                let bytes_to_write = snapshot.bytes_to_write;
 
                 debug!(
                     "diff between write_cur and own byte tracker {} {} {}",
                     write_cur,
                     byte_to_lock,
-                    tracker_dist
+                    snapshot.tracker_dist
+                );
+                debug!(
+                    "final bytes_to_write {} (latency {:.1} ms{})",
+                    bytes_to_write,
+                    game.input.audio_latency * 1000.0,
+                    if snapshot.high_latency { ", can't sync to flip" } else { "" }
                 );
 
-                let bytes_to_consider_underflow = (game.sound_params.buf_size_bytes() / 100 * 1);
-                if tracker_dist < bytes_to_consider_underflow as i32 {
-                    bytes_to_write += bytes_to_consider_underflow;
-                }
-
-                // preventing overflow if the game loop hangs for whatever reason,
-                // e.g. if some Windows event makes PeekMessage wait for too long.
-                if bytes_to_write > game.sound_params.buf_size_bytes() {
-                    bytes_to_write = game.sound_params.buf_size_bytes();
-                }
-
-                debug!("final bytes_to_write {}", bytes_to_write);
+                // nothing to write when the cursor is already far enough
+                // ahead; locking zero bytes fails
+                if bytes_to_write > 0 {
+                    let mut audio_samples = vec![0i16; (bytes_to_write/2) as usize];
+                    rmh::render_audio(
+                        &mut audio_samples,
+                        game.state.sine_wave_half_len,
+                        &mut sine_wave_sample_counter,
+                        &mut game.state.mixer,
+                    );
 
-                let mut audio_samples = vec![0i16; (bytes_to_write/2) as usize];
-                rmh::render_audio(&mut audio_samples, game.state.sine_wave_half_len, &mut sine_wave_sample_counter);
- 
-                let mut part1ptr: *mut std::ffi::c_void = std::ptr::null_mut();
-                let mut part2ptr: *mut std::ffi::c_void = std::ptr::null_mut();
-                let mut part1size = 0u32;
-                let mut part2size = 0u32;
+                    timed_block!("dsound lock/copy");
+                    let mut part1ptr: *mut std::ffi::c_void = std::ptr::null_mut();
+                    let mut part2ptr: *mut std::ffi::c_void = std::ptr::null_mut();
+                    let mut part1size = 0u32;
+                    let mut part2size = 0u32;
+
+                    let result = buf.Lock(
+                        byte_to_lock,
+                        bytes_to_write,
+                        &mut part1ptr,
+                        &mut part1size,
+                        &mut part2ptr,
+                        &mut part2size,
+                        0
+                    );
+                    debug_assert!(result.is_ok());
 
-                let result = buf.Lock(
-                    byte_to_lock,
-                    bytes_to_write,
-                    &mut part1ptr,
-                    &mut part1size,
-                    &mut part2ptr,
-                    &mut part2size,
-                    0
-                );
-                debug_assert!(result.is_ok());
+                    game.sound_cursor.advance(&game.sound_params, bytes_to_write);
 
-                game.sound_sample_idx += (bytes_to_write / game.sound_params.bytes_per_sample());
-                game.sound_sample_idx %= (game.sound_params.buf_size_bytes() / game.sound_params.bytes_per_sample());
+                    let mut sample_transfer_total = 0;
+                    for i in (0..part1size / game.sound_params.n_channels as u32) {
+                        *((part1ptr as *mut i16).add(i as usize)) = audio_samples[sample_transfer_total];
+                        sample_transfer_total += 1
+                    }
+                    for i in (0..part2size / game.sound_params.n_channels as u32) {
+                        *((part2ptr as *mut i16).add(i as usize)) = audio_samples[sample_transfer_total];
+                        sample_transfer_total += 1
+                    }
 
-                let mut sample_transfer_total = 0;
-                for i in (0..part1size / game.sound_params.n_channels as u32) {
-                    *((part1ptr as *mut i16).add(i as usize)) = audio_samples[sample_transfer_total];
-                    sample_transfer_total += 1
-                }
-                for i in (0..part2size / game.sound_params.n_channels as u32) {
-                    *((part2ptr as *mut i16).add(i as usize)) = audio_samples[sample_transfer_total];
-                    sample_transfer_total += 1
+                    let result = buf.Unlock(part1ptr, part1size, part2ptr, part2size);
+                    debug_assert!(result.is_ok());
                 }
 
-                let result = buf.Unlock(part1ptr, part1size, part2ptr, part2size);
-                debug_assert!(result.is_ok());
-
                 if !game.sound_playing {
                     let result = buf.Play(0, 0, DSBPLAY_LOOPING);
                     debug_assert!(result.is_ok());
                     game.sound_playing = true;
                 }
             }
+            timings.audio_ms = elapsed_ms(section_timer);
+
+            let section_timer = std::time::Instant::now();
+            {
+                timed_block!("present");
+                win32_render(&game);
+            }
+            timings.present_ms = elapsed_ms(section_timer);
+
+            // the whole frame, audio included
+            timings.total_ms = elapsed_ms(frame_timer);
+            frame_timer_diff = frame_timer.elapsed().as_millis();
+            frame_timer = std::time::Instant::now();
+
+            game.frame_history.push(timings);
+            rmh::profile::frame_end();
+        }
+
+        // volumes may have been changed in game
+        if let Err(e) = game.state.mixer.settings.save(std::path::Path::new(rmh::AUDIO_SETTINGS_PATH)) {
+            debug!("could not save audio settings: {}", e);
         }
     }
 